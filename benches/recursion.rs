@@ -0,0 +1,76 @@
+use rusp::interpret::Interpreter;
+use std::time::{Duration, Instant};
+
+// Recursive workloads run with the std library loaded. Each is run a few times
+// and the fastest run is reported, as the slower runs are mostly noise.
+//
+//     cargo bench --bench recursion
+
+const RUNS: usize = 5;
+
+const BENCHES: &[(&str, &str, &str, &str)] = &[
+    (
+        "fib",
+        "(defn fib [n] (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))",
+        "(fib 22)",
+        "17711",
+    ),
+    (
+        "tak",
+        "(defn tak [x y z]
+           (if (not (< y x))
+             z
+             (tak (tak (- x 1) y z) (tak (- y 1) z x) (tak (- z 1) x y))))",
+        "(tak 18 12 6)",
+        "7",
+    ),
+    (
+        "count-let",
+        "(defn count [n acc]
+           (let [(m (- n 1)) (a (+ acc 1))]
+             (if (= n 0) acc (count m a))))",
+        "(count 50000 0)",
+        "50000",
+    ),
+    (
+        "loop-cond",
+        "(defn sum [n]
+           (loop [(i 0) (acc 0)]
+             (cond ((= i n) acc)
+                   (else (recur (+ i 1) (+ acc i))))))",
+        "(sum 100000)",
+        "4999950000",
+    ),
+    // The body is compiled by (count 0 0), before incr is a macro
+    (
+        "late-macro",
+        "(defn count [n acc]
+           (if (= n 0) acc (count (- n 1) (incr acc))))
+         (count 0 0)
+         (defmacro incr [x] `(+ ,x 1))",
+        "(count 50000 0)",
+        "50000",
+    ),
+    (
+        "defaults",
+        "(defn count [n &opt (acc 0) (step 1)]
+           (if (= n 0) acc (count (- n 1) (+ acc step))))",
+        "(count 50000)",
+        "50000",
+    ),
+];
+
+fn main() {
+    for (name, setup, expr, expected) in BENCHES {
+        let mut best = Duration::MAX;
+        for _ in 0..RUNS {
+            let mut interpreter = Interpreter::new().init();
+            interpreter.eval_string(setup);
+            let start = Instant::now();
+            let result = interpreter.eval_string(expr);
+            best = best.min(start.elapsed());
+            assert_eq!(result, *expected, "{name} returned the wrong value");
+        }
+        println!("{name:<12} {:>10.2?}", best);
+    }
+}
@@ -0,0 +1,59 @@
+use rusp::interpret::Interpreter;
+use std::time::{Duration, Instant};
+
+// Workloads that mostly look up and bind local variables, through nested
+// scopes and closures, run with the std library loaded. The fastest of a few
+// runs is reported.
+//
+//     cargo bench --bench scopes
+
+const RUNS: usize = 5;
+
+const BENCHES: &[(&str, &str, &str, &str)] = &[
+    (
+        "nested-let",
+        "(defn f [a b]
+           (let [(c (+ a b))]
+             (let [(d (+ a c))]
+               (let [(e (+ b d))]
+                 (+ a b c d e)))))
+         (defn run [n acc]
+           (if (= n 0) acc (run (- n 1) (+ acc (f n 1)))))",
+        "(run 20000 0)",
+        "1200160000",
+    ),
+    (
+        "closures",
+        "(defn adder [n] (lambda [x] (+ x n)))
+         (defn run [n acc]
+           (if (= n 0) acc (run (- n 1) ((adder n) acc))))",
+        "(run 50000 0)",
+        "1250025000",
+    ),
+    (
+        "loop-locals",
+        "(defn sum [n]
+           (let [(step 1) (scale 2)]
+             (loop [(i 0) (acc 0)]
+               (if (= i n)
+                 acc
+                 (recur (+ i step) (+ acc (* i scale)))))))",
+        "(sum 100000)",
+        "9999900000",
+    ),
+];
+
+fn main() {
+    for (name, setup, expr, expected) in BENCHES {
+        let mut best = Duration::MAX;
+        for _ in 0..RUNS {
+            let mut interpreter = Interpreter::new().init();
+            interpreter.eval_string(setup);
+            let start = Instant::now();
+            let result = interpreter.eval_string(expr);
+            best = best.min(start.elapsed());
+            assert_eq!(result, *expected, "{name} returned the wrong value");
+        }
+        println!("{name:<12} {:>10.2?}", best);
+    }
+}
@@ -1,13 +1,18 @@
 mod character;
+mod code;
 mod env;
 mod error;
+mod frame;
 mod list;
 mod macros;
 mod map;
 mod number;
 mod pattern;
 mod procedure;
+mod scope;
+mod span;
 mod string;
+mod symbol;
 mod template;
 mod value;
 mod vector;
@@ -26,6 +31,27 @@ pub trait ValueIterator {
     fn values(&self) -> Box<dyn Iterator<Item = Val>>;
 }
 
+// Syntax /////////////////////////////////////////////////////////////////////
+
+// The forms the vm evaluates itself rather than by calling a procedure. These
+// can only be used at the head of a list and cannot be rebound.
+pub const SPECIAL_FORMS: &[&str] = &[
+    "if",
+    "do",
+    "lambda",
+    "loop",
+    "recur",
+    "macro-expand",
+    "try",
+    "quote",
+    "quasiquote",
+    "unquote",
+    "unquote-splicing",
+];
+
+// Forms that are only evaluated at the top level.
+pub const TOP_LEVEL_FORMS: &[&str] = &["def", "defn", "macro-rules", "defmacro"];
+
 // Data Exports ///////////////////////////////////////////////////////////////
 
 // By making them public we can import all data types using
@@ -33,18 +59,25 @@ pub trait ValueIterator {
 // this way as all types are used through the lib rather I guess.
 
 pub use character::Char;
+pub use code::{Code, Handler, MacroCall, MacroUse, Op, Shape};
 pub use env::Env;
-pub use error::Error;
+pub use error::{Error, ScanError};
+pub use frame::Frame;
 pub use list::List;
 pub use macros::Macro;
 pub use map::Map;
 pub use number::Num;
-pub use pattern::Pattern;
-pub use procedure::{Closure, Formals, Lambda, Procedure, TailCall};
+pub use pattern::{Capture, Captures, Pattern};
+pub use procedure::{
+    Arity, Clause, Closure, Formals, Key, Lambda, Opt, Param, Procedure, TailCall,
+};
+pub use scope::{Scope, Slots};
+pub use span::{ElementSpans, Span};
 pub use string::Str;
-pub use template::Template;
+pub use symbol::Sym;
+pub use template::{Renames, Template};
 pub use value::Val;
 pub use vector::Vector;
 
 use std::rc::Rc;
-pub type Environ = Rc<Env<Rc<Str>, Val>>;
+pub type Environ = Rc<Env<Sym, Val>>;
@@ -3,150 +3,114 @@ use std::fmt;
 
 // Scheme characters ///////////////////////////////////////////////////////////
 
-const NULL: u8 = '\0' as u8;
-const LF: u8 = '\n' as u8;
-const TAB: u8 = '\t' as u8;
-const SPACE: u8 = ' ' as u8;
-const FIRST_ASCII: u8 = 33;
-const LAST_ASCII: u8 = 126;
-const UNSUP: u8 = 127;
-
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
-pub enum Char {
-    Char(u8),
-    Null,
-    Tab,
-    LineFeed,
-    Space,
-    Unsupported,
-}
+// Any unicode scalar value. Chars that cannot be written after a \ in source,
+// like whitespace and control chars, are written with a name or as \u{hex}.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Char(char);
+
+// The names of chars that can be written as \name
+const NAMES: &[(&str, char)] = &[
+    ("null", '\0'),
+    ("tab", '\t'),
+    ("newline", '\n'),
+    ("space", ' '),
+    ("slash", '\\'),
+];
 
 impl Char {
+    pub fn from_name(name: &str) -> Option<Char> {
+        NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, ch)| Char(*ch))
+    }
+
     // Conversion //
 
-    pub fn to_byte(&self) -> u8 {
-        match self {
-            Char::Null => NULL,
-            Char::Tab => TAB,
-            Char::LineFeed => LF,
-            Char::Space => SPACE,
-            Char::Char(byte) => *byte,
-            Char::Unsupported => UNSUP,
-        }
+    pub fn to_char(&self) -> char {
+        self.0
     }
 
     pub fn to_int(&self) -> i64 {
-        self.to_byte() as i64
+        self.0 as i64
     }
 
+    // Chars whose case maps to more than one char, like ß, are left as they are
     pub fn to_upper_case(&self) -> Char {
-        Char::from((self.to_byte() as char).to_ascii_uppercase())
+        single(self.0.to_uppercase()).unwrap_or(*self)
     }
 
     pub fn to_lower_case(&self) -> Char {
-        Char::from((self.to_byte() as char).to_ascii_lowercase())
+        single(self.0.to_lowercase()).unwrap_or(*self)
     }
 
     // Predicates //
 
     pub fn is_alpha(&self) -> bool {
-        (self.to_byte() as char).is_ascii_alphabetic()
+        self.0.is_alphabetic()
     }
 
     pub fn is_alphanumeric(&self) -> bool {
-        (self.to_byte() as char).is_ascii_alphanumeric()
+        self.0.is_alphanumeric()
     }
 
     pub fn is_numeric(&self) -> bool {
-        (self.to_byte() as char).is_ascii_digit()
+        self.0.is_numeric()
     }
 
     pub fn is_whitespace(&self) -> bool {
-        match self {
-            Char::Null => false,
-            Char::Tab => true,
-            Char::LineFeed => true,
-            Char::Space => true,
-            Char::Char(byte) => (*byte as char).is_ascii_whitespace(),
-            Char::Unsupported => false,
-        }
-    }
-
-    pub fn is_unsup(&self) -> bool {
-        match self {
-            Char::Unsupported => true,
-            _ => false,
-        }
+        self.0.is_whitespace()
     }
 
     pub fn is_upper_case(&self) -> bool {
-        self.to_byte() >= 'A' as u8 && self.to_byte() <= 'Z' as u8
+        self.0.is_uppercase()
     }
 
     pub fn is_lower_case(&self) -> bool {
-        self.to_byte() >= 'a' as u8 && self.to_byte() <= 'z' as u8
+        self.0.is_lowercase()
     }
-}
-
-// Traits /////////////////////////////////////////////////////////////////////
 
-impl Default for Char {
-    fn default() -> Char {
-        Char::Char('\0' as u8)
+    // Whether the char has to be written with a name or its code in source
+    fn is_unprintable(&self) -> bool {
+        self.0.is_whitespace() || self.0.is_control()
     }
 }
 
-impl From<u8> for Char {
-    fn from(byte: u8) -> Char {
-        match byte {
-            NULL => Char::Null,
-            TAB => Char::Tab,
-            LF => Char::LineFeed,
-            SPACE => Char::Space,
-            FIRST_ASCII..=LAST_ASCII => Char::Char(byte),
-            _ => Char::Unsupported,
-        }
+fn single(mut chars: impl Iterator<Item = char>) -> Option<Char> {
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) => Some(Char(ch)),
+        _ => None,
     }
 }
 
+// Traits /////////////////////////////////////////////////////////////////////
+
 impl From<char> for Char {
     fn from(ch: char) -> Char {
-        Char::from(ch as u8)
+        Char(ch)
     }
 }
 
+// Ints that are not unicode scalar values are the replacement char
 impl From<i64> for Char {
     fn from(int: i64) -> Char {
-        if int < 0 || int > LAST_ASCII as i64 {
-            Char::Unsupported
-        } else {
-            Char::from(int as u8)
-        }
+        let ch = u32::try_from(int).ok().and_then(char::from_u32);
+        Char(ch.unwrap_or(char::REPLACEMENT_CHARACTER))
     }
 }
 
 impl DisplayRep for Char {
     fn to_display(&self) -> String {
-        match self {
-            Char::Null => "\0".to_string(),
-            Char::Tab => "\t".to_string(),
-            Char::LineFeed => "\n".to_string(),
-            Char::Space => " ".to_string(),
-            Char::Char(byte) => format!("{}", *byte as char),
-            Char::Unsupported => "**UNSUP**".to_string(),
-        }
+        self.0.to_string()
     }
 }
 
 impl ExternalRep for Char {
     fn to_external(&self) -> String {
-        match self {
-            Char::Null => "\\null".to_string(),
-            Char::Tab => "\\tab".to_string(),
-            Char::LineFeed => "\\newline".to_string(),
-            Char::Space => "\\space".to_string(),
-            Char::Char(byte) => format!("\\{}", *byte as char),
-            Char::Unsupported => "\\unsup".to_string(),
+        match NAMES.iter().find(|(_, ch)| *ch == self.0) {
+            Some((name, _)) if self.is_unprintable() => format!("\\{name}"),
+            _ if self.is_unprintable() => format!("\\u{{{:x}}}", self.0 as u32),
+            _ => format!("\\{}", self.0),
         }
     }
 }
@@ -170,318 +134,85 @@ mod test {
     use super::*;
 
     #[test]
-    fn test_char_from_u8_and_display() {
-        for i in FIRST_ASCII..=LAST_ASCII {
-            let ch = Char::from(i);
-            let other = i as char;
-            assert_eq!(ch.to_display(), String::from(other));
+    fn test_char_display() {
+        for ch in [
+            'a', 'Z', '0', '~', 'λ', 'é', '中', '😀', '\0', '\t', '\n', ' ',
+        ] {
+            assert_eq!(Char::from(ch).to_display(), ch.to_string());
         }
-
-        assert_eq!(Char::from(NULL).to_display(), "\0".to_owned());
-        assert_eq!(Char::from(TAB).to_display(), "\t".to_owned());
-        assert_eq!(Char::from(LF).to_display(), "\n".to_owned());
-        assert_eq!(Char::from(SPACE).to_display(), " ".to_owned());
-        assert_eq!(Char::from(UNSUP).to_display(), "**UNSUP**".to_owned());
     }
 
     #[test]
-    fn test_char_from_char_and_display() {
-        for i in FIRST_ASCII..=LAST_ASCII {
-            let other = i as char;
-            let ch = Char::from(other);
-            assert_eq!(ch.to_display(), String::from(other));
-        }
+    fn test_char_external() {
+        assert_eq!(Char::from('a').to_external(), "\\a");
+        assert_eq!(Char::from('\\').to_external(), "\\\\");
+        assert_eq!(Char::from('λ').to_external(), "\\λ");
+        assert_eq!(Char::from('😀').to_external(), "\\😀");
 
-        assert_eq!(Char::from('\0').to_display(), "\0".to_owned());
-        assert_eq!(Char::from('\t').to_display(), "\t".to_owned());
-        assert_eq!(Char::from('\n').to_display(), "\n".to_owned());
-        assert_eq!(Char::from(' ').to_display(), " ".to_owned());
-        assert_eq!(Char::from('\x7f').to_display(), "**UNSUP**".to_owned());
+        assert_eq!(Char::from('\0').to_external(), "\\null");
+        assert_eq!(Char::from('\t').to_external(), "\\tab");
+        assert_eq!(Char::from('\n').to_external(), "\\newline");
+        assert_eq!(Char::from(' ').to_external(), "\\space");
+        assert_eq!(Char::from('\x02').to_external(), "\\u{2}");
+        assert_eq!(Char::from('\u{2000}').to_external(), "\\u{2000}");
     }
 
     #[test]
-    fn test_char_from_u8_and_external() {
-        for i in FIRST_ASCII..=LAST_ASCII {
-            let ch = Char::from(i);
-            let other = i as char;
-            assert_eq!(ch.to_external(), format!("\\{other}"));
-        }
-
-        assert_eq!(Char::from(NULL).to_external(), "\\null".to_owned());
-        assert_eq!(Char::from(TAB).to_external(), "\\tab".to_owned());
-        assert_eq!(Char::from(LF).to_external(), "\\newline".to_owned());
-        assert_eq!(Char::from(SPACE).to_external(), "\\space".to_owned());
-        assert_eq!(Char::from(UNSUP).to_external(), "\\unsup".to_owned());
-    }
-
-    #[test]
-    fn test_char_is_whitespace() {
-        for i in FIRST_ASCII..=LAST_ASCII {
-            let ch = Char::from(i);
-            assert!(!ch.is_whitespace());
-        }
-
-        assert!(Char::from(TAB).is_whitespace());
-        assert!(Char::from(LF).is_whitespace());
-        assert!(Char::from(SPACE).is_whitespace());
-
-        assert!(!Char::from(NULL).is_whitespace());
-        assert!(!Char::from(UNSUP).is_whitespace());
-    }
-
-    #[test]
-    fn test_char_is_alpha_and_numeric() {
-        for i in 'a' as u8..='z' as u8 {
-            let ch = Char::from(i);
-            assert!(ch.is_alpha());
-            assert!(ch.is_alphanumeric());
-            assert!(!ch.is_numeric());
-        }
-
-        for i in 'A' as u8..='Z' as u8 {
-            let ch = Char::from(i);
-            assert!(ch.is_alpha());
-            assert!(ch.is_alphanumeric());
-            assert!(!ch.is_numeric());
-        }
-
-        for i in '0' as u8..='9' as u8 {
-            let ch = Char::from(i);
-            assert!(!ch.is_alpha());
-            assert!(ch.is_alphanumeric());
-            assert!(ch.is_numeric());
-        }
-
-        for i in FIRST_ASCII..'0' as u8 {
-            let ch = Char::from(i);
-            assert!(!ch.is_alpha());
-            assert!(!ch.is_alphanumeric());
-            assert!(!ch.is_numeric());
-        }
-
-        for i in ':' as u8..'A' as u8 {
-            let ch = Char::from(i);
-            assert!(!ch.is_alpha());
-            assert!(!ch.is_alphanumeric());
-            assert!(!ch.is_numeric());
-        }
-
-        for i in '[' as u8..'a' as u8 {
-            let ch = Char::from(i);
-            assert!(!ch.is_alpha());
-            assert!(!ch.is_alphanumeric());
-            assert!(!ch.is_numeric());
-        }
-
-        for i in '{' as u8..=LAST_ASCII {
-            let ch = Char::from(i);
-            assert!(!ch.is_alpha());
-            assert!(!ch.is_alphanumeric());
-            assert!(!ch.is_numeric());
-        }
-
-        assert!(!Char::from(NULL).is_alpha());
-        assert!(!Char::from(TAB).is_alpha());
-        assert!(!Char::from(LF).is_alpha());
-        assert!(!Char::from(SPACE).is_alpha());
-        assert!(!Char::from(UNSUP).is_alpha());
-
-        assert!(!Char::from(NULL).is_alphanumeric());
-        assert!(!Char::from(TAB).is_alphanumeric());
-        assert!(!Char::from(LF).is_alphanumeric());
-        assert!(!Char::from(SPACE).is_alphanumeric());
-        assert!(!Char::from(UNSUP).is_alphanumeric());
-
-        assert!(!Char::from(NULL).is_numeric());
-        assert!(!Char::from(TAB).is_numeric());
-        assert!(!Char::from(LF).is_numeric());
-        assert!(!Char::from(SPACE).is_numeric());
-        assert!(!Char::from(UNSUP).is_numeric());
+    fn test_char_from_name() {
+        assert_eq!(Char::from_name("newline"), Some(Char::from('\n')));
+        assert_eq!(Char::from_name("slash"), Some(Char::from('\\')));
+        assert_eq!(Char::from_name("jersey"), None);
     }
 
     #[test]
-    fn test_char_is_unsup() {
-        for i in FIRST_ASCII..=LAST_ASCII {
-            let ch = Char::from(i);
-            assert!(!ch.is_unsup());
-        }
-
-        assert!(!Char::from(TAB).is_unsup());
-        assert!(!Char::from(LF).is_unsup());
-        assert!(!Char::from(SPACE).is_unsup());
-        assert!(!Char::from(NULL).is_unsup());
-
-        assert!(Char::from(UNSUP).is_unsup());
-
-        for i in NULL + 1..TAB {
-            let ch = Char::from(i);
-            assert!(ch.is_unsup());
-        }
-
-        for i in TAB + 1..LF {
-            let ch = Char::from(i);
-            assert!(ch.is_unsup());
-        }
-
-        for i in LF + 1..SPACE {
-            let ch = Char::from(i);
-            assert!(ch.is_unsup());
-        }
-
-        assert!(!Char::from(LAST_ASCII).is_unsup());
-        assert!(Char::from(127i64).is_unsup());
-        assert!(Char::from(-23i64).is_unsup());
-        assert!(Char::from(800i64).is_unsup());
+    fn test_char_from_int() {
+        assert_eq!(Char::from(97i64), Char::from('a'));
+        assert_eq!(Char::from(0x3bbi64), Char::from('λ'));
+        assert_eq!(Char::from(-23i64), Char::from('\u{fffd}'));
+        assert_eq!(Char::from(0xd800i64), Char::from('\u{fffd}'));
+        assert_eq!(Char::from(0x110000i64), Char::from('\u{fffd}'));
+        assert_eq!(Char::from('λ').to_int(), 0x3bb);
     }
 
     #[test]
-    fn test_char_is_upper_and_to_upper() {
-        for i in 'A' as u8..='Z' as u8 {
-            let ch = Char::from(i);
-            assert!(ch.is_upper_case());
-            assert!(ch.to_upper_case().is_upper_case());
-        }
-
-        for i in FIRST_ASCII..'A' as u8 {
-            let ch = Char::from(i);
-            assert!(!ch.is_upper_case());
-            assert!(!ch.to_upper_case().is_upper_case());
+    fn test_char_predicates() {
+        for ch in ['a', 'Z', 'λ', 'Ж', '中'] {
+            assert!(Char::from(ch).is_alpha());
+            assert!(Char::from(ch).is_alphanumeric());
+            assert!(!Char::from(ch).is_numeric());
         }
-
-        for i in 'Z' as u8 + 1..'a' as u8 {
-            let ch = Char::from(i);
-            assert!(!ch.is_upper_case());
-            assert!(!ch.to_upper_case().is_upper_case());
+        for ch in ['0', '9', '٣'] {
+            assert!(!Char::from(ch).is_alpha());
+            assert!(Char::from(ch).is_alphanumeric());
+            assert!(Char::from(ch).is_numeric());
         }
-
-        for i in 'a' as u8..='z' as u8 {
-            let ch = Char::from(i);
-            assert!(!ch.is_upper_case());
-            assert!(ch.to_upper_case().is_upper_case());
+        for ch in ['-', '~', '\0', '😀'] {
+            assert!(!Char::from(ch).is_alphanumeric());
         }
 
-        for i in 'z' as u8 + 1..=LAST_ASCII {
-            let ch = Char::from(i);
-            assert!(!ch.is_upper_case());
-            assert!(!ch.to_upper_case().is_upper_case());
+        for ch in [' ', '\t', '\n', '\u{2000}', '\u{3000}'] {
+            assert!(Char::from(ch).is_whitespace());
         }
-
-        assert!(!Char::from(TAB).is_upper_case());
-        assert!(!Char::from(LF).is_upper_case());
-        assert!(!Char::from(SPACE).is_upper_case());
-        assert!(!Char::from(NULL).is_upper_case());
-        assert!(!Char::from(UNSUP).is_upper_case());
-
-        assert!(!Char::from(TAB).to_upper_case().is_upper_case());
-        assert!(!Char::from(LF).to_upper_case().is_upper_case());
-        assert!(!Char::from(SPACE).to_upper_case().is_upper_case());
-        assert!(!Char::from(NULL).to_upper_case().is_upper_case());
-        assert!(!Char::from(UNSUP).to_upper_case().is_upper_case());
+        assert!(!Char::from('\0').is_whitespace());
+        assert!(!Char::from('a').is_whitespace());
     }
 
     #[test]
-    fn test_char_is_lower_and_to_lower() {
-        for i in 'a' as u8..='z' as u8 {
-            let ch = Char::from(i);
-            assert!(ch.is_lower_case());
-            assert!(ch.to_lower_case().is_lower_case());
-        }
+    fn test_char_case() {
+        assert!(Char::from('A').is_upper_case());
+        assert!(Char::from('Λ').is_upper_case());
+        assert!(Char::from('a').is_lower_case());
+        assert!(Char::from('λ').is_lower_case());
+        assert!(!Char::from('8').is_upper_case());
+        assert!(!Char::from('8').is_lower_case());
 
-        for i in 'A' as u8..='Z' as u8 {
-            let ch = Char::from(i);
-            assert!(!ch.is_lower_case());
-            assert!(ch.to_lower_case().is_lower_case());
-        }
-
-        for i in FIRST_ASCII..'A' as u8 {
-            let ch = Char::from(i);
-            assert!(!ch.is_lower_case());
-            assert!(!ch.to_lower_case().is_lower_case());
-        }
-
-        for i in 'Z' as u8 + 1..'a' as u8 {
-            let ch = Char::from(i);
-            assert!(!ch.is_lower_case());
-            assert!(!ch.to_lower_case().is_lower_case());
-        }
-
-        for i in 'z' as u8 + 1..=LAST_ASCII {
-            let ch = Char::from(i);
-            assert!(!ch.is_lower_case());
-            assert!(!ch.to_lower_case().is_lower_case());
-        }
-
-        assert!(!Char::from(TAB).is_lower_case());
-        assert!(!Char::from(LF).is_lower_case());
-        assert!(!Char::from(SPACE).is_lower_case());
-        assert!(!Char::from(NULL).is_lower_case());
-        assert!(!Char::from(UNSUP).is_lower_case());
-
-        assert!(!Char::from(TAB).to_lower_case().is_lower_case());
-        assert!(!Char::from(LF).to_lower_case().is_lower_case());
-        assert!(!Char::from(SPACE).to_lower_case().is_lower_case());
-        assert!(!Char::from(NULL).to_lower_case().is_lower_case());
-        assert!(!Char::from(UNSUP).to_lower_case().is_lower_case());
-    }
-
-    #[test]
-    fn test_char_additional_to_upper() {
         assert_eq!(Char::from('a').to_upper_case(), Char::from('A'));
-        assert_eq!(Char::from('h').to_upper_case(), Char::from('H'));
-        assert_eq!(Char::from('s').to_upper_case(), Char::from('S'));
-        assert_eq!(Char::from('z').to_upper_case(), Char::from('Z'));
-
-        assert_eq!(Char::from('Z').to_upper_case(), Char::from('Z'));
+        assert_eq!(Char::from('λ').to_upper_case(), Char::from('Λ'));
+        assert_eq!(Char::from('Ж').to_lower_case(), Char::from('ж'));
         assert_eq!(Char::from('.').to_upper_case(), Char::from('.'));
-        assert_eq!(Char::from(':').to_upper_case(), Char::from(':'));
-        assert_eq!(Char::from('-').to_upper_case(), Char::from('-'));
-        assert_eq!(Char::from('8').to_upper_case(), Char::from('8'));
-
-        assert_eq!(Char::from(TAB).to_upper_case(), Char::from(TAB));
-        assert_eq!(Char::from(LF).to_upper_case(), Char::from(LF));
-        assert_eq!(Char::from(SPACE).to_upper_case(), Char::from(SPACE));
-        assert_eq!(Char::from(NULL).to_upper_case(), Char::from(NULL));
-        assert_eq!(Char::from(UNSUP).to_upper_case(), Char::from(UNSUP));
-    }
-
-    #[test]
-    fn test_char_additional_to_lower() {
-        assert_eq!(Char::from('A').to_lower_case(), Char::from('a'));
-        assert_eq!(Char::from('H').to_lower_case(), Char::from('h'));
-        assert_eq!(Char::from('S').to_lower_case(), Char::from('s'));
-        assert_eq!(Char::from('Z').to_lower_case(), Char::from('z'));
-
-        assert_eq!(Char::from('a').to_lower_case(), Char::from('a'));
-        assert_eq!(Char::from('.').to_lower_case(), Char::from('.'));
-        assert_eq!(Char::from(':').to_lower_case(), Char::from(':'));
-        assert_eq!(Char::from('-').to_lower_case(), Char::from('-'));
-        assert_eq!(Char::from('8').to_lower_case(), Char::from('8'));
-
-        assert_eq!(Char::from(TAB).to_lower_case(), Char::from(TAB));
-        assert_eq!(Char::from(LF).to_lower_case(), Char::from(LF));
-        assert_eq!(Char::from(SPACE).to_lower_case(), Char::from(SPACE));
-        assert_eq!(Char::from(NULL).to_lower_case(), Char::from(NULL));
-        assert_eq!(Char::from(UNSUP).to_lower_case(), Char::from(UNSUP));
-    }
-
-    #[test]
-    fn test_char_to_byte_and_to_int() {
-        for i in FIRST_ASCII..=LAST_ASCII {
-            let ch = Char::from(i);
-            assert_eq!(ch.to_byte(), i);
-            assert_eq!(ch.to_int(), i as i64);
-        }
+        assert_eq!(Char::from('\n').to_lower_case(), Char::from('\n'));
 
-        assert_eq!(Char::from('\t').to_byte(), TAB);
-        assert_eq!(Char::from('\n').to_byte(), LF);
-        assert_eq!(Char::from(' ').to_byte(), SPACE);
-        assert_eq!(Char::from('\0').to_byte(), NULL);
-        assert_eq!(Char::from('\x7f').to_byte(), UNSUP);
-
-        assert_eq!(Char::from('\t').to_int(), TAB as i64);
-        assert_eq!(Char::from('\n').to_int(), LF as i64);
-        assert_eq!(Char::from(' ').to_int(), SPACE as i64);
-        assert_eq!(Char::from('\0').to_int(), NULL as i64);
-        assert_eq!(Char::from('\x7f').to_int(), UNSUP as i64);
+        // Maps to SS
+        assert_eq!(Char::from('ß').to_upper_case(), Char::from('ß'));
     }
 }
@@ -0,0 +1,227 @@
+use crate::data::{Clause, Error, List, Macro, Scope, Span, Sym, Val};
+use std::cell::{Cell, OnceCell, RefCell};
+use std::rc::Rc;
+
+// Code ///////////////////////////////////////////////////////////////////////
+
+// The compiled form of a top level form or of the body of a closure clause.
+// Each op is paired with the span of the innermost form read from source that
+// it was compiled from, so that errors it raises can be located.
+#[derive(Debug, Default)]
+pub struct Code {
+    pub ops: Vec<Op>,
+    pub spans: Vec<Option<Rc<Span>>>,
+    pub consts: Vec<Val>,
+}
+
+impl Code {
+    pub fn new() -> Code {
+        Code::default()
+    }
+
+    // Add an op and return its index
+    pub fn emit(&mut self, op: Op, span: Option<Rc<Span>>) -> usize {
+        self.ops.push(op);
+        self.spans.push(span);
+        self.ops.len() - 1
+    }
+
+    pub fn constant(&mut self, val: Val) -> usize {
+        self.consts.push(val);
+        self.consts.len() - 1
+    }
+
+    // Remove the ops from an index onwards, like when a form fails to compile
+    // and the ops for its subforms are replaced.
+    pub fn truncate(&mut self, len: usize) {
+        self.ops.truncate(len);
+        self.spans.truncate(len);
+    }
+
+    // The index the next op will have
+    pub fn next(&self) -> usize {
+        self.ops.len()
+    }
+}
+
+// Op /////////////////////////////////////////////////////////////////////////
+
+// The ops work on a stack of values. Local names are bound in the slots of the
+// scopes of the closure being applied, and other names are looked up in the
+// global environment.
+#[derive(Debug, Clone)]
+pub enum Op {
+    // Push a constant
+    Const(usize),
+    // Push the value in a slot of the scope at a depth from the current one.
+    // The name is for errors.
+    Local(Sym, usize, usize),
+    // Push the value bound to a name in the global environment
+    Global(Sym),
+    // Add slots to the current scope that are Undefined until the values of
+    // their definitions are evaluated
+    Declare(usize),
+    // Pop a value into a slot of the current scope
+    Define(usize),
+    // Pop a value and bind it to a name in the global environment
+    DefineGlobal(Sym),
+    // Give the closure on top of the stack the name of its definition
+    Name(Sym),
+    Pop,
+    Jump(usize),
+    // Pop a value and jump if it is false
+    JumpIfFalse(usize),
+    // Push a closure over the current scope with the clauses of a lambda
+    Lambda(Rc<[Clause]>),
+    // If the value on top of the stack is a macro, evaluate the expansion of
+    // the call instead of the call
+    MacroCheck(Rc<MacroCall>),
+    // Go on to the expansion of a macro use that follows, as long as the
+    // macro it was expanded by is still bound to the name it was used by
+    Expanded(Rc<MacroUse>),
+    // Expand the macro use in a (macro-expand use) form
+    MacroExpand(Val, Option<Rc<Scope>>),
+    // Pop a form and push it with all of the macro uses in it expanded
+    ExpandAll,
+    // Apply the procedure under the args on top of the stack to them. The
+    // list is the call form, which is used for the frame of the call.
+    Call(usize, Rc<List>),
+    // A call whose value is returned, which replaces the current frame
+    TailCall(usize, Rc<List>),
+    Return,
+    // Pop the given number of values into the slots of a new scope, or go
+    // back to the enclosing one
+    Scope(usize),
+    Unscope,
+    // Pop the given number of values into a new list, vector or map. Maps
+    // take a key and a value for each entry.
+    Build(Shape, usize),
+    // Build a list or vector, splicing in the values that are marked
+    Splice(Shape, Rc<[bool]>),
+    // Pop values for the vars of the enclosing loop
+    Recur(usize),
+    // Leave the scope of a loop body and go back to the scope op at the index
+    // if the body evaluated to a recur
+    EndLoop(usize),
+    // Errors raised until the matching EndTry are handled by the handler
+    Try(Rc<Handler>),
+    EndTry,
+    // Raise the error value on top of the stack again
+    Rethrow,
+    Raise(Error),
+}
+
+// A call whose head might evaluate to a macro, which is expanded in the scope
+// of the call before continuing after it at the index. The code for the last
+// expansion is kept with the macro it was expanded by, so the call is only
+// expanded again if its head evaluates to another macro.
+#[derive(Debug)]
+pub struct MacroCall {
+    pub form: Rc<List>,
+    pub scope: Option<Rc<Scope>>,
+    pub tail: bool,
+    pub after: usize,
+    expansion: RefCell<Option<(Rc<Macro>, Rc<Code>)>>,
+}
+
+impl MacroCall {
+    pub fn new(form: Rc<List>, scope: Option<Rc<Scope>>, tail: bool, after: usize) -> MacroCall {
+        MacroCall {
+            form,
+            scope,
+            tail,
+            after,
+            expansion: RefCell::new(None),
+        }
+    }
+
+    // The code for the expansion of the call by a macro, if it is the last
+    // macro the call was expanded by
+    pub fn expansion(&self, m: &Rc<Macro>) -> Option<Rc<Code>> {
+        match &*self.expansion.borrow() {
+            Some((last, code)) if Rc::ptr_eq(last, m) => Some(code.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn set_expansion(&self, m: Rc<Macro>, code: Rc<Code>) {
+        self.expansion.replace(Some((m, code)));
+    }
+}
+
+// A use of a macro that was expanded when it was compiled, with the code for
+// the expansion following it up to the index. The name it was used by only has
+// to be looked up again when the vm's macro version changes, which is when a
+// macro bound at the top level is rebound. If the name is no longer bound to
+// the macro the use is compiled again, and that code is evaluated instead from
+// then on.
+#[derive(Debug)]
+pub struct MacroUse {
+    pub call: MacroCall,
+    pub name: Sym,
+    pub mac: Rc<Macro>,
+    checked: Cell<usize>,
+    recompiled: OnceCell<Rc<Code>>,
+}
+
+impl MacroUse {
+    pub fn new(call: MacroCall, name: Sym, mac: Rc<Macro>, version: usize) -> MacroUse {
+        MacroUse {
+            call,
+            name,
+            mac,
+            checked: Cell::new(version),
+            recompiled: OnceCell::new(),
+        }
+    }
+
+    // Whether the binding was checked at the version
+    pub fn is_checked(&self, version: usize) -> bool {
+        self.checked.get() == version
+    }
+
+    pub fn check(&self, version: usize) {
+        self.checked.set(version);
+    }
+
+    // The code the use was compiled to again once its macro was rebound
+    pub fn recompiled(&self) -> Option<Rc<Code>> {
+        self.recompiled.get().cloned()
+    }
+
+    pub fn set_recompiled(&self, code: Rc<Code>) -> Rc<Code> {
+        self.recompiled.get_or_init(|| code).clone()
+    }
+}
+
+// The kinds of collections that can be built
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    List,
+    Vector,
+    Tuple,
+    Map,
+    Dict,
+}
+
+// Where the code for a try goes when an error is raised. The first catch
+// with no kind or the kind of the error is jumped to with the error on top of
+// the stack. Without one the error goes to the fallback, which evaluates the
+// finally forms before raising it again.
+#[derive(Debug, Clone, Default)]
+pub struct Handler {
+    pub catches: Vec<(Option<Val>, usize)>,
+    pub fallback: Option<usize>,
+}
+
+impl Handler {
+    pub fn target(&self, err: &Error) -> Option<usize> {
+        self.catches
+            .iter()
+            .find(|(kind, _)| match kind {
+                Some(kind) => *kind == err.kind(),
+                None => true,
+            })
+            .map(|(_, pc)| *pc)
+    }
+}
@@ -1,6 +1,6 @@
-use crate::data::Val;
+use crate::data::{DisplayRep, ExternalRep, Frame, Span, Val};
+use std::rc::Rc;
 
-// TODO The error type needs to be remade because it accidentally got deleted.
 // TODO I think if these are runtime errors then the scanner and reader errors
 // need to be combined with the base error type. Either that or we need to think
 // about what parts of the program will return Err(Error) and which parts will just
@@ -17,127 +17,425 @@ use crate::data::Val;
 // from those functions as Val::Error(Error). So, we can decide to combine the
 // errors together or not, though if not we need an Error::ScanErr variant.
 
+// Error //////////////////////////////////////////////////////////////////////
+
+// Errors raised while evaluating. Errors raised while reading source text are
+// ScanErrors, and are wrapped by Read if they need to be returned with runtime
+// errors, like when evaluating a whole script.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     OutOfRange,
     OutOfRangeIdx(&'static str, usize, Val),
     Undeclared(String),
+    Unassigned(String),
     BadIndex(usize, Val),
     ArgsNotList,
     Arity(&'static str),
+    ClosureArity(String, String, usize),
+    BadKeywordArg(String, Val),
     BadArg(usize),
     BadType(Val),
     ArgType(&'static str, &'static str, Val),
     DivideByZero,
     MapArgsNotEven(Val),
-    // TODO replace with a scan error
     CantParseNum(String),
     NotAProcedure(Val),
     NotHashable(Val),
     Immutable,
-    Eof(usize),
-    BadChar(usize, char),
-    BadToken(usize, String),
-    BadIdentifier(usize, String),
-    BadEscape(usize, String),
-    BadNumber(usize, String),
-    MultiLineString(usize),
-    DerefNotIdent(String),
-    OddMapPairs(usize),
     BadRule(Val),
     BadPattern(Val),
     BadTemplate(Val),
+    BadParam(Val),
+    ShadowedClause(Val),
+    Destructure(Val, Val),
     TemplateElipseNotVar,
+    ElipseMismatch(String, String),
     NoMacroMatch(String),
     Throw(Val, Val, Vec<Val>),
+    Read(ScanError),
+    Located(Rc<Span>, Box<Error>),
+    Trace(Vec<Frame>, Box<Error>),
 }
 
-// Scan/Read Error ////////////////////////////////////////////////////////////
+impl Error {
+    // Attach the location of the form that raised the error. Errors are
+    // located as they are raised, so an error that already has a location
+    // keeps the innermost one.
+    pub fn at(self, span: Option<Rc<Span>>) -> Error {
+        match (self, span) {
+            (err @ Error::Located(..), _) => err,
+            (err, Some(span)) => Error::Located(span, Box::new(err)),
+            (err, None) => err,
+        }
+    }
 
-/*
-#[derive(Debug, Clone, PartialEq)]
-pub enum ScanError {
-    Eof(usize),
-    BadChar(usize, char),
-    BadToken(usize, String),
-    BadIdentifier(usize, String),
-    BadEscape(usize, String),
-    BadNumber(usize, String),
-    MultiLineString(usize),
-    OddMapPairs(usize),
-}
+    // Where the error was raised, if it was raised by a form read from source
+    pub fn span(&self) -> Option<Rc<Span>> {
+        match self {
+            Error::Located(span, _) => Some(span.clone()),
+            Error::Read(err) => err.span(),
+            Error::Trace(_, err) => err.span(),
+            _ => None,
+        }
+    }
 
-impl std::fmt::Display for ScanError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    // The message describing the error, without its kind or location
+    pub fn message(&self) -> String {
         match self {
-            ScanError::Eof(line) => {
-                write!(f, "ReadError: Line: {line}, unexpected EOF")
+            Error::OutOfRange => "index out of range".to_string(),
+            Error::OutOfRangeIdx(_, idx, val) => {
+                format!("{idx} is not a valid index for {}", val.to_external())
             }
-            ScanError::BadToken(line, tk) => {
-                write!(f, "ReadError: Line: {line}, unexpected token: {tk}")
+            Error::Undeclared(name) => format!("undeclared symbol: {name}"),
+            Error::Unassigned(name) => format!("{name} used before its definition"),
+            Error::BadIndex(idx, val) => {
+                format!("{idx} is not a valid index for {}", val.to_external())
             }
-            ScanError::BadChar(line, ch) => {
-                write!(f, "ReadError: Line: {line}, unexpected character: {ch}")
+            Error::ArgsNotList => "arguments must be a list".to_string(),
+            Error::Arity(_) => "incorrect argument count".to_string(),
+            Error::ClosureArity(_, expected, got) => format!("expected {expected}, got {got}"),
+            Error::BadKeywordArg(_, val) => {
+                format!("invalid keyword argument: {}", val.to_external())
             }
-            ScanError::BadIdentifier(line, id) => {
-                write!(f, "ReadError: Line: {line}, invalid identifier: {id}")
+            Error::BadArg(pos) => format!("invalid argument in position {pos}"),
+            Error::BadType(val) => format!("unexpected type for {}", val.to_external()),
+            Error::ArgType(_, kind, val) => format!("{} must be {kind}", val.to_external()),
+            Error::DivideByZero => "division by zero".to_string(),
+            Error::MapArgsNotEven(val) => format!(
+                "maps need an even number of keys and values: {}",
+                val.to_external()
+            ),
+            Error::CantParseNum(s) => format!("invalid number: {s}"),
+            Error::NotAProcedure(val) => format!("{} is not a procedure", val.to_external()),
+            Error::NotHashable(val) => format!("{} cannot be used as a key", val.to_external()),
+            Error::Immutable => "cannot modify an immutable collection".to_string(),
+            Error::BadRule(val) => format!("invalid macro rule: {}", val.to_external()),
+            Error::BadPattern(val) => format!("invalid macro pattern: {}", val.to_external()),
+            Error::BadTemplate(val) => format!("invalid macro template: {}", val.to_external()),
+            Error::BadParam(val) => format!("invalid parameter: {}", val.to_external()),
+            Error::ShadowedClause(val) => format!(
+                "earlier clauses accept every argument count of {}",
+                val.to_external()
+            ),
+            Error::Destructure(form, val) => format!(
+                "cannot destructure {} with {}",
+                val.to_external(),
+                form.to_external()
+            ),
+            Error::TemplateElipseNotVar => {
+                "... in a macro template must follow a form with a pattern variable matched by ..."
+                    .to_string()
             }
-            ScanError::BadEscape(line, s) => {
-                write!(f, "ReadError: Line: {line}, invalid escape character: {s}")
+            Error::ElipseMismatch(a, b) => {
+                format!("{a} and {b} matched different numbers of forms but share a ...")
             }
-            ScanError::BadNumber(line, n) => {
-                write!(f, "ReadError: Line: {line}, invalid number: {n}")
+            Error::NoMacroMatch(_) => "no macro rule matches".to_string(),
+            Error::Throw(_, msg, _) => msg.to_display(),
+            Error::Read(err) => err.message(),
+            Error::Located(_, err) | Error::Trace(_, err) => err.message(),
+        }
+    }
+
+    // The values the error was raised with
+    pub fn irritants(&self) -> Vec<Val> {
+        match self {
+            Error::OutOfRangeIdx(_, idx, val) | Error::BadIndex(idx, val) => {
+                vec![Val::from(*idx as i64), val.clone()]
             }
-            ScanError::MultiLineString(line) => {
-                write!(
-                    f,
-                    "ReadError: Line: {line}, string literals cannot span multiple lines"
-                )
+            Error::Undeclared(name) | Error::Unassigned(name) => vec![Val::symbol(name)],
+            Error::BadArg(pos) => vec![Val::from(*pos as i64)],
+            Error::ClosureArity(_, _, got) => vec![Val::from(*got as i64)],
+            Error::BadKeywordArg(_, val) => vec![val.clone()],
+            Error::BadType(val)
+            | Error::ArgType(_, _, val)
+            | Error::MapArgsNotEven(val)
+            | Error::NotAProcedure(val)
+            | Error::NotHashable(val)
+            | Error::BadRule(val)
+            | Error::BadPattern(val)
+            | Error::BadTemplate(val)
+            | Error::BadParam(val)
+            | Error::ShadowedClause(val) => vec![val.clone()],
+            Error::Destructure(form, val) => vec![form.clone(), val.clone()],
+            Error::CantParseNum(s) => vec![Val::from(s.as_str())],
+            Error::Throw(_, _, irritants) => irritants.clone(),
+            Error::Located(_, err) | Error::Trace(_, err) => err.irritants(),
+            _ => vec![],
+        }
+    }
+
+    // The name of the procedure or macro that raised the error, if it is known
+    pub fn procedure(&self) -> Option<&str> {
+        match self {
+            Error::OutOfRangeIdx(name, ..) | Error::Arity(name) | Error::ArgType(name, ..) => {
+                Some(name)
             }
+            Error::NoMacroMatch(name)
+            | Error::ClosureArity(name, ..)
+            | Error::BadKeywordArg(name, _) => Some(name),
+            Error::Located(_, err) | Error::Trace(_, err) => err.procedure(),
+            _ => None,
+        }
+    }
+
+    // The keyword that identifies the kind of error, so that rusp code can
+    // tell errors apart without looking at their messages. Thrown errors use
+    // the keyword they were thrown with.
+    pub fn kind(&self) -> Val {
+        match self {
+            Error::OutOfRange => Val::keyword("out-of-range"),
+            Error::OutOfRangeIdx(..) => Val::keyword("out-of-range"),
+            Error::Undeclared(_) => Val::keyword("undeclared"),
+            Error::Unassigned(_) => Val::keyword("unassigned"),
+            Error::BadIndex(..) => Val::keyword("bad-index"),
+            Error::ArgsNotList => Val::keyword("args-not-list"),
+            Error::Arity(_) => Val::keyword("arity"),
+            Error::ClosureArity(..) => Val::keyword("arity"),
+            Error::BadKeywordArg(..) => Val::keyword("bad-keyword-arg"),
+            Error::BadArg(_) => Val::keyword("bad-arg"),
+            Error::BadType(_) => Val::keyword("bad-type"),
+            Error::ArgType(..) => Val::keyword("arg-type"),
+            Error::DivideByZero => Val::keyword("divide-by-zero"),
+            Error::MapArgsNotEven(_) => Val::keyword("map-args-not-even"),
+            Error::CantParseNum(_) => Val::keyword("cant-parse-num"),
+            Error::NotAProcedure(_) => Val::keyword("not-a-procedure"),
+            Error::NotHashable(_) => Val::keyword("not-hashable"),
+            Error::Immutable => Val::keyword("immutable"),
+            Error::BadRule(_) => Val::keyword("bad-rule"),
+            Error::BadPattern(_) => Val::keyword("bad-pattern"),
+            Error::BadTemplate(_) => Val::keyword("bad-template"),
+            Error::TemplateElipseNotVar => Val::keyword("bad-template"),
+            Error::BadParam(_) => Val::keyword("bad-param"),
+            Error::ShadowedClause(_) => Val::keyword("shadowed-clause"),
+            Error::Destructure(..) => Val::keyword("destructure"),
+            Error::ElipseMismatch(..) => Val::keyword("elipse-mismatch"),
+            Error::NoMacroMatch(_) => Val::keyword("no-macro-match"),
+            Error::Throw(kind, ..) => kind.clone(),
+            Error::Read(_) => Val::keyword("read"),
+            Error::Located(_, err) | Error::Trace(_, err) => err.kind(),
         }
     }
 }
-*/
-
-// UserError //////////////////////////////////////////////////////////////////
-
-/*
-#[derive(Debug, Clone, PartialEq)]
-pub enum UserError {
-    Undeclared(Str),
-    ArgType(String, String, Val),
-    OutOfRange(usize, Val),
-    IndexError(String, usize, Val),
-    Arity(String),
-    Syntax(Val),
-    ReadError(ScanError),
+
+impl From<ScanError> for Error {
+    fn from(err: ScanError) -> Error {
+        Error::Read(err)
+    }
 }
 
-impl std::fmt::Display for UserError {
+impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            UserError::Undeclared(name) => {
-                write!(f, "Error: undeclared symbol: {name}")
-            }
-            UserError::Arity(name) => {
-                write!(f, "Error in {name}: incorrect argument count")
-            }
-            UserError::ArgType(name, kind, expr) => {
-                write!(f, "Error in {name}: {expr} must be {kind}")
-            }
-            UserError::OutOfRange(idx, val) => {
-                write!(f, "Error: {idx} is not a valid index for {val}")
-            }
-            UserError::IndexError(name, idx, val) => {
-                write!(f, "Error in {name}: {idx} is not a valid index for {val}")
+            Error::Throw(kind, _, irritants) => {
+                write!(f, "Error {}: {}", kind.to_display(), self.message())?;
+                for val in irritants.iter() {
+                    write!(f, " {}", val.to_external())?;
+                }
+                Ok(())
             }
-            UserError::Syntax(expr) => {
-                write!(f, "Error: invalid syntax: {expr}")
-            }
-            UserError::ReadError(err) => {
-                write!(f, "{}", err)
+            Error::Read(err) => write!(f, "{err}"),
+            Error::Located(span, err) => write!(f, "{span}: {err}"),
+            Error::Trace(frames, err) => {
+                write!(f, "{err}")?;
+                write_trace(f, frames)
             }
+            err => match err.procedure() {
+                Some(name) => write!(f, "Error in {name}: {}", err.message()),
+                None => write!(f, "Error: {}", err.message()),
+            },
+        }
+    }
+}
+
+// The most frames a backtrace prints, not counting repeats
+const TRACE_LIMIT: usize = 32;
+
+// The innermost frame is printed first. A frame repeated by recursion is
+// printed once followed by the number of repeats, and frames past the limit are
+// only counted.
+fn write_trace(f: &mut std::fmt::Formatter, frames: &[Frame]) -> std::fmt::Result {
+    let lines: Vec<String> = frames.iter().rev().map(|frame| frame.to_string()).collect();
+    let mut printed = 0;
+    let mut i = 0;
+    while i < lines.len() {
+        if printed == TRACE_LIMIT {
+            return write!(f, "\n  ... {} more frames", lines.len() - i);
+        }
+        let repeats = lines[i..]
+            .iter()
+            .take_while(|line| **line == lines[i])
+            .count();
+        write!(f, "\n  {}", lines[i])?;
+        if repeats > 1 {
+            write!(f, "\n  ... repeated {} more times", repeats - 1)?;
         }
+        printed += 1;
+        i += repeats;
+    }
+    Ok(())
+}
+
+// Scan/Read Error ////////////////////////////////////////////////////////////
+
+// Errors raised while reading source text. The reader locates them the same
+// way runtime errors are located, at the token they were raised on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanError {
+    Eof,
+    BadChar(char),
+    BadToken(String),
+    BadIdentifier(String),
+    BadEscape(String),
+    BadNumber(String),
+    MultiLineString,
+    DerefNotIdent(String),
+    OddMapPairs,
+    BadMapKey(String),
+    Located(Rc<Span>, Box<ScanError>),
+}
+
+impl ScanError {
+    // Attach the location of the token that raised the error, keeping the
+    // innermost location like Error::at
+    pub fn at(self, span: Rc<Span>) -> ScanError {
+        match self {
+            err @ ScanError::Located(..) => err,
+            err => ScanError::Located(span, Box::new(err)),
+        }
+    }
+
+    pub fn span(&self) -> Option<Rc<Span>> {
+        match self {
+            ScanError::Located(span, _) => Some(span.clone()),
+            _ => None,
+        }
+    }
+
+    // Whether the text ended in the middle of a form
+    pub fn is_eof(&self) -> bool {
+        match self {
+            ScanError::Located(_, err) => err.is_eof(),
+            err => *err == ScanError::Eof,
+        }
+    }
+
+    // The message describing the error, without its location
+    pub fn message(&self) -> String {
+        match self {
+            ScanError::Eof => "unexpected EOF".to_string(),
+            ScanError::BadToken(tk) => format!("unexpected token: {tk}"),
+            ScanError::BadChar(ch) => format!("unexpected character: {ch}"),
+            ScanError::BadIdentifier(id) => format!("invalid identifier: {id}"),
+            ScanError::BadEscape(s) => format!("invalid escape character: {s}"),
+            ScanError::BadNumber(n) => format!("invalid number: {n}"),
+            ScanError::MultiLineString => "string literals cannot span multiple lines".to_string(),
+            ScanError::OddMapPairs => "map literals need a value for every key".to_string(),
+            ScanError::BadMapKey(key) => format!("invalid map key: {key}"),
+            ScanError::DerefNotIdent(tk) => format!("@ must be followed by a symbol: {tk}"),
+            ScanError::Located(_, err) => err.message(),
+        }
+    }
+}
+
+impl std::fmt::Display for ScanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ScanError::Located(span, err) => write!(f, "{span}: {err}"),
+            err => write!(f, "Error: {}", err.message()),
+        }
+    }
+}
+
+// Testing ////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::List;
+
+    #[test]
+    fn test_error_messages() {
+        assert_eq!(
+            Error::ArgType("nth", "non-negative integer", Val::from(-1)).to_string(),
+            "Error in nth: -1 must be non-negative integer"
+        );
+        assert_eq!(
+            Error::Undeclared("x".to_string()).to_string(),
+            "Error: undeclared symbol: x"
+        );
+        assert_eq!(
+            Error::ClosureArity("f".to_string(), "2 arguments".to_string(), 3).to_string(),
+            "Error in f: expected 2 arguments, got 3"
+        );
+        assert_eq!(
+            Error::Throw(
+                Val::keyword("bad-value"),
+                Val::from("not allowed"),
+                vec![Val::from(3), Val::from("a")]
+            )
+            .to_string(),
+            "Error :bad-value: not allowed 3 \"a\""
+        );
+        assert_eq!(
+            Error::from(ScanError::Eof).to_string(),
+            "Error: unexpected EOF"
+        );
+        let span = Rc::new(Span::new(Some(Rc::from("file.rusp")), 3, 12));
+        assert_eq!(
+            Error::from(ScanError::BadChar('%').at(span)).to_string(),
+            "file.rusp:3:12: Error: unexpected character: %"
+        );
+
+        let span = Rc::new(Span::new(Some(Rc::from("file.rusp")), 42, 7));
+        assert_eq!(
+            Error::Arity("car").at(Some(span)).to_string(),
+            "file.rusp:42:7: Error in car: incorrect argument count"
+        );
+    }
+
+    #[test]
+    fn test_traces_collapse_repeated_frames() {
+        let call = |name: &str, line: usize| {
+            let span = Rc::new(Span::new(None, line, 1));
+            Frame::new(
+                name,
+                Rc::new(List::new(Val::symbol(name), None).with_span(span)),
+            )
+        };
+        let mut frames = vec![call("main", 1)];
+        frames.extend(std::iter::repeat_n(call("deep", 2), 5000));
+        frames.push(call("car", 3));
+        assert_eq!(
+            Error::Trace(frames, Box::new(Error::DivideByZero)).to_string(),
+            "Error: division by zero\n  in car at 3:1\n  in deep at 2:1\n  \
+             ... repeated 4999 more times\n  in main at 1:1"
+        );
+
+        let frames = (0..40).map(|i| call("f", i)).collect();
+        let trace = Error::Trace(frames, Box::new(Error::DivideByZero)).to_string();
+        assert_eq!(trace.lines().count(), 1 + TRACE_LIMIT + 1);
+        assert!(trace.ends_with("\n  in f at 8:1\n  ... 8 more frames"));
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        assert_eq!(Error::DivideByZero.kind(), Val::keyword("divide-by-zero"));
+        assert_eq!(
+            Error::Unassigned("x".to_string()).kind(),
+            Val::keyword("unassigned")
+        );
+        assert_eq!(
+            Error::ArgType("car", "list", Val::from(1)).kind(),
+            Val::keyword("arg-type")
+        );
+        assert_eq!(
+            Error::Throw(Val::keyword("oops"), Val::from("msg"), vec![]).kind(),
+            Val::keyword("oops")
+        );
+        let span = Rc::new(Span::new(None, 1, 1));
+        assert_eq!(
+            Error::Arity("car").at(Some(span)).kind(),
+            Val::keyword("arity")
+        );
     }
 }
-*/
@@ -0,0 +1,34 @@
+use crate::data::{List, Span};
+use std::rc::Rc;
+
+// Frame //////////////////////////////////////////////////////////////////////
+
+// A procedure or closure that was being applied when an error was raised,
+// along with the form that called it. A list of frames makes up a backtrace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub name: String,
+    pub form: Rc<List>,
+}
+
+impl Frame {
+    pub fn new(name: &str, form: Rc<List>) -> Frame {
+        Frame {
+            name: name.to_string(),
+            form,
+        }
+    }
+
+    pub fn span(&self) -> Option<Rc<Span>> {
+        self.form.span()
+    }
+}
+
+impl std::fmt::Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.span() {
+            Some(span) => write!(f, "in {} at {}", self.name, span),
+            None => write!(f, "in {} called as {}", self.name, self.form),
+        }
+    }
+}
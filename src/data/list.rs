@@ -1,13 +1,19 @@
-use crate::data::{DisplayRep, ExternalRep, Val};
+use crate::data::{DisplayRep, ExternalRep, Span, Val};
 use std::rc::Rc;
 
 // List ///////////////////////////////////////////////////////////////////////
 
-#[derive(Clone, PartialEq, Eq)]
+// A list read from source text also carries the span it was read from, so that
+// errors raised while evaluating it can point back to it, and each of its cells
+// carries the span of its head. The spans are not part of the list's value and
+// are ignored when comparing lists.
+#[derive(Clone, Eq)]
 pub struct List {
     head: Val,
     tail: Option<Rc<List>>,
     length: usize,
+    span: Option<Rc<Span>>,
+    head_span: Option<Rc<Span>>,
 }
 
 impl List {
@@ -22,6 +28,8 @@ impl List {
             head: head,
             tail: tail,
             length: len,
+            span: None,
+            head_span: None,
         }
     }
 
@@ -49,6 +57,16 @@ impl List {
         }
     }
 
+    pub fn with_span(mut self, span: Rc<Span>) -> List {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_head_span(mut self, span: Rc<Span>) -> List {
+        self.head_span = Some(span);
+        self
+    }
+
     // Access //
 
     pub fn head(&self) -> &Val {
@@ -59,6 +77,21 @@ impl List {
         self.tail.clone()
     }
 
+    pub fn span(&self) -> Option<Rc<Span>> {
+        self.span.clone()
+    }
+
+    // The span of the first element read that is equal to a value
+    pub fn element_span(&self, val: &Val) -> Option<Rc<Span>> {
+        let mut list = self;
+        loop {
+            if list.head_span.is_some() && list.head == *val {
+                return list.head_span.clone();
+            }
+            list = list.tail.as_deref()?;
+        }
+    }
+
     pub fn get(&self, idx: usize) -> Option<Val> {
         for (i, v) in self.values().enumerate() {
             if i == idx {
@@ -103,6 +136,12 @@ impl List {
 
 // Traits /////////////////////////////////////////////////////////////////////
 
+impl PartialEq for List {
+    fn eq(&self, other: &List) -> bool {
+        self.length == other.length && self.head == other.head && self.tail == other.tail
+    }
+}
+
 impl DisplayRep for List {
     fn to_display(&self) -> String {
         let strings: Vec<String> = self.values().map(|v| v.to_display()).collect();
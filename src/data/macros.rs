@@ -17,7 +17,7 @@ struct Rule {
 }
 
 impl Rule {
-    pub fn from_rule_expr(name: Rc<Str>, expr: Val, syms: &[Rc<Str>]) -> Result<Rule, Error> {
+    pub fn from_rule_expr(name: Sym, expr: Val, syms: &[Sym]) -> Result<Rule, Error> {
         let (p, t) = match expr {
             Val::Vector(ref vec) => (
                 vec.borrow().get(0).ok_or(Error::BadRule(expr.clone()))?,
@@ -46,34 +46,94 @@ impl Rule {
 
 // Macro //////////////////////////////////////////////////////////////////////
 
-#[derive(Clone, PartialEq, Eq)]
+// A macro either has rules, like those made by macro-rules, or a transformer
+// closure, like those made by defmacro. A transformer is applied to the
+// unevaluated arguments of a use and returns the expansion. As applying it needs
+// the vm, the vm expands these macros itself.
+//
+// The environment is the one the macro was defined in. The symbols introduced by
+// the templates of its rules are renamed relative to it when it is expanded.
+#[derive(Clone)]
 pub struct Macro {
-    name: Rc<Str>,
+    name: Sym,
     rules: Vec<Rule>,
+    env: Environ,
+    transformer: Option<Rc<Closure>>,
 }
 
 impl Macro {
-    pub fn new(name: Rc<Str>, syms: &[Rc<Str>], rule_exprs: Rc<List>) -> Result<Self, Error> {
-        let mut full_syms = vec![name.clone()];
+    pub fn new(name: Sym, syms: &[Sym], rule_exprs: Rc<List>, env: Environ) -> Result<Self, Error> {
+        let mut full_syms = vec![name];
         for s in syms {
-            full_syms.push(s.clone())
+            full_syms.push(*s)
         }
 
         let mut rules = Vec::new();
         for expr in rule_exprs.values() {
-            rules.push(Rule::from_rule_expr(name.clone(), expr, &full_syms)?);
+            rules.push(Rule::from_rule_expr(name, expr, &full_syms)?);
         }
 
-        Ok(Self { name, rules })
+        Ok(Self {
+            name,
+            rules,
+            env,
+            transformer: None,
+        })
+    }
+
+    pub fn procedural(name: Sym, transformer: Rc<Closure>) -> Self {
+        Self {
+            name,
+            rules: Vec::new(),
+            env: transformer.env.clone(),
+            transformer: Some(transformer),
+        }
+    }
+
+    pub fn name(&self) -> Sym {
+        self.name
+    }
+
+    pub fn transformer(&self) -> Option<Rc<Closure>> {
+        self.transformer.clone()
     }
 
+    // The expansion takes the span of the macro use, so that errors in the
+    // expanded code point at the place the macro was used. Pieces of the use
+    // that are captured and placed into the expansion keep their own spans.
     pub fn expand(&self, expr: Val, env: Environ) -> Result<Val, Error> {
+        if self.transformer.is_some() {
+            panic!("the vm expands macros with transformers: {}", self.name);
+        }
+
         for rule in self.rules.iter() {
             if let Some(captures) = rule.pattern.matches(expr.clone(), env.clone()) {
-                return rule.template.expand(self.name.clone(), captures);
+                let renames = Renames::new(self.env.clone());
+                return Ok(Macro::at_use(
+                    rule.template.expand(&captures, Some(&renames))?,
+                    &expr,
+                ));
+            }
+        }
+        Err(Error::NoMacroMatch(self.name.to_string()).at(use_span(&expr)))
+    }
+
+    // Give an expansion the span of the use it was expanded from if it does not
+    // already have one.
+    pub fn at_use(expansion: Val, expr: &Val) -> Val {
+        match (expansion, use_span(expr)) {
+            (Val::List(ls), Some(span)) if ls.span().is_none() => {
+                Val::from(List::clone(&ls).with_span(span))
             }
+            (val, _) => val,
         }
-        Err(Error::NoMacroMatch(self.name.to_string()))
+    }
+}
+
+fn use_span(expr: &Val) -> Option<Rc<Span>> {
+    match expr {
+        Val::List(ls) => ls.span(),
+        _ => None,
     }
 }
 
@@ -91,6 +151,22 @@ impl ExternalRep for Macro {
     }
 }
 
+impl PartialEq for Macro {
+    fn eq(&self, other: &Macro) -> bool {
+        let same_transformer = match (&self.transformer, &other.transformer) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        self.name == other.name
+            && self.rules == other.rules
+            && Rc::ptr_eq(&self.env, &other.env)
+            && same_transformer
+    }
+}
+
+impl Eq for Macro {}
+
 impl std::fmt::Display for Macro {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}", self.to_display())
@@ -112,7 +188,7 @@ mod tests {
     #[test]
     fn test_simple_pattern_with_literals() {
         let env = Rc::new(Env::new());
-        let name = Rc::new(Str::from("f"));
+        let name = Sym::from("f");
         // just 1 rule
         // ([(f 10 11)
         //   (if #t 9 10)])
@@ -124,14 +200,14 @@ mod tests {
             Val::from(10),
         ]);
         let rules = List::from_vec(&vec![Val::from(vec![expr.clone(), result.clone()])]).unwrap();
-        let mac = Macro::new(name.clone(), &vec![], Rc::new(rules.clone())).unwrap();
+        let mac = Macro::new(name, &vec![], Rc::new(rules.clone()), env.clone()).unwrap();
         assert_eq!(mac.expand(expr, env.clone()), Ok(result));
     }
 
     #[test]
     fn test_multi_pattern_with_literals() {
         let env = Rc::new(Env::new());
-        let name = Rc::new(Str::from("f"));
+        let name = Sym::from("f");
         // just 1 rule
         // ([(f 44 90)
         //   (if #t 9 10)]
@@ -156,14 +232,14 @@ mod tests {
             Val::from(vec![pat2.clone(), temp2.clone()]),
         ])
         .unwrap();
-        let mac = Macro::new(name.clone(), &vec![], Rc::new(rules.clone())).unwrap();
+        let mac = Macro::new(name, &vec![], Rc::new(rules.clone()), env.clone()).unwrap();
         assert_eq!(mac.expand(pat2, env.clone()), Ok(temp2));
 
-        let mac = Macro::new(name.clone(), &vec![], Rc::new(rules.clone())).unwrap();
+        let mac = Macro::new(name, &vec![], Rc::new(rules.clone()), env.clone()).unwrap();
         assert_eq!(mac.expand(pat1, env.clone()), Ok(temp1));
 
         let pat3 = Val::list_from_vec(&vec![Val::symbol("u"), Val::from(10), Val::from(11)]);
-        let mac = Macro::new(name.clone(), &vec![], Rc::new(rules.clone())).unwrap();
+        let mac = Macro::new(name, &vec![], Rc::new(rules.clone()), env.clone()).unwrap();
         assert_eq!(
             mac.expand(pat3, env.clone()),
             Err(Error::NoMacroMatch("f".to_string()))
@@ -173,7 +249,7 @@ mod tests {
     #[test]
     fn test_let_to_lambda() {
         let env = Rc::new(Env::new());
-        let name = Rc::new(Str::from("let"));
+        let name = Sym::from("let");
 
         // (let ((var val) ...) body ...)
         let pat = Val::list_from_vec(&vec![
@@ -221,14 +297,39 @@ mod tests {
             Val::from(6),
         ]);
 
-        let mac = Macro::new(name.clone(), &vec![], Rc::new(rules.clone())).unwrap();
+        let mac = Macro::new(name, &vec![], Rc::new(rules.clone()), env.clone()).unwrap();
         assert_eq!(mac.expand(expr, env.clone()), Ok(result));
     }
 
+    #[test]
+    fn test_expansion_keeps_the_span_of_the_use() {
+        let env = Rc::new(Env::new());
+        let name = Sym::from("f");
+        // ([(f x) (if x 1 2)])
+        let pat = Val::list_from_vec(&[Val::symbol("f"), Val::symbol("x")]);
+        let temp = Val::list_from_vec(&[
+            Val::symbol("if"),
+            Val::symbol("x"),
+            Val::from(1),
+            Val::from(2),
+        ]);
+        let rules = List::from_vec(&[Val::from(vec![pat, temp])]).unwrap();
+        let mac = Macro::new(name, &[], Rc::new(rules), env.clone()).unwrap();
+
+        let span = Rc::new(Span::new(None, 4, 2));
+        let expr = List::from_vec(&[Val::symbol("f"), Val::Bool(true)])
+            .unwrap()
+            .with_span(span.clone());
+        match mac.expand(Val::from(expr), env) {
+            Ok(Val::List(ls)) => assert_eq!(ls.span(), Some(span)),
+            result => panic!("expected a list: {:?}", result),
+        }
+    }
+
     #[test]
     fn test_nested_lists_and_vecs_without_elipse() {
         let env = Rc::new(Env::new());
-        let name = Rc::new(Str::from("let*"));
+        let name = Sym::from("let*");
         // ([(let* [(var val)] body)
         //   ((lambda [var] body) val)])
         let pat = Val::list_from_vec(&vec![
@@ -268,7 +369,7 @@ mod tests {
             Val::from(5),
         ]);
 
-        let mac = Macro::new(name.clone(), &vec![], Rc::new(rules.clone())).unwrap();
+        let mac = Macro::new(name, &vec![], Rc::new(rules.clone()), env.clone()).unwrap();
         assert_eq!(mac.expand(expr, env.clone()), Ok(result));
     }
 }
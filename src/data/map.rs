@@ -1,10 +1,12 @@
-use crate::data::{DisplayRep, Error, ExternalRep, Val};
+use crate::data::{DisplayRep, ElementSpans, Error, ExternalRep, Span, Val};
 use std::collections::HashMap;
+use std::rc::Rc;
 
 #[derive(Clone)]
 pub struct Map {
     mutable: bool,
     entries: HashMap<Val, Val>,
+    spans: ElementSpans,
 }
 
 impl Map {
@@ -14,6 +16,7 @@ impl Map {
         Map {
             mutable: true,
             entries: HashMap::new(),
+            spans: ElementSpans::default(),
         }
     }
 
@@ -21,6 +24,7 @@ impl Map {
         let mut map = Map {
             mutable: true,
             entries: HashMap::with_capacity(entries.len()),
+            spans: ElementSpans::default(),
         };
         for (k, v) in entries.iter() {
             match map.assoc(k.clone(), v.clone()) {
@@ -41,6 +45,7 @@ impl Map {
         let mut map = Map {
             mutable: true,
             entries: HashMap::with_capacity(entries.len()),
+            spans: ElementSpans::default(),
         };
         map.add_pairs_from_vec(entries)?;
         Ok(map)
@@ -80,6 +85,16 @@ impl Map {
         map
     }
 
+    // A map read from source keeps the spans of its keys and values
+    pub fn with_spans(mut self, spans: Vec<(Val, Rc<Span>)>) -> Map {
+        self.spans = ElementSpans::new(spans);
+        self
+    }
+
+    pub fn element_span(&self, val: &Val) -> Option<Rc<Span>> {
+        self.spans.find(val)
+    }
+
     // Access //
 
     pub fn freeze(&mut self) {
@@ -1,5 +1,5 @@
 use crate::data::*;
-use std::rc::Rc;
+use std::collections::HashMap;
 
 // TODO I have the expected functionality for everything except the scheme
 // way of handing literals and symbols when a symbol is defined outside the
@@ -9,27 +9,28 @@ use std::rc::Rc;
 // TODO I beleive that most of the current implementation for correct patterns is
 // complete, but there is no testing for errors.
 //
-// TODO for a full implementation either Maps should have the same behaviour as
-// lists and vectors or they should match as literals. The first is the best, but
-// I wanted to get it working for now ignoring maps, but could do the second in
-// the interim.
+// Map patterns match maps and dicts that have exactly the keys of the pattern,
+// unless the pattern has a ... key. Then the value of the ... key is a var that
+// captures a map or dict with the rest of the entries. Keys are literals that
+// are compared with the keys of the map, and values are patterns.
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Pattern {
-    Atom(Rc<Str>, Val),
-    Var(Rc<Str>),
+    Atom(Sym, Val),
+    Var(Sym),
     List(Vec<Pattern>, Option<Box<Pattern>>),
     Vector(Vec<Pattern>, Option<Box<Pattern>>),
+    Map(Vec<(Val, Pattern)>, Option<Sym>),
 }
 
 impl Pattern {
     // Construct //
 
-    pub fn new(name: Rc<Str>, syms: &[Rc<Str>], expr: Val) -> Result<Pattern, Error> {
+    pub fn new(name: Sym, syms: &[Sym], expr: Val) -> Result<Pattern, Error> {
         match expr {
             Val::Symbol(s) => {
                 if s == name || syms.contains(&s) {
-                    Ok(Pattern::Atom(name, Val::symbol(s.to_string().as_str())))
+                    Ok(Pattern::Atom(name, Val::Symbol(s)))
                 } else {
                     Ok(Pattern::Var(s))
                 }
@@ -47,14 +48,28 @@ impl Pattern {
                     Pattern::from_collection(vec.borrow().values().cloned(), name, syms, e)?;
                 Ok(Pattern::Vector(patterns, elipse))
             }
+            Val::Map(ref map) => {
+                let mut patterns = Vec::new();
+                let mut rest = None;
+                for (key, val) in map.borrow().entries() {
+                    match (key, val) {
+                        (Val::Symbol(k), Val::Symbol(v)) if k.is("...") => rest = Some(*v),
+                        (Val::Symbol(k), _) if k.is("...") => {
+                            return Err(Error::BadPattern(expr.clone()))
+                        }
+                        _ => patterns.push((key.clone(), Pattern::new(name, syms, val.clone())?)),
+                    }
+                }
+                Ok(Pattern::Map(patterns, rest))
+            }
             _ => Err(Error::BadPattern(expr)),
         }
     }
 
     fn from_collection(
         iter: impl Iterator<Item = Val>,
-        name: Rc<Str>,
-        syms: &[Rc<Str>],
+        name: Sym,
+        syms: &[Sym],
         expr: Val,
     ) -> Result<(Vec<Pattern>, Option<Box<Pattern>>), Error> {
         let mut pxs = vec![];
@@ -68,7 +83,7 @@ impl Pattern {
                     };
                     break;
                 }
-                _ => pxs.push(Pattern::new(name.clone(), syms, val.clone())?),
+                _ => pxs.push(Pattern::new(name, syms, val.clone())?),
             }
         }
         Ok((pxs, elipse))
@@ -76,97 +91,173 @@ impl Pattern {
 
     // Matching //
 
-    pub fn matches(&self, expr: Val, env: Environ) -> Option<Environ> {
-        self.matches_rec(expr, env, Rc::new(Env::new()), 0)
+    pub fn matches(&self, expr: Val, env: Environ) -> Option<Captures> {
+        let mut captures = Captures::new();
+        if self.matches_rec(expr, &env, &mut captures) {
+            Some(captures)
+        } else {
+            None
+        }
     }
 
-    fn matches_rec(
-        &self,
-        expr: Val,
-        env: Environ,
-        captures: Environ,
-        idx: usize,
-    ) -> Option<Environ> {
+    fn matches_rec(&self, expr: Val, env: &Environ, captures: &mut Captures) -> bool {
         match self {
             Pattern::Atom(name, val) => {
                 if let Val::Symbol(s) = val {
-                    if s != name && env.lookup(&s).is_some() {
-                        return None;
+                    if s != name && env.lookup(s).is_some() {
+                        return false;
                     }
                 }
-                if *val == expr {
-                    Some(captures)
-                } else {
-                    None
-                }
+                // A literal symbol also matches a copy of itself that was
+                // renamed by the expansion of another macro.
+                let renamed = match (val, &expr) {
+                    (Val::Symbol(s), Val::Symbol(e)) => e.renamed_from().as_ref() == Some(s),
+                    _ => false,
+                };
+                *val == expr || renamed
             }
             Pattern::Var(s) => {
-                let name = Rc::new(Str::from(format!("{s}!!{idx}").as_str()));
-                captures.insert(name, expr);
-                Some(captures)
+                captures.insert(*s, Capture::Single(expr));
+                true
             }
             Pattern::List(patterns, elipse) => match expr {
-                Val::List(ls) => self.match_collection(
-                    ls.values().collect(),
-                    patterns,
-                    elipse,
-                    idx,
-                    captures.clone(),
-                    env.clone(),
-                ),
-                _ => None,
+                Val::List(ls) => {
+                    self.match_collection(ls.values().collect(), patterns, elipse, env, captures)
+                }
+                Val::Empty => self.match_collection(vec![], patterns, elipse, env, captures),
+                _ => false,
             },
             Pattern::Vector(patterns, elipse) => match expr {
                 Val::Vector(vals) => self.match_collection(
                     vals.borrow().values().cloned().collect(),
                     patterns,
                     elipse,
-                    idx,
-                    captures.clone(),
-                    env.clone(),
+                    env,
+                    captures,
                 ),
-                _ => None,
+                _ => false,
             },
+            Pattern::Map(patterns, rest) => match expr {
+                Val::Map(map) => self.match_map(&map.borrow(), patterns, rest, env, captures),
+                _ => false,
+            },
+        }
+    }
+
+    fn match_map(
+        &self,
+        map: &Map,
+        patterns: &[(Val, Pattern)],
+        rest: &Option<Sym>,
+        env: &Environ,
+        captures: &mut Captures,
+    ) -> bool {
+        if rest.is_none() && map.len() != patterns.len() {
+            return false;
+        }
+
+        // match the patterns for each key
+        for (key, p) in patterns.iter() {
+            let matched = match map.get(key.clone()) {
+                Some(val) => p.matches_rec(val, env, captures),
+                None => false,
+            };
+            if !matched {
+                return false;
+            }
+        }
+
+        // capture the entries that were not matched
+        if let Some(var) = rest {
+            let mut others = Map::new();
+            for (key, val) in map.entries() {
+                if !patterns.iter().any(|(k, _)| k == key) {
+                    // the keys came from a map so they can be added to one
+                    others.assoc(key.clone(), val.clone()).unwrap();
+                }
+            }
+            if map.is_dict() {
+                others.freeze();
+            }
+            captures.insert(*var, Capture::Single(Val::from(others)));
         }
+        true
     }
 
+    // Each var in the elipse pattern captures a sequence with what it matched in
+    // each of the remaining values, so that they can be expanded in lockstep.
     fn match_collection(
         &self,
         vals: Vec<Val>,
         patterns: &[Pattern],
         elipse: &Option<Box<Pattern>>,
-        idx: usize,
-        captures: Environ,
-        env: Environ,
-    ) -> Option<Environ> {
-        //println!("{:?}", vals);
-        let mut captures = captures;
+        env: &Environ,
+        captures: &mut Captures,
+    ) -> bool {
+        if vals.len() < patterns.len() || (elipse.is_none() && vals.len() != patterns.len()) {
+            return false;
+        }
+
         // match patterns
-        for (i, p) in patterns.iter().enumerate() {
-            captures = match p.matches_rec(vals[i].clone(), env.clone(), captures, idx) {
-                Some(captures) => captures,
-                None => return None,
-            };
+        for (p, val) in patterns.iter().zip(vals.iter()) {
+            if !p.matches_rec(val.clone(), env, captures) {
+                return false;
+            }
         }
+
         // match elipse
-        if patterns.len() < vals.len() {
-            match elipse {
-                Some(pat) => {
-                    for (i, val) in vals[patterns.len()..].iter().enumerate() {
-                        captures = match pat.matches_rec(val.clone(), env.clone(), captures, i) {
-                            Some(captures) => captures,
-                            None => return None,
-                        }
-                    }
+        if let Some(pat) = elipse {
+            let mut matched = Vec::new();
+            for val in vals[patterns.len()..].iter() {
+                let mut caps = Captures::new();
+                if !pat.matches_rec(val.clone(), env, &mut caps) {
+                    return false;
                 }
-                None => return None,
-            };
+                matched.push(caps);
+            }
+            for var in pat.vars() {
+                let seq = matched
+                    .iter_mut()
+                    .filter_map(|caps| caps.remove(&var))
+                    .collect();
+                captures.insert(var, Capture::Seq(seq));
+            }
+        }
+        true
+    }
+
+    // The pattern variables in the pattern
+    pub fn vars(&self) -> Vec<Sym> {
+        match self {
+            Pattern::Atom(..) => vec![],
+            Pattern::Var(s) => vec![*s],
+            Pattern::List(patterns, elipse) | Pattern::Vector(patterns, elipse) => patterns
+                .iter()
+                .chain(elipse.iter().map(|p| p.as_ref()))
+                .flat_map(|p| p.vars())
+                .collect(),
+            Pattern::Map(patterns, rest) => patterns
+                .iter()
+                .flat_map(|(_, p)| p.vars())
+                .chain(rest.iter().cloned())
+                .collect(),
         }
-        // return captures
-        Some(captures)
     }
 }
 
+// Captures ///////////////////////////////////////////////////////////////////
+
+// What a pattern variable matched. A variable under an elipse matches a
+// sequence with a capture for each form the elipse matched, nested once for
+// each elipse it is under.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Capture {
+    Single(Val),
+    Seq(Vec<Capture>),
+}
+
+pub type Captures = HashMap<Sym, Capture>;
+
 // Testing ////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
@@ -176,48 +267,48 @@ mod test {
     // Creation //
     #[test]
     fn test_creating_atoms() {
-        let name = Rc::new(Str::from("name"));
-        let syms = vec![Rc::new(Str::from("sym"))];
+        let name = Sym::from("name");
+        let syms = vec![Sym::from("sym")];
         assert_eq!(
-            Pattern::new(name.clone(), &syms, Val::symbol("sym")),
-            Ok(Pattern::Atom(name.clone(), Val::symbol("sym")))
+            Pattern::new(name, &syms, Val::symbol("sym")),
+            Ok(Pattern::Atom(name, Val::symbol("sym")))
         );
         assert_eq!(
-            Pattern::new(name.clone(), &syms, Val::from('A')),
-            Ok(Pattern::Atom(name.clone(), Val::from('A')))
+            Pattern::new(name, &syms, Val::from('A')),
+            Ok(Pattern::Atom(name, Val::from('A')))
         );
         assert_eq!(
-            Pattern::new(name.clone(), &syms, Val::Bool(true)),
-            Ok(Pattern::Atom(name.clone(), Val::Bool(true)))
+            Pattern::new(name, &syms, Val::Bool(true)),
+            Ok(Pattern::Atom(name, Val::Bool(true)))
         );
         assert_eq!(
-            Pattern::new(name.clone(), &syms, Val::from(18)),
-            Ok(Pattern::Atom(name.clone(), Val::from(18)))
+            Pattern::new(name, &syms, Val::from(18)),
+            Ok(Pattern::Atom(name, Val::from(18)))
         );
         assert_eq!(
-            Pattern::new(name.clone(), &syms, Val::from("hello, world!")),
-            Ok(Pattern::Atom(name.clone(), Val::from("hello, world!")))
+            Pattern::new(name, &syms, Val::from("hello, world!")),
+            Ok(Pattern::Atom(name, Val::from("hello, world!")))
         );
         assert_eq!(
-            Pattern::new(name.clone(), &syms, Val::keyword(":hello")),
-            Ok(Pattern::Atom(name.clone(), Val::keyword(":hello")))
+            Pattern::new(name, &syms, Val::keyword(":hello")),
+            Ok(Pattern::Atom(name, Val::keyword(":hello")))
         );
     }
 
     #[test]
     fn test_creating_var() {
-        let name = Rc::new(Str::from("name"));
+        let name = Sym::from("name");
         let syms = vec![];
         assert_eq!(
-            Pattern::new(name.clone(), &syms, Val::symbol("let")),
-            Ok(Pattern::Var(Rc::new(Str::from("let"))))
+            Pattern::new(name, &syms, Val::symbol("let")),
+            Ok(Pattern::Var(Sym::from("let")))
         );
     }
 
     #[test]
     fn test_creating_list() {
-        let name = Rc::new(Str::from("cons"));
-        let syms = vec![Rc::new(Str::from("list"))];
+        let name = Sym::from("cons");
+        let syms = vec![Sym::from("list")];
 
         // (cons 89 (list 2 10))
         let expr = Val::list_from_vec(&vec![
@@ -229,13 +320,13 @@ mod test {
         // Pattern
         let pat = Pattern::List(
             vec![
-                Pattern::Atom(name.clone(), Val::symbol("cons")),
-                Pattern::Atom(name.clone(), Val::from(89)),
+                Pattern::Atom(name, Val::symbol("cons")),
+                Pattern::Atom(name, Val::from(89)),
                 Pattern::List(
                     vec![
-                        Pattern::Atom(name.clone(), Val::symbol("list")),
-                        Pattern::Atom(name.clone(), Val::from(2)),
-                        Pattern::Atom(name.clone(), Val::from(10)),
+                        Pattern::Atom(name, Val::symbol("list")),
+                        Pattern::Atom(name, Val::from(2)),
+                        Pattern::Atom(name, Val::from(10)),
                     ],
                     None,
                 ),
@@ -243,13 +334,13 @@ mod test {
             None,
         );
 
-        assert_eq!(Pattern::new(name.clone(), &syms, expr), Ok(pat));
+        assert_eq!(Pattern::new(name, &syms, expr), Ok(pat));
     }
 
     #[test]
     fn test_creating_vectors() {
-        let name = Rc::new(Str::from("name"));
-        let syms = vec![Rc::new(Str::from("push"))];
+        let name = Sym::from("name");
+        let syms = vec![Sym::from("push")];
 
         // [push 89 [2 10]]
         let expr = Val::from(vec![
@@ -261,12 +352,12 @@ mod test {
         // Pattern
         let pat = Pattern::Vector(
             vec![
-                Pattern::Atom(name.clone(), Val::symbol("push")),
-                Pattern::Atom(name.clone(), Val::from(89)),
+                Pattern::Atom(name, Val::symbol("push")),
+                Pattern::Atom(name, Val::from(89)),
                 Pattern::Vector(
                     vec![
-                        Pattern::Atom(name.clone(), Val::from(2)),
-                        Pattern::Atom(name.clone(), Val::from(10)),
+                        Pattern::Atom(name, Val::from(2)),
+                        Pattern::Atom(name, Val::from(10)),
                     ],
                     None,
                 ),
@@ -274,27 +365,27 @@ mod test {
             None,
         );
 
-        assert_eq!(Pattern::new(name.clone(), &syms, expr), Ok(pat));
+        assert_eq!(Pattern::new(name, &syms, expr), Ok(pat));
     }
 
     #[test]
     fn test_creating_vec_with_elipse() {
-        let name = Rc::new(Str::from("name"));
+        let name = Sym::from("name");
         let syms = vec![];
 
         // [1 2 ...]
         let expr = Val::from(vec![Val::from(1), Val::from(2), Val::symbol("...")]);
         let pat = Pattern::Vector(
-            vec![Pattern::Atom(name.clone(), Val::from(1))],
-            Some(Box::new(Pattern::Atom(name.clone(), Val::from(2)))),
+            vec![Pattern::Atom(name, Val::from(1))],
+            Some(Box::new(Pattern::Atom(name, Val::from(2)))),
         );
 
-        assert_eq!(Pattern::new(name.clone(), &syms, expr), Ok(pat));
+        assert_eq!(Pattern::new(name, &syms, expr), Ok(pat));
     }
 
     #[test]
     fn test_creating_nested_lists_with_elipses() {
-        let name = Rc::new(Str::from("let"));
+        let name = Sym::from("let");
         let syms = vec![];
 
         // (let ((var val) ...) body ...)
@@ -311,56 +402,46 @@ mod test {
         // Pattern
         let pat = Pattern::List(
             vec![
-                Pattern::Atom(name.clone(), Val::symbol("let")),
+                Pattern::Atom(name, Val::symbol("let")),
                 Pattern::List(
                     vec![],
                     Some(Box::new(Pattern::List(
                         vec![
-                            Pattern::Var(Rc::new(Str::from("var"))),
-                            Pattern::Var(Rc::new(Str::from("val"))),
+                            Pattern::Var(Sym::from("var")),
+                            Pattern::Var(Sym::from("val")),
                         ],
                         None,
                     ))),
                 ),
             ],
-            Some(Box::new(Pattern::Var(Rc::new(Str::from("body"))))),
+            Some(Box::new(Pattern::Var(Sym::from("body")))),
         );
 
-        assert_eq!(Pattern::new(name.clone(), &syms, expr), Ok(pat));
+        assert_eq!(Pattern::new(name, &syms, expr), Ok(pat));
     }
 
     // Matching //
 
     #[test]
     fn test_matching_atoms() {
-        let name = Rc::new(Str::from("name"));
+        let name = Sym::from("name");
         let env = Rc::new(Env::new());
-        let captures = Rc::new(Env::new());
 
-        let p = Pattern::Atom(name.clone(), Val::from(10));
-        assert_eq!(
-            p.matches_rec(Val::from(10), env.clone(), captures.clone(), 0),
-            Some(captures.clone())
-        );
-        assert_eq!(
-            p.matches_rec(Val::from(30), env.clone(), captures.clone(), 0),
-            None
-        );
+        let p = Pattern::Atom(name, Val::from(10));
+        assert_eq!(p.matches(Val::from(10), env.clone()), Some(Captures::new()));
+        assert_eq!(p.matches(Val::from(30), env.clone()), None);
 
-        let p = Pattern::Atom(name.clone(), Val::from("hello, world!"));
+        let p = Pattern::Atom(name, Val::from("hello, world!"));
         assert_eq!(
-            p.matches_rec(Val::from("hello, world!"), env.clone(), captures.clone(), 0),
-            Some(captures.clone())
-        );
-        assert_eq!(
-            p.matches_rec(Val::from("not it"), env.clone(), captures.clone(), 0),
-            None
+            p.matches(Val::from("hello, world!"), env.clone()),
+            Some(Captures::new())
         );
+        assert_eq!(p.matches(Val::from("not it"), env.clone()), None);
     }
 
     #[test]
     fn test_matching_atom_when_sym_is_in_env() {
-        let name = Rc::new(Str::from("name"));
+        let name = Sym::from("name");
         // Atoms that are symbols should not match if an identifier is in the
         // enclosing environment. The example in R5RS is for cond inside a let where
         // => is bound in let. The match for cond treats => as an identifier rather
@@ -374,40 +455,30 @@ mod test {
         // NOTE if symbol is the name it should still match, as it should always
         // be in the env.
         let env = Rc::new(Env::new());
-        let captures = Rc::new(Env::new());
 
-        let p = Pattern::Atom(name.clone(), Val::symbol("=>"));
-        assert_eq!(
-            p.matches_rec(Val::symbol("=>"), env.clone(), captures.clone(), 0),
-            Some(captures.clone())
-        );
-        env.insert(Rc::new(Str::from("=>")), Val::from(99));
+        let p = Pattern::Atom(name, Val::symbol("=>"));
         assert_eq!(
-            p.matches_rec(Val::symbol("=>"), env.clone(), captures.clone(), 0),
-            None
+            p.matches(Val::symbol("=>"), env.clone()),
+            Some(Captures::new())
         );
+        env.insert(Sym::from("=>"), Val::from(99));
+        assert_eq!(p.matches(Val::symbol("=>"), env.clone()), None);
     }
 
     #[test]
     fn test_matching_with_var() {
         let env = Rc::new(Env::new());
-        let captures = Rc::new(Env::new());
 
-        let var = Rc::new(Str::from("name"));
-        let fixed_var = Rc::new(Str::from("name!!0"));
-        let p = Pattern::Var(var.clone());
-        assert_eq!(
-            p.matches_rec(Val::from(10), env.clone(), captures.clone(), 0),
-            Some(captures.clone())
-        );
-        assert_eq!(captures.lookup(&fixed_var), Some(Val::from(10)));
+        let var = Sym::from("name");
+        let p = Pattern::Var(var);
+        let captures = p.matches(Val::from(10), env.clone()).unwrap();
+        assert_eq!(captures.get(&var), Some(&Capture::Single(Val::from(10))));
     }
 
     #[test]
     fn test_matching_list() {
-        let name = Rc::new(Str::from("name"));
+        let name = Sym::from("name");
         let env = Rc::new(Env::new());
-        let captures = Rc::new(Env::new());
 
         let expr = Val::list_from_vec(&vec![
             Val::symbol("name"),
@@ -416,36 +487,29 @@ mod test {
         ]);
         let p = Pattern::List(
             vec![
-                Pattern::Atom(name.clone(), Val::symbol("name")),
-                Pattern::Atom(name.clone(), Val::from(10)),
-                Pattern::Atom(name.clone(), Val::from("hello, world!")),
+                Pattern::Atom(name, Val::symbol("name")),
+                Pattern::Atom(name, Val::from(10)),
+                Pattern::Atom(name, Val::from("hello, world!")),
             ],
             None,
         );
-        assert_eq!(
-            p.matches_rec(expr.clone(), env.clone(), captures.clone(), 0),
-            Some(captures.clone())
-        );
+        assert_eq!(p.matches(expr.clone(), env.clone()), Some(Captures::new()));
 
         let p = Pattern::List(
             vec![
-                Pattern::Atom(name.clone(), Val::symbol("name")),
-                Pattern::Atom(name.clone(), Val::from(99)),
-                Pattern::Atom(name.clone(), Val::from("hello")),
+                Pattern::Atom(name, Val::symbol("name")),
+                Pattern::Atom(name, Val::from(99)),
+                Pattern::Atom(name, Val::from("hello")),
             ],
             None,
         );
-        assert_eq!(
-            p.matches_rec(expr.clone(), env.clone(), captures.clone(), 0),
-            None
-        );
+        assert_eq!(p.matches(expr.clone(), env.clone()), None);
     }
 
     #[test]
     fn test_matching_vector() {
-        let name = Rc::new(Str::from("name"));
+        let name = Sym::from("name");
         let env = Rc::new(Env::new());
-        let captures = Rc::new(Env::new());
 
         let expr = Val::from(vec![
             Val::symbol("name"),
@@ -454,23 +518,19 @@ mod test {
         ]);
         let p = Pattern::Vector(
             vec![
-                Pattern::Atom(name.clone(), Val::symbol("name")),
-                Pattern::Atom(name.clone(), Val::from(10)),
-                Pattern::Atom(name.clone(), Val::from("hello, world!")),
+                Pattern::Atom(name, Val::symbol("name")),
+                Pattern::Atom(name, Val::from(10)),
+                Pattern::Atom(name, Val::from("hello, world!")),
             ],
             None,
         );
-        assert_eq!(
-            p.matches_rec(expr.clone(), env.clone(), captures.clone(), 0),
-            Some(captures.clone())
-        );
+        assert_eq!(p.matches(expr.clone(), env.clone()), Some(Captures::new()));
     }
 
     #[test]
     fn test_matching_nested_list_with_vars() {
-        let name = Rc::new(Str::from("name"));
+        let name = Sym::from("name");
         let env = Rc::new(Env::new());
-        let captures = Rc::new(Env::new());
 
         let list = Val::list_from_vec(&vec![Val::symbol("+"), Val::symbol("x"), Val::symbol("y")]);
         let expr = Val::list_from_vec(&vec![
@@ -479,38 +539,28 @@ mod test {
             list.clone(),
         ]);
 
-        let a = Rc::new(Str::from("a"));
-        let b = Rc::new(Str::from("b"));
-        let body = Rc::new(Str::from("body"));
+        let a = Sym::from("a");
+        let b = Sym::from("b");
+        let body = Sym::from("body");
         let p = Pattern::List(
             vec![
-                Pattern::Atom(name.clone(), Val::symbol("lambda")),
-                Pattern::List(vec![Pattern::Var(a.clone()), Pattern::Var(b.clone())], None),
-                Pattern::Var(body.clone()),
+                Pattern::Atom(name, Val::symbol("lambda")),
+                Pattern::List(vec![Pattern::Var(a), Pattern::Var(b)], None),
+                Pattern::Var(body),
             ],
             None,
         );
 
-        assert_eq!(
-            p.matches_rec(expr.clone(), env.clone(), captures.clone(), 0),
-            Some(captures.clone())
-        );
-        assert_eq!(
-            captures.lookup(&Rc::new(Str::from("a!!0"))),
-            Some(Val::symbol("x"))
-        );
-        assert_eq!(
-            captures.lookup(&Rc::new(Str::from("b!!0"))),
-            Some(Val::symbol("y"))
-        );
-        assert_eq!(captures.lookup(&Rc::new(Str::from("body!!0"))), Some(list));
+        let captures = p.matches(expr.clone(), env.clone()).unwrap();
+        assert_eq!(captures.get(&a), Some(&Capture::Single(Val::symbol("x"))));
+        assert_eq!(captures.get(&b), Some(&Capture::Single(Val::symbol("y"))));
+        assert_eq!(captures.get(&body), Some(&Capture::Single(list)));
     }
 
     #[test]
     fn test_matching_elipse_list() {
-        let name = Rc::new(Str::from("name"));
+        let name = Sym::from("name");
         let env = Rc::new(Env::new());
-        let captures = Rc::new(Env::new());
 
         let expr = Val::list_from_vec(&vec![
             Val::symbol("name"),
@@ -519,18 +569,12 @@ mod test {
         ]);
         let p = Pattern::List(
             vec![
-                Pattern::Atom(name.clone(), Val::symbol("name")),
-                Pattern::Atom(name.clone(), Val::from(10)),
+                Pattern::Atom(name, Val::symbol("name")),
+                Pattern::Atom(name, Val::from(10)),
             ],
-            Some(Box::new(Pattern::Atom(
-                name.clone(),
-                Val::from("hello, world!"),
-            ))),
-        );
-        assert_eq!(
-            p.matches_rec(expr.clone(), env.clone(), captures.clone(), 0),
-            Some(captures.clone())
+            Some(Box::new(Pattern::Atom(name, Val::from("hello, world!")))),
         );
+        assert_eq!(p.matches(expr.clone(), env.clone()), Some(Captures::new()));
 
         let expr = Val::list_from_vec(&vec![
             Val::symbol("name"),
@@ -540,23 +584,16 @@ mod test {
             Val::from("hello, world!"),
             Val::from("hello, world!"),
         ]);
-        assert_eq!(
-            p.matches_rec(expr.clone(), env.clone(), captures.clone(), 0),
-            Some(captures.clone())
-        );
+        assert_eq!(p.matches(expr.clone(), env.clone()), Some(Captures::new()));
 
         let expr = Val::list_from_vec(&vec![Val::symbol("name"), Val::from(10)]);
-        assert_eq!(
-            p.matches_rec(expr.clone(), env.clone(), captures.clone(), 0),
-            Some(captures.clone())
-        );
+        assert_eq!(p.matches(expr.clone(), env.clone()), Some(Captures::new()));
     }
 
     #[test]
     fn test_matching_elipse_vector() {
-        let name = Rc::new(Str::from("name"));
+        let name = Sym::from("name");
         let env = Rc::new(Env::new());
-        let captures = Rc::new(Env::new());
 
         let expr = Val::from(vec![
             Val::symbol("name"),
@@ -565,18 +602,12 @@ mod test {
         ]);
         let p = Pattern::Vector(
             vec![
-                Pattern::Atom(name.clone(), Val::symbol("name")),
-                Pattern::Atom(name.clone(), Val::from(10)),
+                Pattern::Atom(name, Val::symbol("name")),
+                Pattern::Atom(name, Val::from(10)),
             ],
-            Some(Box::new(Pattern::Atom(
-                name.clone(),
-                Val::from("hello, world!"),
-            ))),
-        );
-        assert_eq!(
-            p.matches_rec(expr.clone(), env.clone(), captures.clone(), 0),
-            Some(captures.clone())
+            Some(Box::new(Pattern::Atom(name, Val::from("hello, world!")))),
         );
+        assert_eq!(p.matches(expr.clone(), env.clone()), Some(Captures::new()));
 
         let expr = Val::from(vec![
             Val::symbol("name"),
@@ -586,23 +617,16 @@ mod test {
             Val::from("hello, world!"),
             Val::from("hello, world!"),
         ]);
-        assert_eq!(
-            p.matches_rec(expr.clone(), env.clone(), captures.clone(), 0),
-            Some(captures.clone())
-        );
+        assert_eq!(p.matches(expr.clone(), env.clone()), Some(Captures::new()));
 
         let expr = Val::from(vec![Val::symbol("name"), Val::from(10)]);
-        assert_eq!(
-            p.matches_rec(expr.clone(), env.clone(), captures.clone(), 0),
-            Some(captures.clone())
-        );
+        assert_eq!(p.matches(expr.clone(), env.clone()), Some(Captures::new()));
     }
 
     #[test]
     fn test_matching_elipse_list_with_var() {
-        let name = Rc::new(Str::from("name"));
+        let name = Sym::from("name");
         let env = Rc::new(Env::new());
-        let captures = Rc::new(Env::new());
 
         let expr = Val::list_from_vec(&vec![
             Val::symbol("name"),
@@ -611,29 +635,127 @@ mod test {
             Val::from(88),
             Val::from(99),
         ]);
-        let var = Rc::new(Str::from("x"));
+        let var = Sym::from("x");
         let p = Pattern::List(
             vec![
-                Pattern::Atom(name.clone(), Val::symbol("name")),
-                Pattern::Atom(name.clone(), Val::from(10)),
+                Pattern::Atom(name, Val::symbol("name")),
+                Pattern::Atom(name, Val::from(10)),
             ],
             Some(Box::new(Pattern::Var(var))),
         );
+        let captures = p.matches(expr.clone(), env.clone()).unwrap();
         assert_eq!(
-            p.matches_rec(expr.clone(), env.clone(), captures.clone(), 0),
-            Some(captures.clone())
+            captures.get(&Sym::from("x")),
+            Some(&Capture::Seq(vec![
+                Capture::Single(Val::from(77)),
+                Capture::Single(Val::from(88)),
+                Capture::Single(Val::from(99)),
+            ]))
         );
-        assert_eq!(
-            captures.lookup(&Rc::new(Str::from("x!!0"))),
-            Some(Val::from(77))
+    }
+
+    #[test]
+    fn test_matching_nested_elipses() {
+        let name = Sym::from("name");
+        let env = Rc::new(Env::new());
+
+        // (name (a b ...) ...)
+        let a = Sym::from("a");
+        let b = Sym::from("b");
+        let p = Pattern::List(
+            vec![Pattern::Atom(name, Val::symbol("name"))],
+            Some(Box::new(Pattern::List(
+                vec![Pattern::Var(a)],
+                Some(Box::new(Pattern::Var(b))),
+            ))),
         );
+
+        // (name (1 2 3) (4))
+        let expr = Val::list_from_vec(&[
+            Val::symbol("name"),
+            Val::list_from_vec(&[Val::from(1), Val::from(2), Val::from(3)]),
+            Val::list_from_vec(&[Val::from(4)]),
+        ]);
+        let captures = p.matches(expr, env.clone()).unwrap();
         assert_eq!(
-            captures.lookup(&Rc::new(Str::from("x!!1"))),
-            Some(Val::from(88))
+            captures.get(&a),
+            Some(&Capture::Seq(vec![
+                Capture::Single(Val::from(1)),
+                Capture::Single(Val::from(4)),
+            ]))
         );
         assert_eq!(
-            captures.lookup(&Rc::new(Str::from("x!!2"))),
-            Some(Val::from(99))
+            captures.get(&b),
+            Some(&Capture::Seq(vec![
+                Capture::Seq(vec![
+                    Capture::Single(Val::from(2)),
+                    Capture::Single(Val::from(3)),
+                ]),
+                Capture::Seq(vec![]),
+            ]))
+        );
+
+        // (name (1 2) 3) does not match as 3 is not a list
+        let expr = Val::list_from_vec(&[
+            Val::symbol("name"),
+            Val::list_from_vec(&[Val::from(1), Val::from(2)]),
+            Val::from(3),
+        ]);
+        assert_eq!(p.matches(expr, env), None);
+    }
+
+    #[test]
+    fn test_matching_maps() {
+        let name = Sym::from("name");
+        let env = Rc::new(Env::new());
+        let x = Sym::from("x");
+        let rest = Sym::from("rest");
+
+        // {:a x}
+        let p = Pattern::Map(vec![(Val::keyword(":a"), Pattern::Var(x))], None);
+        let expr = Val::from(Map::map(&[(Val::keyword(":a"), Val::from(1))]).unwrap());
+        let captures = p.matches(expr, env.clone()).unwrap();
+        assert_eq!(captures.get(&x), Some(&Capture::Single(Val::from(1))));
+
+        // Without a rest the keys must be the same
+        let expr = Val::from(
+            Map::map(&[
+                (Val::keyword(":a"), Val::from(1)),
+                (Val::keyword(":b"), Val::from(2)),
+            ])
+            .unwrap(),
+        );
+        assert_eq!(p.matches(expr.clone(), env.clone()), None);
+        let other = Val::from(Map::map(&[(Val::keyword(":b"), Val::from(1))]).unwrap());
+        assert_eq!(p.matches(other, env.clone()), None);
+        assert_eq!(p.matches(Val::from(1), env.clone()), None);
+
+        // {:a x ... rest} on a dict captures the rest as a dict
+        let p = Pattern::Map(vec![(Val::keyword(":a"), Pattern::Var(x))], Some(rest));
+        let expr = Val::from(
+            Map::dict(&[
+                (Val::keyword(":a"), Val::from(1)),
+                (Val::keyword(":b"), Val::from(2)),
+            ])
+            .unwrap(),
+        );
+        let captures = p.matches(expr, env.clone()).unwrap();
+        assert_eq!(captures.get(&x), Some(&Capture::Single(Val::from(1))));
+        assert_eq!(
+            captures.get(&rest),
+            Some(&Capture::Single(Val::from(
+                Map::dict(&[(Val::keyword(":b"), Val::from(2))]).unwrap()
+            )))
+        );
+
+        // The map pattern is read from a map
+        let expr = Val::from(
+            Map::map(&[
+                (Val::keyword(":a"), Val::symbol("x")),
+                (Val::symbol("..."), Val::symbol("rest")),
+            ])
+            .unwrap(),
         );
+        assert_eq!(Pattern::new(name, &[], expr), Ok(p));
     }
 }
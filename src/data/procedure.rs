@@ -1,4 +1,5 @@
 use crate::data::*;
+use std::cell::OnceCell;
 use std::rc::Rc;
 
 // Procedure ///////////////////////////////////////////////////////////////////////
@@ -131,12 +132,17 @@ impl Eq for Lambda {}
 
 // Closure ////////////////////////////////////////////////////////////////////
 
+// A closure has a clause for each number of arguments it can be applied to,
+// and is applied with the first clause that accepts the arguments. Closures
+// made by the same lambda share its clauses, so a body is only compiled once.
+// Its names are looked up in the slots of the scopes it was made in, and then
+// in the global environment.
 #[derive(Clone, PartialEq)]
 pub struct Closure {
     pub name: Option<Str>,
     pub env: Environ,
-    pub formals: Formals,
-    pub body: Option<Rc<List>>,
+    pub slots: Option<Rc<Slots>>,
+    pub clauses: Rc<[Clause]>,
 }
 
 impl Closure {
@@ -146,29 +152,112 @@ impl Closure {
         formals: Formals,
         body: Option<Rc<List>>,
     ) -> Closure {
+        Closure::with_clauses(name, env, vec![Clause::new(formals, body)])
+    }
+
+    pub fn with_clauses(name: Option<Str>, env: Environ, clauses: Vec<Clause>) -> Closure {
         Closure {
-            name: name,
-            env: env,
-            formals: formals,
-            body: body,
+            name,
+            env,
+            slots: None,
+            clauses: clauses.into(),
         }
     }
 
     pub fn tail_call(env: Environ, body: Val) -> Closure {
-        Closure {
-            name: None,
-            env: env,
-            formals: Formals::Fixed(Vec::new()),
-            body: Some(Rc::new(List::new(body, None))),
+        Closure::new(
+            None,
+            env,
+            Formals::Fixed(Vec::new()),
+            Some(Rc::new(List::new(body, None))),
+        )
+    }
+
+    // The shape of the arguments for each of the closure's clauses
+    pub fn arity(&self) -> Vec<Arity> {
+        self.clauses
+            .iter()
+            .map(|clause| clause.formals.arity())
+            .collect()
+    }
+
+    pub fn clause(&self, args: usize) -> Option<&Clause> {
+        self.clauses
+            .iter()
+            .find(|clause| clause.formals.accepts(args))
+    }
+
+    // The arguments the closure can be applied to, like "2 arguments" or
+    // "1 or at least 3 arguments"
+    pub fn expected_args(&self) -> String {
+        let arity = self.arity();
+        let counts: Vec<String> = arity.iter().map(|a| a.describe()).collect();
+        let noun = match arity.last() {
+            Some(a) if a.most() == 1 && (a.keys.is_empty() && !a.rest || a.required == 1) => {
+                "argument"
+            }
+            _ => "arguments",
+        };
+        match counts.split_last() {
+            Some((last, [])) => format!("{last} {noun}"),
+            Some((last, init)) => format!("{} or {last} {noun}", init.join(", ")),
+            None => format!("0 {noun}"),
         }
     }
+}
+
+// The code for the body is compiled the first time the clause is applied, in
+// a scope for its params inside the scope of the lambda it is from. So is the
+// code for the default of a param, which is kept by the slot of the param.
+#[derive(Debug, Clone)]
+pub struct Clause {
+    pub formals: Formals,
+    pub body: Option<Rc<List>>,
+    pub scope: Option<Rc<Scope>>,
+    code: OnceCell<Rc<Code>>,
+    defaults: Box<[OnceCell<Rc<Code>>]>,
+}
 
-    pub fn arity(&self) -> usize {
-        match &self.formals {
-            Formals::Collect(_) => 0,
-            Formals::Fixed(vec) | Formals::Rest(vec, _) => vec.len(),
+impl Clause {
+    pub fn new(formals: Formals, body: Option<Rc<List>>) -> Clause {
+        let defaults = vec![OnceCell::new(); formals.names().len()];
+        Clause {
+            formals,
+            body,
+            scope: None,
+            code: OnceCell::new(),
+            defaults: defaults.into(),
         }
     }
+
+    pub fn in_scope(self, scope: Option<Rc<Scope>>) -> Clause {
+        Clause { scope, ..self }
+    }
+
+    pub fn code(&self) -> Option<Rc<Code>> {
+        self.code.get().cloned()
+    }
+
+    // Compiling a body can expand a macro that applies the closure, in which
+    // case the body is compiled again and the first code to finish is kept.
+    pub fn set_code(&self, code: Rc<Code>) -> Rc<Code> {
+        self.code.get_or_init(|| code).clone()
+    }
+
+    pub fn default_code(&self, slot: usize) -> Option<Rc<Code>> {
+        self.defaults[slot].get().cloned()
+    }
+
+    pub fn set_default_code(&self, slot: usize, code: Rc<Code>) -> Rc<Code> {
+        self.defaults[slot].get_or_init(|| code).clone()
+    }
+}
+
+// Clauses are the same if they have the same formals and body
+impl PartialEq for Clause {
+    fn eq(&self, other: &Clause) -> bool {
+        self.formals == other.formals && self.body == other.body
+    }
 }
 
 // Closure Traits /////////////////////////////////////////////////////////////
@@ -207,11 +296,288 @@ impl Eq for Closure {}
 
 // Formals ////////////////////////////////////////////////////////////////////
 
+// The params of a closure clause. Optional formals have required params
+// followed by &opt params and then &key params, which are bound to the values
+// after keywords in the arguments that follow the optional ones.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Formals {
-    Collect(Rc<Str>),
-    Fixed(Vec<Rc<Str>>),
-    Rest(Vec<Rc<Str>>, Rc<Str>),
+    Collect(Sym),
+    Fixed(Vec<Param>),
+    Rest(Vec<Param>, Sym),
+    Optional(Vec<Param>, Vec<Opt>, Vec<Key>),
+}
+
+impl Formals {
+    // Formals from a vector of params, with either a . and a symbol that
+    // collects the rest of the arguments, or &opt and &key sections.
+    pub fn from_vector<'a>(
+        vals: impl Iterator<Item = &'a Val>,
+        form: &Val,
+    ) -> Result<Formals, Error> {
+        let vals: Vec<&Val> = vals.collect();
+        let marker = |name: &str| {
+            vals.iter()
+                .position(|val| matches!(val, Val::Symbol(s) if s.is(name)))
+        };
+        let (opt, key) = (marker("&opt"), marker("&key"));
+        let required = opt.or(key).unwrap_or(vals.len());
+        let (params, rest) = Param::sequence(vals[..required].iter().cloned(), form)?;
+        match (opt, key, rest) {
+            (None, None, Some(rest)) => return Ok(Formals::Rest(params, rest)),
+            (None, None, None) => return Ok(Formals::Fixed(params)),
+            (Some(o), Some(k), _) if k < o => return Err(Error::BadParam(form.clone())),
+            (_, _, Some(_)) => return Err(Error::BadParam(form.clone())),
+            _ => (),
+        }
+
+        let opts = match opt {
+            Some(o) => vals[o + 1..key.unwrap_or(vals.len())]
+                .iter()
+                .map(|val| {
+                    let (name, default) = optional_param(val, form)?;
+                    Ok(Opt { name, default })
+                })
+                .collect::<Result<Vec<Opt>, Error>>()?,
+            None => vec![],
+        };
+        let keys = match key {
+            Some(k) => vals[k + 1..]
+                .iter()
+                .map(|val| {
+                    let (name, default) = optional_param(val, form)?;
+                    Ok(Key::new(name, default))
+                })
+                .collect::<Result<Vec<Key>, Error>>()?,
+            None => vec![],
+        };
+        Ok(Formals::Optional(params, opts, keys))
+    }
+
+    pub fn arity(&self) -> Arity {
+        match self {
+            Formals::Collect(_) => Arity {
+                required: 0,
+                optional: 0,
+                rest: true,
+                keys: vec![],
+            },
+            Formals::Fixed(params) | Formals::Rest(params, _) => Arity {
+                required: params.len(),
+                optional: 0,
+                rest: matches!(self, Formals::Rest(..)),
+                keys: vec![],
+            },
+            Formals::Optional(params, opts, keys) => Arity {
+                required: params.len(),
+                optional: opts.len(),
+                rest: false,
+                keys: keys.iter().map(|key| key.key.clone()).collect(),
+            },
+        }
+    }
+
+    // Whether the formals can be bound to a number of arguments. Whether the
+    // arguments for &key params are keywords is checked when they are bound.
+    pub fn accepts(&self, args: usize) -> bool {
+        match self {
+            Formals::Collect(_) => true,
+            Formals::Fixed(params) => args == params.len(),
+            Formals::Rest(params, _) => args >= params.len(),
+            Formals::Optional(params, opts, keys) => {
+                args >= params.len() && (!keys.is_empty() || args <= params.len() + opts.len())
+            }
+        }
+    }
+
+    // The names the formals bind, in the order they are bound
+    pub fn names(&self) -> Vec<Sym> {
+        let mut names = Vec::new();
+        match self {
+            Formals::Collect(name) => names.push(*name),
+            Formals::Fixed(params) => params.iter().for_each(|p| p.add_names(&mut names)),
+            Formals::Rest(params, rest) => {
+                params.iter().for_each(|p| p.add_names(&mut names));
+                names.push(*rest);
+            }
+            Formals::Optional(params, opts, keys) => {
+                params.iter().for_each(|p| p.add_names(&mut names));
+                names.extend(opts.iter().map(|opt| opt.name));
+                names.extend(keys.iter().map(|key| key.name));
+            }
+        }
+        names
+    }
+}
+
+// An &opt param is a symbol or (symbol default)
+fn optional_param(val: &Val, form: &Val) -> Result<(Sym, Option<Val>), Error> {
+    match val {
+        Val::Symbol(s) if !s.is("&opt") && !s.is("&key") && !s.is(".") => Ok((*s, None)),
+        Val::List(ls) if ls.len() == 2 => match (ls.head(), ls.second()) {
+            (Val::Symbol(s), default) => Ok((*s, default)),
+            _ => Err(Error::BadParam(form.clone())),
+        },
+        _ => Err(Error::BadParam(form.clone())),
+    }
+}
+
+// An &opt param with a form for its default value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Opt {
+    pub name: Sym,
+    pub default: Option<Val>,
+}
+
+// Arity //////////////////////////////////////////////////////////////////////
+
+// The shape of the arguments that formals can be bound to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arity {
+    pub required: usize,
+    pub optional: usize,
+    pub rest: bool,
+    pub keys: Vec<Val>,
+}
+
+impl Arity {
+    pub fn accepts(&self, args: usize) -> bool {
+        args >= self.required
+            && (self.rest || !self.keys.is_empty() || args <= self.required + self.optional)
+    }
+
+    // The most arguments that can be given without a rest or keywords
+    fn most(&self) -> usize {
+        self.required + self.optional
+    }
+
+    // Like "2", "1 to 3" or "at least 1"
+    fn describe(&self) -> String {
+        let open = self.rest || !self.keys.is_empty();
+        match (open, self.optional) {
+            (true, _) if self.required == 0 => "any number of".to_string(),
+            (true, _) => format!("at least {}", self.required),
+            (false, 0) => self.required.to_string(),
+            (false, _) => format!("{} to {}", self.required, self.most()),
+        }
+    }
+}
+
+// Param //////////////////////////////////////////////////////////////////////
+
+// A parameter is a symbol or a pattern that destructures its argument. A
+// vector [a b . rest] matches a list, vector or tuple with a value for each
+// param, and a map {:keys [a b] :or {b 2} :as m} binds the values of the
+// keywords :a and :b in a map. The patterns keep their form for errors.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    Symbol(Sym),
+    Seq(Vec<Param>, Option<Sym>, Val),
+    Keys(Vec<Key>, Option<Sym>, Val),
+}
+
+// A name bound by a keys pattern or an &key param, with the keyword it is the
+// value of and a form for its default value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Key {
+    pub name: Sym,
+    pub key: Val,
+    pub default: Option<Val>,
+}
+
+impl Key {
+    // A symbol renamed by a macro expansion is still bound to the keyword for
+    // the symbol it was renamed from.
+    pub fn new(name: Sym, default: Option<Val>) -> Key {
+        let key = match name.renamed_from() {
+            Some(prefix) => Val::keyword(&prefix.to_string()),
+            None => Val::keyword(&name.to_string()),
+        };
+        Key { name, key, default }
+    }
+}
+
+impl Param {
+    pub fn new(form: &Val) -> Result<Param, Error> {
+        match form {
+            Val::Symbol(s) if !s.is(".") => Ok(Param::Symbol(*s)),
+            Val::Vector(vec) => {
+                let (params, rest) = Param::sequence(vec.borrow().values(), form)?;
+                Ok(Param::Seq(params, rest, form.clone()))
+            }
+            Val::Map(map) => Param::keys(&map.borrow(), form),
+            _ => Err(Error::BadParam(form.clone())),
+        }
+    }
+
+    // The params in a vector of params, and the symbol after a . that collects
+    // the rest of the values
+    pub fn sequence<'a>(
+        mut vals: impl Iterator<Item = &'a Val>,
+        form: &Val,
+    ) -> Result<(Vec<Param>, Option<Sym>), Error> {
+        let mut params = Vec::new();
+        while let Some(val) = vals.next() {
+            match val {
+                Val::Symbol(s) if s.is(".") => {
+                    return match (vals.next(), vals.next()) {
+                        (Some(Val::Symbol(rest)), None) => Ok((params, Some(*rest))),
+                        _ => Err(Error::BadParam(form.clone())),
+                    }
+                }
+                _ => params.push(Param::new(val)?),
+            }
+        }
+        Ok((params, None))
+    }
+
+    // Add the names the param binds, in the order they are bound
+    fn add_names(&self, names: &mut Vec<Sym>) {
+        match self {
+            Param::Symbol(name) => names.push(*name),
+            Param::Seq(params, rest, _) => {
+                params.iter().for_each(|p| p.add_names(names));
+                names.extend(rest.iter().cloned());
+            }
+            Param::Keys(keys, name_as, _) => {
+                names.extend(keys.iter().map(|key| key.name));
+                names.extend(name_as.iter().cloned());
+            }
+        }
+    }
+
+    fn keys(map: &Map, form: &Val) -> Result<Param, Error> {
+        let mut names = Vec::new();
+        let mut defaults = Map::new();
+        let mut name_as = None;
+        for (key, val) in map.entries() {
+            match (key, val) {
+                (Val::Keyword(k), Val::Vector(vec)) if k.is(":keys") => {
+                    for name in vec.borrow().values() {
+                        match name {
+                            Val::Symbol(s) => names.push(*s),
+                            _ => return Err(Error::BadParam(form.clone())),
+                        }
+                    }
+                }
+                (Val::Keyword(k), Val::Map(m)) if k.is(":or") => defaults = m.borrow().clone(),
+                (Val::Keyword(k), Val::Symbol(s)) if k.is(":as") => name_as = Some(*s),
+                _ => return Err(Error::BadParam(form.clone())),
+            }
+        }
+
+        let mut keys = Vec::new();
+        for name in names {
+            let default = defaults.get(Val::Symbol(name));
+            keys.push(Key::new(name, default));
+        }
+        // every default must be for one of the keys
+        for (name, _) in defaults.entries() {
+            if !keys.iter().any(|k| Val::Symbol(k.name) == *name) {
+                return Err(Error::BadParam(form.clone()));
+            }
+        }
+        Ok(Param::Keys(keys, name_as, form.clone()))
+    }
 }
 
 // Tail Call //////////////////////////////////////////////////////////////////
@@ -255,3 +621,185 @@ impl std::fmt::Debug for TailCall {
 }
 
 impl Eq for TailCall {}
+
+// Testing ////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sym(s: &str) -> Sym {
+        Sym::from(s)
+    }
+
+    #[test]
+    fn test_creating_params() {
+        assert_eq!(Param::new(&Val::symbol("a")), Ok(Param::Symbol(sym("a"))));
+
+        // [a [b] . c]
+        let form = Val::from(vec![
+            Val::symbol("a"),
+            Val::from(vec![Val::symbol("b")]),
+            Val::symbol("."),
+            Val::symbol("c"),
+        ]);
+        let inner = Val::from(vec![Val::symbol("b")]);
+        assert_eq!(
+            Param::new(&form),
+            Ok(Param::Seq(
+                vec![
+                    Param::Symbol(sym("a")),
+                    Param::Seq(vec![Param::Symbol(sym("b"))], None, inner)
+                ],
+                Some(sym("c")),
+                form.clone()
+            ))
+        );
+
+        // {:keys [x y] :or {y 2}}
+        let mut defaults = Map::new();
+        defaults.assoc(Val::symbol("y"), Val::from(2)).unwrap();
+        let mut map = Map::new();
+        map.assoc(
+            Val::keyword("keys"),
+            Val::from(vec![Val::symbol("x"), Val::symbol("y")]),
+        )
+        .unwrap();
+        map.assoc(Val::keyword("or"), Val::from(defaults)).unwrap();
+        let form = Val::from(map);
+        assert_eq!(
+            Param::new(&form),
+            Ok(Param::Keys(
+                vec![
+                    Key {
+                        name: sym("x"),
+                        key: Val::keyword("x"),
+                        default: None
+                    },
+                    Key {
+                        name: sym("y"),
+                        key: Val::keyword("y"),
+                        default: Some(Val::from(2))
+                    },
+                ],
+                None,
+                form.clone()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_creating_optional_formals() {
+        // [a &opt (b 2) &key c]
+        let vals = vec![
+            Val::symbol("a"),
+            Val::symbol("&opt"),
+            Val::list_from_vec(&[Val::symbol("b"), Val::from(2)]),
+            Val::symbol("&key"),
+            Val::symbol("c"),
+        ];
+        let form = Val::from(vals.clone());
+        let formals = Formals::from_vector(vals.iter(), &form).unwrap();
+        assert_eq!(
+            formals,
+            Formals::Optional(
+                vec![Param::Symbol(sym("a"))],
+                vec![Opt {
+                    name: sym("b"),
+                    default: Some(Val::from(2))
+                }],
+                vec![Key::new(sym("c"), None)]
+            )
+        );
+        assert_eq!(
+            formals.arity(),
+            Arity {
+                required: 1,
+                optional: 1,
+                rest: false,
+                keys: vec![Val::keyword("c")]
+            }
+        );
+        assert!(!formals.accepts(0));
+        assert!(formals.accepts(4));
+
+        // [&key a &opt b]
+        let vals = vec![
+            Val::symbol("&key"),
+            Val::symbol("a"),
+            Val::symbol("&opt"),
+            Val::symbol("b"),
+        ];
+        let form = Val::from(vals.clone());
+        assert_eq!(
+            Formals::from_vector(vals.iter(), &form),
+            Err(Error::BadParam(form.clone()))
+        );
+    }
+
+    #[test]
+    fn test_expected_args() {
+        let env = Env::add_scope(Rc::new(Env::new()));
+        let fixed = |n: usize| Clause::new(Formals::Fixed(vec![Param::Symbol(sym("x")); n]), None);
+        let optional = Clause::new(
+            Formals::Optional(
+                vec![Param::Symbol(sym("x"))],
+                vec![Opt {
+                    name: sym("y"),
+                    default: None,
+                }],
+                vec![],
+            ),
+            None,
+        );
+        let closure = |clauses| Closure::with_clauses(None, env.clone(), clauses);
+        assert_eq!(closure(vec![fixed(1)]).expected_args(), "1 argument");
+        assert_eq!(closure(vec![fixed(2)]).expected_args(), "2 arguments");
+        assert_eq!(closure(vec![optional]).expected_args(), "1 to 2 arguments");
+        assert_eq!(
+            closure(vec![fixed(0), fixed(2), fixed(3)]).expected_args(),
+            "0, 2 or 3 arguments"
+        );
+    }
+
+    #[test]
+    fn test_formals_accept_what_their_arity_accepts() {
+        let params = |n: usize| vec![Param::Symbol(sym("x")); n];
+        let opt = Opt {
+            name: sym("y"),
+            default: None,
+        };
+        let all = [
+            Formals::Collect(sym("xs")),
+            Formals::Fixed(params(0)),
+            Formals::Fixed(params(2)),
+            Formals::Rest(params(1), sym("xs")),
+            Formals::Optional(params(1), vec![opt.clone()], vec![]),
+            Formals::Optional(params(1), vec![opt], vec![Key::new(sym("z"), None)]),
+        ];
+        for formals in all.iter() {
+            for args in 0..5 {
+                assert_eq!(
+                    formals.accepts(args),
+                    formals.arity().accepts(args),
+                    "{formals:?} {args}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_creating_bad_params() {
+        assert_eq!(
+            Param::new(&Val::from(5)),
+            Err(Error::BadParam(Val::from(5)))
+        );
+        let form = Val::from(vec![Val::symbol("."), Val::symbol("a"), Val::symbol("b")]);
+        assert_eq!(Param::new(&form), Err(Error::BadParam(form.clone())));
+        let mut map = Map::new();
+        map.assoc(Val::keyword("keys"), Val::from(vec![Val::from(1)]))
+            .unwrap();
+        let form = Val::from(map);
+        assert_eq!(Param::new(&form), Err(Error::BadParam(form.clone())));
+    }
+}
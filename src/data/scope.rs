@@ -0,0 +1,151 @@
+use crate::data::{Sym, Val};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Scope //////////////////////////////////////////////////////////////////////
+
+// The names bound by a scope of compiled code, like the params of a closure
+// clause or the vars of a loop, in the order of the slots that hold their
+// values. Code looks up a local name by how many scopes out it is bound and
+// its slot there. Only names that are not bound by any scope are looked up by
+// name, in the global environment.
+#[derive(Debug, Default)]
+pub struct Scope {
+    pub names: Vec<Sym>,
+    pub outer: Option<Rc<Scope>>,
+    // Whether a recur can go back to the start of the scope
+    pub is_loop: bool,
+}
+
+impl Scope {
+    pub fn new(names: Vec<Sym>, outer: Option<Rc<Scope>>) -> Scope {
+        Scope {
+            names,
+            outer,
+            is_loop: false,
+        }
+    }
+
+    // The depth and slot of the binding for a name. When a scope binds a
+    // name more than once the last binding is used.
+    pub fn address(&self, name: Sym) -> Option<(usize, usize)> {
+        let mut scope = self;
+        let mut depth = 0;
+        loop {
+            if let Some(slot) = scope.names.iter().rposition(|n| *n == name) {
+                return Some((depth, slot));
+            }
+            scope = scope.outer.as_deref()?;
+            depth += 1;
+        }
+    }
+
+    // Whether this scope or one it is inside of is a loop
+    pub fn in_loop(&self) -> bool {
+        self.is_loop || self.outer.as_ref().is_some_and(|outer| outer.in_loop())
+    }
+
+    // All of the names bound by this scope and those it is inside of
+    pub fn all_names(&self) -> Vec<Sym> {
+        let mut names = self.names.clone();
+        if let Some(outer) = &self.outer {
+            names.extend(outer.all_names());
+        }
+        names
+    }
+}
+
+// Slots //////////////////////////////////////////////////////////////////////
+
+// The values bound in a scope of running code, in the slots the compiler gave
+// their names, along with the slots of the scope it is inside of.
+#[derive(Debug, Default)]
+pub struct Slots {
+    vals: RefCell<Vec<Val>>,
+    outer: Option<Rc<Slots>>,
+}
+
+impl Slots {
+    pub fn new(vals: Vec<Val>, outer: Option<Rc<Slots>>) -> Slots {
+        Slots {
+            vals: RefCell::new(vals),
+            outer,
+        }
+    }
+
+    pub fn get(&self, depth: usize, slot: usize) -> Val {
+        self.at(depth).vals.borrow()[slot].clone()
+    }
+
+    pub fn set(&self, depth: usize, slot: usize, val: Val) {
+        self.at(depth).vals.borrow_mut()[slot] = val;
+    }
+
+    // Add a slot to the end of this scope
+    pub fn push(&self, val: Val) {
+        self.vals.borrow_mut().push(val);
+    }
+
+    pub fn len(&self) -> usize {
+        self.vals.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vals.borrow().is_empty()
+    }
+
+    pub fn outer(&self) -> Option<Rc<Slots>> {
+        self.outer.clone()
+    }
+
+    fn at(&self, depth: usize) -> &Slots {
+        let mut slots = self;
+        for _ in 0..depth {
+            slots = slots.outer.as_deref().expect("scope should be that deep");
+        }
+        slots
+    }
+}
+
+// Slots are only the same if they are the same slots, as their values can
+// hold closures over the slots themselves.
+impl PartialEq for Slots {
+    fn eq(&self, other: &Slots) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+// Tests //////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<Sym> {
+        names.iter().map(|n| Sym::from(*n)).collect()
+    }
+
+    #[test]
+    fn test_scope_addresses() {
+        let outer = Rc::new(Scope::new(names(&["a", "b"]), None));
+        let scope = Scope::new(names(&["c", "a", "c"]), Some(outer));
+        assert_eq!(scope.address(Sym::from("a")), Some((0, 1)));
+        assert_eq!(scope.address(Sym::from("b")), Some((1, 1)));
+        assert_eq!(scope.address(Sym::from("c")), Some((0, 2)));
+        assert_eq!(scope.address(Sym::from("d")), None);
+    }
+
+    #[test]
+    fn test_slots() {
+        let outer = Rc::new(Slots::new(vec![Val::from(1), Val::from(2)], None));
+        let slots = Slots::new(vec![Val::from(3)], Some(outer.clone()));
+        slots.push(Val::Undefined);
+        slots.set(0, 1, Val::from(4));
+        slots.set(1, 0, Val::from(5));
+        assert_eq!(slots.get(0, 0), Val::from(3));
+        assert_eq!(slots.get(0, 1), Val::from(4));
+        assert_eq!(slots.get(1, 1), Val::from(2));
+        assert_eq!(outer.get(0, 0), Val::from(5));
+        assert_eq!(slots.len(), 2);
+    }
+}
@@ -0,0 +1,76 @@
+use crate::data::Val;
+use std::rc::Rc;
+
+// Span ///////////////////////////////////////////////////////////////////////
+
+// The place in the source text where a form was read. The source is the name
+// of the file the text came from, if there was one. Lines and columns both
+// start at 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub source: Option<Rc<str>>,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    pub fn new(source: Option<Rc<str>>, line: usize, col: usize) -> Span {
+        Span { source, line, col }
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{}:{}:{}", source, self.line, self.col),
+            None => write!(f, "{}:{}", self.line, self.col),
+        }
+    }
+}
+
+// Element Spans //
+
+// The spans of the elements of a vector or map read from source, in the order
+// they were read. Like the span of a list, they are only used to locate errors
+// and are not part of the value.
+#[derive(Debug, Clone, Default)]
+pub struct ElementSpans(Option<Rc<[Element]>>);
+
+type Element = (Val, Rc<Span>);
+
+impl ElementSpans {
+    pub fn new(spans: Vec<Element>) -> ElementSpans {
+        ElementSpans(Some(spans.into()))
+    }
+
+    // The span of the first element read that is equal to a value
+    pub fn find(&self, val: &Val) -> Option<Rc<Span>> {
+        let spans = self.0.as_ref()?;
+        spans
+            .iter()
+            .find(|(v, _)| v == val)
+            .map(|(_, span)| span.clone())
+    }
+}
+
+impl PartialEq for ElementSpans {
+    fn eq(&self, _other: &ElementSpans) -> bool {
+        true
+    }
+}
+
+impl Eq for ElementSpans {}
+
+// Testing ////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_span_display() {
+        let span = Span::new(Some(Rc::from("file.rusp")), 42, 7);
+        assert_eq!(span.to_string(), "file.rusp:42:7");
+        assert_eq!(Span::new(None, 3, 1).to_string(), "3:1");
+    }
+}
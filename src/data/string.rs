@@ -1,110 +1,117 @@
 use crate::data::{Char, DisplayRep, ExternalRep};
-use std::hash::{Hash, Hasher};
 
 // String /////////////////////////////////////////////////////////////////////
 
-#[derive(Clone, PartialEq, Eq)]
+// Strings are utf-8, but like scheme strings they are indexed by char. The
+// number of chars is kept so that it does not have to be counted, and a string
+// of only ascii chars, which are a byte each, is indexed by byte.
+#[derive(Clone, PartialEq, Eq, Hash, Default)]
 pub struct Str {
-    chars: Vec<Char>,
+    text: String,
+    len: usize,
 }
 
 impl Str {
     pub fn new(ch: Char, size: usize) -> Str {
-        let mut chars = Vec::with_capacity(size);
-        for _ in 0..size {
-            chars.push(ch.clone());
+        Str {
+            text: std::iter::repeat_n(ch.to_char(), size).collect(),
+            len: size,
         }
-        Str { chars: chars }
     }
 
     pub fn fill(&mut self, ch: Char) {
-        for c in self.chars.iter_mut() {
-            *c = ch.clone();
-        }
+        *self = Str::new(ch, self.len);
     }
 
     // Access //
 
-    pub fn get(&self, idx: usize) -> Option<&Char> {
-        if idx < self.chars.len() {
-            Some(&self.chars[idx])
-        } else {
-            None
-        }
+    pub fn get(&self, idx: usize) -> Option<Char> {
+        let start = self.byte_idx(idx)?;
+        self.text[start..].chars().next().map(Char::from)
     }
 
     pub fn set(&mut self, ch: Char, idx: usize) -> Option<Char> {
-        if idx < self.len() {
-            let c = self.chars[idx];
-            self.chars[idx] = ch.clone();
-            Some(c)
-        } else {
-            None
-        }
+        let start = self.byte_idx(idx)?;
+        let old = self.text[start..].chars().next()?;
+        let mut buf = [0; 4];
+        let new = ch.to_char().encode_utf8(&mut buf);
+        self.text.replace_range(start..start + old.len_utf8(), new);
+        Some(Char::from(old))
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = Char> + '_ {
+        self.text.chars().map(Char::from)
     }
 
-    pub fn chars(&self) -> std::slice::Iter<'_, Char> {
-        self.chars.iter()
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    // The byte the char at an index starts at, or the end of the text for the
+    // index after the last char
+    fn byte_idx(&self, idx: usize) -> Option<usize> {
+        if idx == self.len {
+            Some(self.text.len())
+        } else if idx > self.len {
+            None
+        } else if self.text.len() == self.len {
+            Some(idx)
+        } else {
+            self.text.char_indices().nth(idx).map(|(i, _)| i)
+        }
     }
 
     // Information //
 
     pub fn len(&self) -> usize {
-        self.chars.len()
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
     }
 
     pub fn is(&self, other: &str) -> bool {
-        // TODO make this more efficient?
-        self == &Str::from(other)
+        self.text == other
     }
 
     // Functions //
 
     pub fn append(&self, other: Str) -> Str {
-        let mut chars = Vec::with_capacity(self.len() + other.len());
-        for ch in self.chars().chain(other.chars()) {
-            chars.push(ch.clone())
+        Str {
+            text: self.text.clone() + &other.text,
+            len: self.len + other.len,
         }
-        Str::from(chars)
     }
 
     pub fn substring(&self, start: usize, end: usize) -> Str {
+        let end = end.min(self.len);
         if start >= end {
             return Str::default();
         }
-        let last = match end {
-            i if i >= self.len() => self.len(),
-            i => i,
-        };
-
-        let mut chars = Vec::with_capacity(end - start);
-        for i in start..last {
-            chars.push(self.chars[i].clone());
+        match (self.byte_idx(start), self.byte_idx(end)) {
+            (Some(from), Some(to)) => Str {
+                text: self.text[from..to].to_string(),
+                len: end - start,
+            },
+            _ => Str::default(),
         }
-        Str::from(chars)
     }
 }
 
 // Traits /////////////////////////////////////////////////////////////////////
 
-impl Default for Str {
-    fn default() -> Str {
-        Str::from("")
-    }
-}
-
 impl From<&str> for Str {
     fn from(s: &str) -> Str {
-        Str {
-            chars: s.chars().map(|ch| Char::from(ch)).collect(),
-        }
+        Str::from(s.to_string())
     }
 }
 
-impl From<&[u8]> for Str {
-    fn from(bytes: &[u8]) -> Str {
+impl From<String> for Str {
+    fn from(text: String) -> Str {
         Str {
-            chars: bytes.iter().map(|b| Char::from(*b)).collect(),
+            len: text.chars().count(),
+            text,
         }
     }
 }
@@ -112,55 +119,38 @@ impl From<&[u8]> for Str {
 impl From<Vec<Char>> for Str {
     fn from(chars: Vec<Char>) -> Str {
         Str {
-            chars: chars.iter().map(|ch| ch.clone()).collect(),
+            text: chars.iter().map(|ch| ch.to_char()).collect(),
+            len: chars.len(),
         }
     }
 }
 
-// Hashing //
-
-impl Hash for Str {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.chars.iter().for_each(|ch| ch.to_byte().hash(state));
-    }
-}
-
 // Representation //
 
 impl DisplayRep for Str {
     fn to_display(&self) -> String {
-        let mut strings = vec![];
-        for ch in self.chars.iter() {
-            strings.push(match ch.clone() {
-                Char::Null => "\0".to_owned(),
-                Char::Tab => "\t".to_owned(),
-                Char::LineFeed => "\n".to_owned(),
-                Char::Space => " ".to_owned(),
-                Char::Unsupported => "UNSUP".to_owned(),
-                _ => ch.to_display(),
-            })
-        }
-        strings.join("")
+        self.text.clone()
     }
 }
 
+// Chars are written the way the scanner reads them in a string literal
 impl ExternalRep for Str {
     fn to_external(&self) -> String {
-        let quote = '"' as u8;
-        let mut strings = vec!["\"".to_owned()];
-        for ch in self.chars.iter() {
-            strings.push(match ch.clone() {
-                Char::Null => "\\0".to_owned(),
-                Char::Tab => "\\t".to_owned(),
-                Char::LineFeed => "\\n".to_owned(),
-                Char::Space => " ".to_owned(),
-                Char::Unsupported => "UNSUP".to_owned(),
-                Char::Char(ch) if ch == quote => "\\\"".to_owned(),
-                _ => ch.to_display(),
-            })
+        let mut external = String::with_capacity(self.text.len() + 2);
+        external.push('"');
+        for ch in self.text.chars() {
+            match ch {
+                '\0' => external.push_str("\\0"),
+                '\t' => external.push_str("\\t"),
+                '\n' => external.push_str("\\n"),
+                '\\' => external.push_str("\\\\"),
+                '"' => external.push_str("\\\""),
+                ch if ch.is_control() => external.push_str(&format!("\\u{{{:x}}}", ch as u32)),
+                ch => external.push(ch),
+            }
         }
-        strings.push("\"".to_owned());
-        strings.join("")
+        external.push('"');
+        external
     }
 }
 
@@ -178,7 +168,6 @@ impl std::fmt::Debug for Str {
 
 // Testing ////////////////////////////////////////////////////////////////////
 
-/*
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -192,11 +181,15 @@ mod tests {
         assert_eq!(string.get(3), Some(Char::from('a')));
         assert_eq!(string.get(4), Some(Char::from('a')));
         assert!(string.get(5).is_none());
+
+        let string = Str::new(Char::from('λ'), 2);
+        assert_eq!(string.to_display(), "λλ");
+        assert_eq!(string.len(), 2);
     }
 
     #[test]
     fn test_string_fill() {
-        let string = Str::new(Char::from('a'), 5);
+        let mut string = Str::new(Char::from('a'), 5);
         string.fill(Char::from('p'));
         assert_eq!(string.get(0), Some(Char::from('p')));
         assert_eq!(string.get(1), Some(Char::from('p')));
@@ -204,17 +197,23 @@ mod tests {
         assert_eq!(string.get(3), Some(Char::from('p')));
         assert_eq!(string.get(4), Some(Char::from('p')));
         assert!(string.get(5).is_none());
+
+        string.fill(Char::from('ж'));
+        assert_eq!(string.to_display(), "жжжжж");
     }
 
     #[test]
     fn test_string_length() {
         let string = Str::new(Char::from('a'), 5);
         assert_eq!(string.len(), 5);
+        assert_eq!(Str::from("héllo wörld").len(), 11);
+        assert_eq!(Str::from("😀").len(), 1);
+        assert!(Str::from("").is_empty());
     }
 
     #[test]
     fn test_string_set() {
-        let string = Str::from(vec![Char::from('a'); 3]);
+        let mut string = Str::from(vec![Char::from('a'); 3]);
         assert_eq!(string.set(Char::from('d'), 0), Some(Char::from('a')));
         assert_eq!(string.set(Char::from('e'), 2), Some(Char::from('a')));
         assert!(string.set(Char::from('l'), 3).is_none());
@@ -224,12 +223,26 @@ mod tests {
         assert!(string.get(3).is_none());
     }
 
+    #[test]
+    fn test_string_unicode_access() {
+        let mut string = Str::from("añb中c");
+        assert_eq!(string.get(1), Some(Char::from('ñ')));
+        assert_eq!(string.get(3), Some(Char::from('中')));
+        assert_eq!(string.get(4), Some(Char::from('c')));
+        assert!(string.get(5).is_none());
+
+        assert_eq!(string.set(Char::from('x'), 1), Some(Char::from('ñ')));
+        assert_eq!(string.set(Char::from('😀'), 2), Some(Char::from('b')));
+        assert_eq!(string.to_display(), "ax😀中c");
+        assert_eq!(string.len(), 5);
+    }
+
     #[test]
     fn test_string_iter() {
-        let string = Str::from("hello");
+        let string = Str::from("héllo");
         let mut iter = string.chars();
         assert_eq!(iter.next(), Some(Char::from('h')));
-        assert_eq!(iter.next(), Some(Char::from('e')));
+        assert_eq!(iter.next(), Some(Char::from('é')));
         assert_eq!(iter.next(), Some(Char::from('l')));
         assert_eq!(iter.next(), Some(Char::from('l')));
         assert_eq!(iter.next(), Some(Char::from('o')));
@@ -241,14 +254,18 @@ mod tests {
         let string = Str::from("hello-world\n");
         assert_eq!(string.to_display(), "hello-world\n");
         assert_eq!(string.to_external(), "\"hello-world\\n\"");
+
+        let string = Str::from("λ \"x\" \\ \x02");
+        assert_eq!(string.to_display(), "λ \"x\" \\ \x02");
+        assert_eq!(string.to_external(), "\"λ \\\"x\\\" \\\\ \\u{2}\"");
     }
 
     #[test]
     fn test_string_append() {
         let string = Str::from("hello, ");
-        let string2 = Str::from("world!");
+        let string2 = Str::from("wörld!");
         let string3 = string.append(string2);
-        assert_eq!(string3.to_display(), "hello, world!");
+        assert_eq!(string3.to_display(), "hello, wörld!");
         assert_eq!(string3.len(), 13);
     }
 
@@ -259,6 +276,10 @@ mod tests {
         assert_eq!(string.substring(0, 10).to_display(), "hello");
         assert_eq!(string.substring(2, 4).to_display(), "ll");
         assert_eq!(string.substring(4, 1).to_display(), "");
+
+        let string = Str::from("αβγδε");
+        assert_eq!(string.substring(1, 3).to_display(), "βγ");
+        assert_eq!(string.substring(3, 10).to_display(), "δε");
+        assert_eq!(string.substring(1, 3).len(), 2);
     }
 }
-*/
@@ -0,0 +1,207 @@
+use crate::data::{Str, SPECIAL_FORMS};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Symbol /////////////////////////////////////////////////////////////////////
+
+// The name of a symbol or keyword. Names are interned, so each one has a single
+// id that it is compared and hashed by, and the name itself is only needed to
+// print it. Interned names are never freed, the same as in most lisps.
+//
+// Symbols made by gensym, and by macro templates for hygiene, are not interned
+// so that making them at runtime does not grow the interner. They keep the id
+// of the name they were made from and add a count no other symbol has.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Sym {
+    id: u32,
+    count: u64,
+    renamed: bool,
+}
+
+impl Sym {
+    pub fn new(name: Str) -> Sym {
+        Sym::named(INTERNER.with(|interner| interner.borrow_mut().intern(name)))
+    }
+
+    fn named(id: u32) -> Sym {
+        Sym {
+            id,
+            count: 0,
+            renamed: false,
+        }
+    }
+
+    // Made symbols are named like prefix#12. The # cannot be read as part of a
+    // symbol, so the name can never be written in a program to capture them.
+    pub fn name(&self) -> Rc<Str> {
+        let name = INTERNER.with(|interner| interner.borrow().names[self.id as usize].clone());
+        match self.count {
+            0 => name,
+            count => Rc::new(Str::from(format!("{name}#{count}"))),
+        }
+    }
+
+    pub fn is(&self, name: &str) -> bool {
+        self.count == 0 && self.name().is(name)
+    }
+
+    // The special forms are interned before any other name, so they are the
+    // lowest ids.
+    pub fn is_special_form(&self) -> bool {
+        self.count == 0 && (self.id as usize) < SPECIAL_FORMS.len()
+    }
+
+    // A new symbol that is not equal to any other symbol
+    pub fn gensym(prefix: &str) -> Sym {
+        Sym {
+            count: next_count(),
+            ..Sym::from(prefix)
+        }
+    }
+
+    // A new symbol like gensym that a macro template puts in place of a
+    // symbol, which remembers the symbol it was renamed from. Renaming a
+    // renamed symbol gives a copy of the original symbol, and renaming a gensym
+    // gives another gensym.
+    pub fn rename(sym: Sym) -> Sym {
+        Sym {
+            id: sym.id,
+            count: next_count(),
+            renamed: sym.renamed || sym.count == 0,
+        }
+    }
+
+    // The symbol a symbol made by rename was renamed from
+    pub fn renamed_from(&self) -> Option<Sym> {
+        match self.renamed {
+            true => Some(Sym::named(self.id)),
+            false => None,
+        }
+    }
+}
+
+fn next_count() -> u64 {
+    COUNT.with(|count| {
+        count.set(count.get() + 1);
+        count.get()
+    })
+}
+
+// Interner //
+
+struct Interner {
+    ids: HashMap<Rc<Str>, u32>,
+    names: Vec<Rc<Str>>,
+}
+
+impl Interner {
+    fn new() -> Interner {
+        let mut interner = Interner {
+            ids: HashMap::new(),
+            names: Vec::new(),
+        };
+        for name in SPECIAL_FORMS.iter() {
+            interner.intern(Str::from(*name));
+        }
+        interner
+    }
+
+    fn intern(&mut self, name: Str) -> u32 {
+        if let Some(id) = self.ids.get(&name) {
+            return *id;
+        }
+        let id = self.names.len() as u32;
+        let name = Rc::new(name);
+        self.names.push(name.clone());
+        self.ids.insert(name, id);
+        id
+    }
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::new());
+    static COUNT: Cell<u64> = const { Cell::new(0) };
+}
+
+// Traits /////////////////////////////////////////////////////////////////////
+
+impl From<&str> for Sym {
+    fn from(name: &str) -> Sym {
+        Sym::new(Str::from(name))
+    }
+}
+
+impl std::fmt::Display for Sym {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl std::fmt::Debug for Sym {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Sym({})", self.name())
+    }
+}
+
+// Tests //////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_are_interned() {
+        let a = Sym::from("a-name");
+        assert_eq!(a, Sym::new(Str::from("a-name")));
+        assert_ne!(a, Sym::from("another-name"));
+        assert_eq!(*a.name(), Str::from("a-name"));
+        assert!(a.is("a-name"));
+        assert_eq!(a.to_string(), "a-name");
+    }
+
+    #[test]
+    fn test_gensyms() {
+        let sym = Sym::gensym("t");
+        assert_ne!(sym, Sym::gensym("t"));
+        assert_ne!(sym, Sym::from("t"));
+        assert!(!sym.is("t"));
+        assert!(sym.to_string().starts_with("t#"));
+        assert_ne!(Sym::from(sym.to_string().as_str()), sym);
+        assert_eq!(sym.renamed_from(), None);
+        assert_eq!(Sym::rename(sym).renamed_from(), None);
+    }
+
+    #[test]
+    fn test_made_symbols_are_not_interned() {
+        let t = Sym::from("t");
+        let count = INTERNER.with(|interner| interner.borrow().names.len());
+        for _ in 0..100 {
+            Sym::rename(Sym::rename(t));
+            Sym::gensym("t");
+        }
+        assert_eq!(
+            INTERNER.with(|interner| interner.borrow().names.len()),
+            count
+        );
+    }
+
+    #[test]
+    fn test_renames() {
+        let t = Sym::from("t");
+        let renamed = Sym::rename(t);
+        assert_ne!(renamed, t);
+        assert_ne!(renamed, Sym::rename(t));
+        assert_eq!(renamed.renamed_from(), Some(t));
+        assert_eq!(Sym::rename(renamed).renamed_from(), Some(t));
+        assert_eq!(t.renamed_from(), None);
+    }
+
+    #[test]
+    fn test_special_forms() {
+        assert!(Sym::from("if").is_special_form());
+        assert!(Sym::from("unquote-splicing").is_special_form());
+        assert!(!Sym::from("def").is_special_form());
+        assert!(!Sym::rename(Sym::from("if")).is_special_form());
+    }
+}
@@ -1,14 +1,18 @@
 use crate::data::*;
-use std::rc::Rc;
-
-// TODO I am still trying to get the template to rename variables properly. The
-// problem is that I cannot tell with my setup whether a symbol should be renamed
-// or not in the expansion. Either I acciedentally rename legitimate identifiers
-// like let or I do not rename a bound symbol because it is present in the environment.
-// Maybe I need to be able to capture the macro environment so that I can tell what
-// symbols were symbols when the macro was created. This would make it possible
-// in a macro that has a let template to know that let is an identifier and that
-// a binding variable is being introduced (provided it is not a capture variable).
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+// Symbols that a template introduces into an expansion are renamed to fresh
+// symbols that cannot appear in the program, so that bindings made by the
+// expansion cannot capture the symbols passed to the macro and bindings where
+// the macro is used cannot capture the symbols of the expansion. When a renamed
+// symbol is not bound by the expansion itself the vm looks up the symbol it was
+// renamed from in the top level environment, where the macro was defined. The
+// same symbol is given the same fresh name everywhere in one expansion.
+//
+// Special forms and symbols bound to macros where the macro was defined are not
+// renamed, as they are syntax rather than variables, and neither are quoted
+// symbols. This keeps expansions readable, e.g. with macro-expand.
 //
 // There are a number of issues still that seem to be because it is difficult to
 // share information between macro, pattern, template. I think I might need to take
@@ -32,12 +36,21 @@ use std::rc::Rc;
 // it is a macro we can decide to eval it and apply it and if not we can eval
 // everything and then apply the proc/closure like normal.
 
+// An elipse can only be an item of a list or vector. It is expanded into the
+// items made by expanding its template for each of the captures of the
+// sequence vars in it.
+//
+// A map template has templates for its keys and values, and whether it is a
+// dict. The value of a ... key in a map template must expand to a map, like one
+// captured by the rest of a map pattern, whose entries are added to the map.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Template {
     Atom(Val),
-    Var(Rc<Str>),
-    List(Vec<Template>, Option<Box<Template>>),
-    Vector(Vec<Template>, Option<Box<Template>>),
+    Var(Sym),
+    List(Vec<Template>),
+    Vector(Vec<Template>),
+    Elipse(Box<Template>),
+    Map(Vec<(Template, Template)>, Option<Box<Template>>, bool),
 }
 
 impl Template {
@@ -46,128 +59,212 @@ impl Template {
     pub fn new(expr: Val) -> Result<Template, Error> {
         match expr {
             Val::Symbol(s) => Ok(Template::Var(s)),
-            Val::Bool(_) | Val::Char(_) | Val::Number(_) | Val::String(_) | Val::Keyword(_) => {
-                Ok(Template::Atom(expr))
-            }
-            Val::List(ref ls) => {
-                let (templates, elipse) = Template::from_collection(ls.values(), expr)?;
-                Ok(Template::List(templates, elipse))
-            }
+            Val::None
+            | Val::Bool(_)
+            | Val::Char(_)
+            | Val::Number(_)
+            | Val::String(_)
+            | Val::Keyword(_) => Ok(Template::Atom(expr)),
+            Val::List(ref ls) => Ok(Template::List(Template::from_collection(
+                ls.values(),
+                expr.clone(),
+            )?)),
             Val::Vector(ref vec) => {
                 let e = expr.clone();
-                let (templates, elipse) =
-                    Template::from_collection(vec.borrow().values().cloned(), e)?;
-                Ok(Template::Vector(templates, elipse))
+                let templates = Template::from_collection(vec.borrow().values().cloned(), e)?;
+                Ok(Template::Vector(templates))
+            }
+            Val::Map(ref map) => {
+                let mut templates = Vec::new();
+                let mut rest = None;
+                for (key, val) in map.borrow().entries() {
+                    match key {
+                        Val::Symbol(k) if k.is("...") => {
+                            rest = Some(Box::new(Template::new(val.clone())?))
+                        }
+                        _ => templates
+                            .push((Template::new(key.clone())?, Template::new(val.clone())?)),
+                    }
+                }
+                Ok(Template::Map(templates, rest, map.borrow().is_dict()))
             }
             _ => Err(Error::BadTemplate(expr)),
         }
     }
 
-    fn from_collection(
-        iter: impl Iterator<Item = Val>,
-        expr: Val,
-    ) -> Result<(Vec<Template>, Option<Box<Template>>), Error> {
+    fn from_collection(iter: impl Iterator<Item = Val>, expr: Val) -> Result<Vec<Template>, Error> {
         let mut pxs = Vec::new();
-        let mut elipse = None;
         for val in iter {
             match val {
-                Val::Symbol(s) if s.is("...") => {
-                    match pxs.pop() {
-                        Some(temp) => elipse = Some(Box::new(temp)),
-                        _ => return Err(Error::BadTemplate(expr)),
-                    };
-                    break;
-                }
+                Val::Symbol(s) if s.is("...") => match pxs.pop() {
+                    Some(Template::Elipse(_)) | None => return Err(Error::BadTemplate(expr)),
+                    Some(temp) => pxs.push(Template::Elipse(Box::new(temp))),
+                },
                 _ => pxs.push(Template::new(val.clone())?),
             }
         }
-        Ok((pxs, elipse))
+        Ok(pxs)
     }
 
     // Expand //
 
-    pub fn expand(&self, name: Rc<Str>, captures: Environ) -> Result<Val, Error> {
+    pub fn expand(&self, captures: &Captures, renames: Option<&Renames>) -> Result<Val, Error> {
         match self {
             Template::Atom(val) => Ok(val.clone()),
-            Template::Var(s) => {
-                //println!("{name} {s}");
-                let name = Rc::new(Str::from(format!("{s}!!0").as_str()));
-                match captures.lookup(&name) {
-                    Some(val) => {
-                        //println!("{val}");
-                        Ok(val)
-                    }
-                    None => {
-                        if *s != name && !self.reserved(s.clone()) {
-                            let sym = Rc::new(Str::from(format!("{s}").as_str()));
-                            Ok(Val::Symbol(sym))
-                        } else {
-                            Ok(Val::Symbol(s.clone()))
+            Template::Var(s) => match (captures.get(s), renames) {
+                (Some(Capture::Single(val)), _) => Ok(val.clone()),
+                // A var that matched under an elipse must be under one here too
+                (Some(Capture::Seq(_)), _) => Err(Error::BadTemplate(Val::Symbol(*s))),
+                (None, Some(renames)) => Ok(Val::Symbol(renames.rename(*s))),
+                (None, None) => Ok(Val::Symbol(*s)),
+            },
+            Template::List(templates) => {
+                let renames = match templates.first() {
+                    Some(Template::Var(s)) if s.is("quote") => None,
+                    _ => renames,
+                };
+                self.expand_collection(templates, captures, renames)
+                    .map(|v| Val::list_from_vec(&v))
+            }
+            Template::Vector(templates) => self
+                .expand_collection(templates, captures, renames)
+                .map(Val::from),
+            Template::Map(templates, rest, dict) => {
+                let mut map = Map::new();
+                if let Some(t) = rest {
+                    match t.expand(captures, renames)? {
+                        Val::Map(other) => {
+                            for (key, val) in other.borrow().entries() {
+                                map.assoc(key.clone(), val.clone())?;
+                            }
                         }
-                    } //Ok(Val::Symbol(s.clone())),
+                        val => return Err(Error::BadTemplate(val)),
+                    }
+                }
+                for (k, v) in templates.iter() {
+                    map.assoc(k.expand(captures, renames)?, v.expand(captures, renames)?)?;
                 }
+                if *dict {
+                    map.freeze();
+                }
+                Ok(Val::from(map))
             }
-            Template::List(templates, elipse) => self
-                .expand_collection(name, templates, elipse, captures.clone())
-                .map(|v| Val::list_from_vec(&v)),
-            Template::Vector(templates, elipse) => self
-                .expand_collection(name, templates, elipse, captures.clone())
-                .map(Val::from),
+            Template::Elipse(_) => panic!("an elipse can only be expanded in a collection"),
         }
     }
 
     fn expand_collection(
         &self,
-        name: Rc<Str>,
         templates: &[Template],
-        elipse: &Option<Box<Template>>,
-        captures: Environ,
+        captures: &Captures,
+        renames: Option<&Renames>,
     ) -> Result<Vec<Val>, Error> {
-        // Expand the templates
-        let mut vec = templates
-            .iter()
-            .map(|t| t.expand(name.clone(), captures.clone()))
-            .collect::<Result<Vec<Val>, Error>>()?;
-
-        // Expand the elipse
-        if let Some(t) = elipse {
-            match **t {
-                Template::Var(ref s) => {
-                    let mut idx = 0;
-                    loop {
-                        let name = Rc::new(Str::from(format!("{s}!!{idx}").as_str()));
-                        match captures.lookup(&name) {
-                            Some(val) => vec.push(val),
-                            None => break,
-                        };
-                        idx += 1;
-                    }
-                }
-                _ => {
-                    // TODO this will not work with things like letrec
-                    // where we do (var <literal>) ...
-                    // Or possibly even with (var val) ...
-                    // As mentioned in a number of places the macro needs to be
-                    // re-written so that it covers more cases properly.
-                    // println!("{:?}", t);
-                    // println!("{:?}", captures);
-                    return Err(Error::TemplateElipseNotVar);
-                }
+        let mut vec = Vec::new();
+        for t in templates.iter() {
+            match t {
+                Template::Elipse(t) => vec.extend(t.expand_elipse(captures, renames)?),
+                _ => vec.push(t.expand(captures, renames)?),
             }
+        }
+        Ok(vec)
+    }
+
+    // Expand the template once for each capture of the sequence vars in it,
+    // which must all have the same length. Vars that are not sequences are the
+    // same in every expansion.
+    fn expand_elipse(
+        &self,
+        captures: &Captures,
+        renames: Option<&Renames>,
+    ) -> Result<Vec<Val>, Error> {
+        let seqs: Vec<(Sym, &Vec<Capture>)> = self
+            .vars()
+            .into_iter()
+            .filter_map(|var| match captures.get(&var) {
+                Some(Capture::Seq(seq)) => Some((var, seq)),
+                _ => None,
+            })
+            .collect();
+
+        let len = match seqs.first() {
+            Some((_, seq)) => seq.len(),
+            None => return Err(Error::TemplateElipseNotVar),
         };
+        if let Some((var, _)) = seqs.iter().find(|(_, seq)| seq.len() != len) {
+            return Err(Error::ElipseMismatch(
+                seqs[0].0.to_string(),
+                var.to_string(),
+            ));
+        }
 
-        Ok(vec)
+        let mut vals = Vec::with_capacity(len);
+        for i in 0..len {
+            let mut caps = captures.clone();
+            for (var, seq) in seqs.iter() {
+                caps.insert(*var, seq[i].clone());
+            }
+            vals.push(self.expand(&caps, renames)?);
+        }
+        Ok(vals)
+    }
+
+    // The symbols in the template
+    fn vars(&self) -> Vec<Sym> {
+        match self {
+            Template::Atom(_) => vec![],
+            Template::Var(s) => vec![*s],
+            Template::Elipse(t) => t.vars(),
+            Template::List(templates) | Template::Vector(templates) => {
+                templates.iter().flat_map(|t| t.vars()).collect()
+            }
+            Template::Map(templates, rest, _) => templates
+                .iter()
+                .flat_map(|(k, v)| k.vars().into_iter().chain(v.vars()))
+                .chain(rest.iter().flat_map(|t| t.vars()))
+                .collect(),
+        }
+    }
+}
+
+// Renames ////////////////////////////////////////////////////////////////////
+
+// The fresh names given to the symbols introduced by one expansion of a macro.
+// The environment is the one the macro was defined in.
+pub struct Renames {
+    env: Environ,
+    names: RefCell<HashMap<Sym, Sym>>,
+}
+
+impl Renames {
+    pub fn new(env: Environ) -> Renames {
+        Renames {
+            env,
+            names: RefCell::new(HashMap::new()),
+        }
     }
 
-    // TODO this should be somewhere else
-    fn reserved(&self, ident: Rc<Str>) -> bool {
-        match ident.to_string().as_str() {
-            "if" | "lambda" => true,
-            _ => false,
+    pub fn rename(&self, sym: Sym) -> Sym {
+        if self.is_syntax(&sym) {
+            return sym;
         }
+        *self
+            .names
+            .borrow_mut()
+            .entry(sym)
+            .or_insert_with(|| Sym::rename(sym))
+    }
+
+    fn is_syntax(&self, sym: &Sym) -> bool {
+        sym.is_special_form()
+            || TOP_LEVEL_FORMS.iter().any(|name| sym.is(name))
+            || AUXILIARY_SYNTAX.iter().any(|name| sym.is(name))
+            || matches!(self.env.lookup(sym), Some(Val::Macro(_)))
     }
 }
 
+// Symbols that only have meaning inside of a special form.
+const AUXILIARY_SYNTAX: &[&str] = &["catch", "finally", ".", "&opt", "&key"];
+
 // Testing ////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
@@ -176,43 +273,37 @@ mod tests {
 
     #[test]
     fn test_expanding_with_atoms() {
-        let name = Rc::new(Str::from("name"));
-        let captures = Rc::new(Env::new());
+        let captures = Captures::new();
 
         let t = Template::Atom(Val::from(10));
-        assert_eq!(t.expand(name, captures.clone()), Ok(Val::from(10)));
+        assert_eq!(t.expand(&captures, None), Ok(Val::from(10)));
     }
 
     #[test]
     fn test_expanding_with_list() {
-        let name = Rc::new(Str::from("name"));
-        let captures = Rc::new(Env::new());
+        let captures = Captures::new();
 
-        let t = Template::List(
-            vec![Template::Atom(Val::from(10)), Template::Atom(Val::from(99))],
-            None,
-        );
+        let t = Template::List(vec![
+            Template::Atom(Val::from(10)),
+            Template::Atom(Val::from(99)),
+        ]);
         assert_eq!(
-            t.expand(name, captures.clone()),
+            t.expand(&captures, None),
             Ok(Val::list_from_vec(&vec![Val::from(10), Val::from(99)]))
         );
     }
 
     #[test]
     fn test_expanding_with_nested_list() {
-        let name = Rc::new(Str::from("name"));
-        let captures = Rc::new(Env::new());
-
-        let t = Template::List(
-            vec![
-                Template::Atom(Val::from(10)),
-                Template::List(vec![Template::Atom(Val::from(88))], None),
-                Template::Atom(Val::from(99)),
-            ],
-            None,
-        );
+        let captures = Captures::new();
+
+        let t = Template::List(vec![
+            Template::Atom(Val::from(10)),
+            Template::List(vec![Template::Atom(Val::from(88))]),
+            Template::Atom(Val::from(99)),
+        ]);
         assert_eq!(
-            t.expand(name, captures.clone()),
+            t.expand(&captures, None),
             Ok(Val::list_from_vec(&vec![
                 Val::from(10),
                 Val::list_from_vec(&vec![Val::from(88)]),
@@ -223,28 +314,24 @@ mod tests {
 
     #[test]
     fn test_expanding_with_nested_list_and_vars() {
-        let name = Rc::new(Str::from("f"));
-        let captures = Rc::new(Env::new());
-        captures.insert(Rc::new(Str::from("a!!0")), Val::from(10));
-        captures.insert(Rc::new(Str::from("b!!0")), Val::from(99));
+        let mut captures = Captures::new();
+        captures.insert(Sym::from("a"), Capture::Single(Val::from(10)));
+        captures.insert(Sym::from("b"), Capture::Single(Val::from(99)));
 
         // (f (a) b c)
-        let a = Rc::new(Str::from("a"));
-        let b = Rc::new(Str::from("b"));
-        let c = Rc::new(Str::from("c"));
-        let t = Template::List(
-            vec![
-                Template::Atom(Val::symbol("f")),
-                Template::List(vec![Template::Var(a)], None),
-                Template::Var(b),
-                Template::Var(c),
-            ],
-            None,
-        );
+        let a = Sym::from("a");
+        let b = Sym::from("b");
+        let c = Sym::from("c");
+        let t = Template::List(vec![
+            Template::Atom(Val::symbol("f")),
+            Template::List(vec![Template::Var(a)]),
+            Template::Var(b),
+            Template::Var(c),
+        ]);
 
         // (f (10) 99 c)
         assert_eq!(
-            t.expand(name, captures.clone()),
+            t.expand(&captures, None),
             Ok(Val::list_from_vec(&vec![
                 Val::symbol("f"),
                 Val::list_from_vec(&vec![Val::from(10)]),
@@ -256,30 +343,31 @@ mod tests {
 
     #[test]
     fn test_expanding_with_list_elipse_and_vars() {
-        let name = Rc::new(Str::from("f"));
-        let captures = Rc::new(Env::new());
-        captures.insert(Rc::new(Str::from("a!!0")), Val::from(10));
-        captures.insert(Rc::new(Str::from("a!!1")), Val::from(11));
-        captures.insert(Rc::new(Str::from("a!!2")), Val::from(12));
-        captures.insert(Rc::new(Str::from("b!!0")), Val::from(99));
+        let mut captures = Captures::new();
+        captures.insert(
+            Sym::from("a"),
+            Capture::Seq(vec![
+                Capture::Single(Val::from(10)),
+                Capture::Single(Val::from(11)),
+                Capture::Single(Val::from(12)),
+            ]),
+        );
+        captures.insert(Sym::from("b"), Capture::Single(Val::from(99)));
 
         // (f (a ...) b c) where there are 3 elements in the matched expr
-        let a = Rc::new(Str::from("a"));
-        let b = Rc::new(Str::from("b"));
-        let c = Rc::new(Str::from("c"));
-        let t = Template::List(
-            vec![
-                Template::Atom(Val::symbol("f")),
-                Template::List(vec![], Some(Box::new(Template::Var(a)))),
-                Template::Var(b),
-                Template::Var(c),
-            ],
-            None,
-        );
+        let a = Sym::from("a");
+        let b = Sym::from("b");
+        let c = Sym::from("c");
+        let t = Template::List(vec![
+            Template::Atom(Val::symbol("f")),
+            Template::List(vec![Template::Elipse(Box::new(Template::Var(a)))]),
+            Template::Var(b),
+            Template::Var(c),
+        ]);
 
         // (f (10 11 12) 99)
         assert_eq!(
-            t.expand(name, captures.clone()),
+            t.expand(&captures, None),
             Ok(Val::list_from_vec(&vec![
                 Val::symbol("f"),
                 Val::list_from_vec(&vec![Val::from(10), Val::from(11), Val::from(12)]),
@@ -291,30 +379,31 @@ mod tests {
 
     #[test]
     fn test_expanding_with_vector_elipse_and_vars() {
-        let name = Rc::new(Str::from("name"));
-        let captures = Rc::new(Env::new());
-        captures.insert(Rc::new(Str::from("a!!0")), Val::from(10));
-        captures.insert(Rc::new(Str::from("a!!1")), Val::from(11));
-        captures.insert(Rc::new(Str::from("a!!2")), Val::from(12));
-        captures.insert(Rc::new(Str::from("b!!0")), Val::from(99));
+        let mut captures = Captures::new();
+        captures.insert(
+            Sym::from("a"),
+            Capture::Seq(vec![
+                Capture::Single(Val::from(10)),
+                Capture::Single(Val::from(11)),
+                Capture::Single(Val::from(12)),
+            ]),
+        );
+        captures.insert(Sym::from("b"), Capture::Single(Val::from(99)));
 
         // [f [a ...] b c] where there are 3 elements in the matched expr
-        let a = Rc::new(Str::from("a"));
-        let b = Rc::new(Str::from("b"));
-        let c = Rc::new(Str::from("c"));
-        let t = Template::Vector(
-            vec![
-                Template::Atom(Val::symbol("f")),
-                Template::Vector(vec![], Some(Box::new(Template::Var(a)))),
-                Template::Var(b),
-                Template::Var(c),
-            ],
-            None,
-        );
+        let a = Sym::from("a");
+        let b = Sym::from("b");
+        let c = Sym::from("c");
+        let t = Template::Vector(vec![
+            Template::Atom(Val::symbol("f")),
+            Template::Vector(vec![Template::Elipse(Box::new(Template::Var(a)))]),
+            Template::Var(b),
+            Template::Var(c),
+        ]);
 
         // [f [10 11 12] 99]
         assert_eq!(
-            t.expand(name, captures.clone()),
+            t.expand(&captures, None),
             Ok(Val::from(vec![
                 Val::symbol("f"),
                 Val::from(vec![Val::from(10), Val::from(11), Val::from(12)]),
@@ -323,4 +412,111 @@ mod tests {
             ]))
         );
     }
+
+    #[test]
+    fn test_expanding_renames_introduced_symbols() {
+        let mut captures = Captures::new();
+        captures.insert(Sym::from("a"), Capture::Single(Val::symbol("x")));
+        let renames = Renames::new(Rc::new(Env::new()));
+
+        // (lambda [x] (f x a '(x))) where x and f are introduced
+        let x = || Template::Var(Sym::from("x"));
+        let t = Template::List(vec![
+            Template::Var(Sym::from("lambda")),
+            Template::Vector(vec![x()]),
+            Template::List(vec![
+                Template::Var(Sym::from("f")),
+                x(),
+                Template::Var(Sym::from("a")),
+                Template::List(vec![
+                    Template::Var(Sym::from("quote")),
+                    Template::List(vec![x()]),
+                ]),
+            ]),
+        ]);
+
+        let result = t.expand(&captures, Some(&renames)).unwrap().to_string();
+        let x = renames.rename(Sym::from("x")).to_string();
+        let f = renames.rename(Sym::from("f")).to_string();
+        assert!(x.starts_with("x#") && f.starts_with("f#"));
+        assert_eq!(result, format!("(lambda [{x}] ({f} {x} x (quote (x))))"));
+    }
+
+    #[test]
+    fn test_expanding_nested_elipses() {
+        let mut captures = Captures::new();
+        let single = |v: i64| Capture::Single(Val::from(v));
+        captures.insert(Sym::from("a"), Capture::Seq(vec![single(1), single(4)]));
+        captures.insert(
+            Sym::from("b"),
+            Capture::Seq(vec![
+                Capture::Seq(vec![single(2), single(3)]),
+                Capture::Seq(vec![]),
+            ]),
+        );
+
+        // ((b ... a) ...)
+        let a = Sym::from("a");
+        let b = Sym::from("b");
+        let t = Template::List(vec![Template::Elipse(Box::new(Template::List(vec![
+            Template::Var(a),
+            Template::Elipse(Box::new(Template::Var(b))),
+        ])))]);
+        assert_eq!(
+            t.expand(&captures, None).unwrap().to_string(),
+            "((1 2 3) (4))"
+        );
+
+        // Vars under the same elipse must have the same length
+        let t = Template::List(vec![Template::Elipse(Box::new(Template::List(vec![
+            Template::Var(a),
+            Template::Var(b),
+        ])))]);
+        captures.insert(b, Capture::Seq(vec![single(2)]));
+        assert_eq!(
+            t.expand(&captures, None),
+            Err(Error::ElipseMismatch("a".to_string(), "b".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_expanding_maps() {
+        let mut captures = Captures::new();
+        captures.insert(Sym::from("x"), Capture::Single(Val::from(1)));
+        captures.insert(
+            Sym::from("rest"),
+            Capture::Single(Val::from(
+                Map::map(&[(Val::keyword(":b"), Val::from(2))]).unwrap(),
+            )),
+        );
+
+        // #{:a x ... rest}
+        let t = Template::Map(
+            vec![(
+                Template::Atom(Val::keyword(":a")),
+                Template::Var(Sym::from("x")),
+            )],
+            Some(Box::new(Template::Var(Sym::from("rest")))),
+            true,
+        );
+        let expected = Map::dict(&[
+            (Val::keyword(":a"), Val::from(1)),
+            (Val::keyword(":b"), Val::from(2)),
+        ])
+        .unwrap();
+        match t.expand(&captures, None) {
+            Ok(Val::Map(map)) => {
+                assert!(map.borrow().is_dict());
+                assert_eq!(*map.borrow(), expected);
+            }
+            result => panic!("expected a map: {:?}", result),
+        }
+
+        // The rest must be a map
+        let t = Template::Map(vec![], Some(Box::new(Template::Var(Sym::from("x")))), false);
+        assert_eq!(
+            t.expand(&captures, None),
+            Err(Error::BadTemplate(Val::from(1)))
+        );
+    }
 }
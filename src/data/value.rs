@@ -5,10 +5,10 @@ use std::rc::Rc;
 
 #[derive(Clone, PartialEq, Eq)]
 pub enum Val {
-    Symbol(Rc<Str>),
+    Symbol(Sym),
     Bool(bool),
     Number(Num),
-    Keyword(Rc<Str>),
+    Keyword(Sym),
     Char(Char),
     String(Rc<RefCell<Str>>),
     List(Rc<List>),
@@ -19,10 +19,12 @@ pub enum Val {
     Lambda(Rc<Lambda>),
     Macro(Rc<Macro>),
     Var(Rc<RefCell<Val>>), // To serve as something like clojures atom
+    Error(Rc<Error>),
     Empty,
     None,
     // not available to user
     TailCall(Rc<TailCall>),
+    Recur(Rc<Vec<Val>>),
     Undefined,
 }
 
@@ -36,14 +38,14 @@ impl Val {
     }
 
     pub fn symbol(s: &str) -> Val {
-        Val::Symbol(Rc::new(Str::from(s)))
+        Val::Symbol(Sym::from(s))
     }
 
     pub fn keyword(s: &str) -> Val {
         if s.starts_with(':') {
-            Val::Keyword(Rc::new(Str::from(s)))
+            Val::Keyword(Sym::from(s))
         } else {
-            Val::Keyword(Rc::new(Str::from(format!(":{s}").as_str())))
+            Val::Keyword(Sym::from(format!(":{s}").as_str()))
         }
     }
 
@@ -55,6 +57,10 @@ impl Val {
         Val::Var(Rc::new(RefCell::new(val)))
     }
 
+    pub fn error(err: Error) -> Val {
+        Val::Error(Rc::new(err))
+    }
+
     // Predicates //
 
     pub fn is_true(&self) -> bool {
@@ -71,7 +77,9 @@ impl Val {
             | Val::Char(_)
             | Val::Number(_)
             | Val::Symbol(_)
-            | Val::String(_) => true,
+            | Val::String(_)
+            | Val::Empty => true,
+            Val::List(ls) => ls.values().all(|v| v.is_hashable()),
             _ => false,
         }
     }
@@ -205,7 +213,9 @@ impl DisplayRep for Val {
             Val::Closure(c) => c.to_display(),
             Val::Macro(m) => m.to_display(),
             Val::TailCall(t) => t.to_display(),
+            Val::Recur(_) => "#<recur>".to_string(),
             Val::Var(s) => format!("#<var {}>", s.borrow().to_display()).to_string(),
+            Val::Error(e) => format!("#<error {}>", e),
             Val::Empty => "#()".to_string(),
             Val::None => "#none".to_string(),
             Val::Undefined => "#<undefined>".to_string(),
@@ -230,7 +240,9 @@ impl ExternalRep for Val {
             Val::Closure(c) => c.to_external(),
             Val::Macro(m) => m.to_external(),
             Val::TailCall(t) => t.to_external(),
+            Val::Recur(_) => self.to_display(),
             Val::Var(s) => format!("#<var {}>", s.borrow().to_external()).to_string(),
+            Val::Error(_) => self.to_display(),
             Val::Empty => self.to_display(),
             Val::None => self.to_display(),
             Val::Undefined => self.to_display(),
@@ -261,6 +273,8 @@ impl Hash for Val {
             Val::Symbol(sym) => sym.hash(state),
             Val::Keyword(sym) => sym.hash(state),
             Val::String(s) => s.borrow().hash(state),
+            Val::List(ls) => ls.values().for_each(|v| v.hash(state)),
+            Val::Empty => 0.hash(state),
             _ => panic!("cannot hash this value type: {:?}", self),
         }
     }
@@ -1,9 +1,11 @@
-use crate::data::{DisplayRep, Error, ExternalRep, List, Val};
+use crate::data::{DisplayRep, ElementSpans, Error, ExternalRep, List, Span, Val};
+use std::rc::Rc;
 
 #[derive(Clone, PartialEq, Eq)]
 pub struct Vector {
     mutable: bool,
     vals: Vec<Val>,
+    spans: ElementSpans,
 }
 
 impl Vector {
@@ -15,6 +17,7 @@ impl Vector {
         Vector {
             mutable: true,
             vals: vec,
+            spans: ElementSpans::default(),
         }
     }
 
@@ -22,6 +25,7 @@ impl Vector {
         Vector {
             mutable: false,
             vals,
+            spans: ElementSpans::default(),
         }
     }
 
@@ -29,6 +33,7 @@ impl Vector {
         Vector {
             mutable: false,
             vals: ls.values().map(|v| v.clone()).collect(),
+            spans: ElementSpans::default(),
         }
     }
 
@@ -36,6 +41,7 @@ impl Vector {
         Vector {
             mutable: false,
             vals: vec.vals.clone(),
+            spans: ElementSpans::default(),
         }
     }
 
@@ -43,9 +49,20 @@ impl Vector {
         Vector {
             mutable: true,
             vals: vec.vals.clone(),
+            spans: ElementSpans::default(),
         }
     }
 
+    // A vector read from source keeps the spans of its elements
+    pub fn with_spans(mut self, spans: Vec<(Val, Rc<Span>)>) -> Vector {
+        self.spans = ElementSpans::new(spans);
+        self
+    }
+
+    pub fn element_span(&self, val: &Val) -> Option<Rc<Span>> {
+        self.spans.find(val)
+    }
+
     // Access //
 
     pub fn get(&self, idx: usize) -> Option<Val> {
@@ -121,6 +138,7 @@ impl From<Vec<Val>> for Vector {
         Vector {
             mutable: true,
             vals: vec,
+            spans: ElementSpans::default(),
         }
     }
 }
@@ -130,6 +148,7 @@ impl From<List> for Vector {
         Vector {
             mutable: true,
             vals: ls.values().map(|v| v.clone()).collect(),
+            spans: ElementSpans::default(),
         }
     }
 }
@@ -1,7 +1,10 @@
+mod compiler;
 mod interpreter;
 mod null_env;
+mod repl;
 mod vm;
 
 pub use interpreter::Interpreter;
 pub use null_env::null_env;
+pub use repl::Repl;
 pub use vm::Vm;
@@ -0,0 +1,1348 @@
+use crate::data::*;
+use crate::interpret::Vm;
+use std::rc::Rc;
+
+// Compiler ///////////////////////////////////////////////////////////////////
+
+// Compiles forms into code for the vm. Macros that are bound at the top level
+// when a form is compiled are expanded then, so their expansions are compiled
+// in place of their uses, behind a check that the macro has not been rebound
+// since. Anything else at the head of a call is checked when the call is
+// evaluated, in case it has been bound to a macro since.
+//
+// Like the forms themselves the code evaluates them lazily, so an error found
+// while compiling a form becomes an op that raises it when the form would have
+// been evaluated.
+//
+// The scope is the one the code will be run in, with a scope added for each
+// scope the code adds itself, like those of loops. A name bound by one of them
+// is compiled to the address of its slot, and any other name is looked up in
+// the global environment.
+pub struct Compiler<'a> {
+    vm: &'a Vm,
+    scope: Option<Rc<Scope>>,
+    code: Code,
+    span: Option<Rc<Span>>,
+    // The list, vector or map read from source that the forms being compiled
+    // are elements of, which has their spans
+    within: Option<Val>,
+    // The first error that a form in a body is sure to raise, and how many
+    // calls whose arguments may be a macro's operands are being compiled
+    error: Option<Error>,
+    unsure: usize,
+}
+
+impl<'a> Compiler<'a> {
+    pub fn new(vm: &'a Vm, scope: Option<Rc<Scope>>) -> Compiler<'a> {
+        Compiler {
+            vm,
+            scope,
+            code: Code::new(),
+            span: None,
+            within: None,
+            error: None,
+            unsure: 0,
+        }
+    }
+
+    // Give the code the span of the form it is compiled from, for a form that
+    // cannot carry its own span, like a symbol read at the top level
+    pub fn at(mut self, span: Option<Rc<Span>>) -> Compiler<'a> {
+        self.span = span;
+        self
+    }
+
+    // Compile a form whose value is the value of the code
+    pub fn form(mut self, form: &Val) -> Code {
+        self.compile(form, true);
+        self.code
+    }
+
+    // Compile the body of a closure clause. Its params are bound in a new
+    // scope, which is also where its leading definitions are bound. A body
+    // with a form that fails to compile is an error.
+    pub fn body(mut self, clause: &Clause) -> Result<Code, Error> {
+        let forms: Vec<Val> = clause
+            .body
+            .clone()
+            .map(|ls| ls.values().collect())
+            .unwrap_or_default();
+        let scope = Scope::new(clause.formals.names(), self.scope.clone());
+        self.within = clause.body.clone().map(Val::List);
+        self.definitions(scope, &forms, true);
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.code),
+        }
+    }
+
+    // Emitting //
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.code.emit(op, self.span.clone())
+    }
+
+    fn patch(&mut self, at: usize, op: Op) {
+        self.code.ops[at] = op;
+    }
+
+    // Raise an error in place of a form that failed to compile
+    fn raise(&mut self, err: Error) {
+        if self.unsure == 0 && self.error.is_none() {
+            self.error = Some(err.clone().at(self.span.clone()));
+        }
+        self.emit(Op::Raise(err));
+    }
+
+    fn constant(&mut self, val: Val, tail: bool) {
+        let idx = self.code.constant(val);
+        self.emit(Op::Const(idx));
+        self.ret(tail);
+    }
+
+    fn ret(&mut self, tail: bool) {
+        if tail {
+            self.emit(Op::Return);
+        }
+    }
+
+    // Add a scope and return the enclosing one
+    fn enter(&mut self, scope: Scope) -> Option<Rc<Scope>> {
+        self.scope.replace(Rc::new(scope))
+    }
+
+    // Forms //
+
+    // A form in tail position returns its value, otherwise it leaves it on the
+    // stack. Ops compiled from an element of a collection read from source
+    // have its span.
+    fn compile(&mut self, form: &Val, tail: bool) {
+        let span = self.span.clone();
+        if let Some(element) = self.element_span(form) {
+            self.span = Some(element);
+        }
+        self.compile_form(form, tail);
+        self.span = span;
+    }
+
+    fn compile_form(&mut self, form: &Val, tail: bool) {
+        match form {
+            Val::Symbol(s) => {
+                match self.scope.as_ref().and_then(|scope| scope.address(*s)) {
+                    Some((depth, slot)) => self.emit(Op::Local(*s, depth, slot)),
+                    None => self.emit(Op::Global(*s)),
+                };
+                self.ret(tail);
+            }
+            Val::List(ls) => self.list(ls, tail),
+            // Vector and map literals are evaluated into new collections
+            Val::Vector(v) => {
+                let vals: Vec<Val> = v.borrow().values().cloned().collect();
+                let within = self.within.replace(form.clone());
+                for val in vals.iter() {
+                    self.compile(val, false);
+                }
+                self.within = within;
+                let shape = match v.borrow().is_tuple() {
+                    true => Shape::Tuple,
+                    false => Shape::Vector,
+                };
+                self.emit(Op::Build(shape, vals.len()));
+                self.ret(tail);
+            }
+            Val::Map(m) => {
+                let entries: Vec<(Val, Val)> = m
+                    .borrow()
+                    .entries()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+                let within = self.within.replace(form.clone());
+                for (k, v) in entries.iter() {
+                    self.compile(k, false);
+                    self.compile(v, false);
+                }
+                self.within = within;
+                let shape = match m.borrow().is_dict() {
+                    true => Shape::Dict,
+                    false => Shape::Map,
+                };
+                self.emit(Op::Build(shape, entries.len()));
+                self.ret(tail);
+            }
+            Val::TailCall(_) | Val::Undefined => panic!("should not be evaluated: {form}"),
+            _ => self.constant(form.clone(), tail),
+        }
+    }
+
+    // The span of a symbol or collection literal in the collection it was read
+    // in. Lists carry their own spans.
+    fn element_span(&self, form: &Val) -> Option<Rc<Span>> {
+        if !matches!(form, Val::Symbol(_) | Val::Vector(_) | Val::Map(_)) {
+            return None;
+        }
+        match self.within.as_ref()? {
+            Val::List(ls) => ls.element_span(form),
+            Val::Vector(v) => v.borrow().element_span(form),
+            Val::Map(m) => m.borrow().element_span(form),
+            _ => None,
+        }
+    }
+
+    // Ops compiled from a list have its span, unless it was not read from
+    // source, in which case they have the span of the list it is inside.
+    fn list(&mut self, ls: &Rc<List>, tail: bool) {
+        let span = self.span.clone();
+        let scope = self.scope.clone();
+        if ls.span().is_some() {
+            self.span = ls.span();
+        }
+        let within = self.within.replace(Val::List(ls.clone()));
+        let start = self.code.next();
+        if let Err(err) = self.list_form(ls, tail) {
+            self.code.truncate(start);
+            self.scope = scope;
+            self.raise(err);
+        }
+        self.within = within;
+        self.span = span;
+    }
+
+    fn list_form(&mut self, ls: &Rc<List>, tail: bool) -> Result<(), Error> {
+        match ls.head() {
+            Val::Symbol(s) if s.is_special_form() => {
+                let name = s.to_string();
+                self.special(&name, ls.tail(), tail)
+            }
+            Val::Symbol(_) if self.scope.is_none() && is_define(&Val::List(ls.clone())) => {
+                self.global_definition(ls, tail)
+            }
+            _ => self.call(ls, tail),
+        }
+    }
+
+    fn special(&mut self, name: &str, list: Option<Rc<List>>, tail: bool) -> Result<(), Error> {
+        let ls = match list {
+            Some(ls) => ls,
+            // A try or do with nothing to evaluate is fine, like an empty body
+            None => match name {
+                "try" | "do" => {
+                    self.constant(Val::None, tail);
+                    return Ok(());
+                }
+                "recur" => return self.recur(None, tail),
+                "quote" => return Err(Error::Arity("quote")),
+                "quasiquote" => return Err(Error::Arity("quasiquote")),
+                "loop" => return Err(Error::Arity("loop")),
+                "if" => return Err(Error::Arity("if")),
+                "lambda" => return Err(Error::Arity("lambda")),
+                "macro-expand" => return Err(Error::Arity("macro-expand")),
+                "unquote" => return Err(Error::Arity("unquote")),
+                "unquote-splicing" => return Err(Error::Arity("unquote-splicing")),
+                _ => panic!("not a special form: {name}"),
+            },
+        };
+        match name {
+            "if" => self.if_form(ls, tail),
+            "do" => {
+                let forms: Vec<Val> = ls.values().collect();
+                self.sequence(&forms, tail);
+                Ok(())
+            }
+            "lambda" => {
+                let clauses: Vec<Clause> = lambda_clauses(&ls)?
+                    .into_iter()
+                    .map(|clause| clause.in_scope(self.scope.clone()))
+                    .collect();
+                self.emit(Op::Lambda(clauses.into()));
+                self.ret(tail);
+                Ok(())
+            }
+            "loop" => self.loop_form(ls, tail),
+            "recur" => self.recur(Some(ls), tail),
+            "macro-expand" => self.macro_expand(ls, tail),
+            "try" => self.try_form(ls, tail),
+            "quote" => match ls.len() {
+                1 => {
+                    self.constant(ls.head().clone(), tail);
+                    Ok(())
+                }
+                _ => Err(Error::Arity("quote")),
+            },
+            "quasiquote" => match ls.len() {
+                1 => {
+                    self.quasi(ls.head(), 1)?;
+                    self.ret(tail);
+                    Ok(())
+                }
+                _ => Err(Error::Arity("quasiquote")),
+            },
+            "unquote" => Err(Error::ArgType(
+                "unquote",
+                "used inside quasiquote",
+                Val::from(List::new(Val::symbol(name), Some(ls))),
+            )),
+            "unquote-splicing" => Err(Error::ArgType(
+                "unquote-splicing",
+                "used inside quasiquote",
+                Val::from(List::new(Val::symbol(name), Some(ls))),
+            )),
+            _ => panic!("not a special form: {name}"),
+        }
+    }
+
+    fn if_form(&mut self, list: Rc<List>, tail: bool) -> Result<(), Error> {
+        let cond = list.first().ok_or(Error::Arity("if"))?;
+        let true_branch = list.second().ok_or(Error::Arity("if"))?;
+
+        self.compile(&cond, false);
+        let jump = self.emit(Op::JumpIfFalse(0));
+        self.compile(&true_branch, tail);
+        let skip = match tail {
+            true => None,
+            false => Some(self.emit(Op::Jump(0))),
+        };
+        self.patch(jump, Op::JumpIfFalse(self.code.next()));
+        match list.third() {
+            Some(false_branch) => self.compile(&false_branch, tail),
+            None => self.constant(Val::None, tail),
+        }
+        if let Some(skip) = skip {
+            self.patch(skip, Op::Jump(self.code.next()));
+        }
+        Ok(())
+    }
+
+    // Evaluate the forms in order, with the last one in tail position
+    fn sequence(&mut self, forms: &[Val], tail: bool) {
+        match forms.split_last() {
+            Some((last, init)) => {
+                for form in init {
+                    self.compile(form, false);
+                    self.emit(Op::Pop);
+                }
+                self.compile(last, tail);
+            }
+            None => self.constant(Val::None, tail),
+        }
+    }
+
+    // Compile forms in a new scope, which their leading def and defn forms are
+    // bound in as well, including those that leading macro uses expand to.
+    // All of the names are bound before any value is evaluated, so that like
+    // letrec* the values can refer to each other.
+    fn definitions(&mut self, mut scope: Scope, forms: &[Val], tail: bool) {
+        let mut forms = forms.to_vec();
+        let mut count = 0;
+        let mut names = scope.names.clone();
+        for form in forms.iter_mut() {
+            let inner = Rc::new(Scope::new(names.clone(), scope.outer.clone()));
+            let expanded = self.expand_head(form, &inner);
+            if !is_define(&expanded) {
+                break;
+            }
+            if let Val::List(ls) = &expanded {
+                if let Ok(def) = Definition::new(ls) {
+                    names.push(def.name);
+                }
+            }
+            *form = expanded;
+            count += 1;
+        }
+        let mut defs = Vec::new();
+        for form in forms[..count].iter() {
+            if let Val::List(ls) = form {
+                match Definition::new(ls) {
+                    Ok(def) => defs.push((def, ls.span())),
+                    Err(err) => {
+                        self.raise(err.at(ls.span()));
+                        return;
+                    }
+                }
+            }
+        }
+
+        let first = scope.names.len();
+        scope.names.extend(defs.iter().map(|(def, _)| def.name));
+        let outer = self.enter(scope);
+        if !defs.is_empty() {
+            self.emit(Op::Declare(defs.len()));
+        }
+        for (slot, (def, span)) in (first..).zip(defs) {
+            let outer = self.span.clone();
+            if span.is_some() {
+                self.span = span;
+            }
+            self.compile(&def.value, false);
+            if def.function {
+                self.emit(Op::Name(def.name));
+            }
+            self.emit(Op::Define(slot));
+            self.span = outer;
+        }
+        self.sequence(&forms[count..], tail);
+        self.scope = outer;
+    }
+
+    // A definition in top level code that is not a top level form itself, like
+    // one in the body of a when, binds its name at the top level when it runs
+    fn global_definition(&mut self, ls: &Rc<List>, tail: bool) -> Result<(), Error> {
+        let def = Definition::new(ls)?;
+        self.compile(&def.value, false);
+        if def.function {
+            self.emit(Op::Name(def.name));
+        }
+        self.emit(Op::DefineGlobal(def.name));
+        self.constant(Val::None, tail);
+        Ok(())
+    }
+
+    // A leading form of a body with the macro uses at its head expanded, so
+    // that a macro can expand to a definition. A use that fails to expand is
+    // left for when it is compiled.
+    fn expand_head(&self, form: &Val, scope: &Rc<Scope>) -> Val {
+        let mut form = form.clone();
+        loop {
+            let m = match &form {
+                Val::List(ls) => match ls.head() {
+                    Val::Symbol(s) if scope.address(*s).is_none() => match self.vm.global(s) {
+                        Some(Val::Macro(m)) => m,
+                        _ => return form,
+                    },
+                    _ => return form,
+                },
+                _ => return form,
+            };
+            let frames = self.vm.frame_count();
+            match self.vm.expand_macro(m, form.clone(), &Some(scope.clone())) {
+                Ok(expansion) => form = Macro::at_use(expansion, &form),
+                Err(_) => {
+                    self.vm.truncate_frames(frames);
+                    return form;
+                }
+            }
+        }
+    }
+
+    // (loop [(var init) ...] body ...) evaluates the body with the vars bound to
+    // the inits. A recur in tail position of the body evaluates to the values
+    // for the next iteration, which are bound in new slots before going back
+    // to the start of the body.
+    fn loop_form(&mut self, list: Rc<List>, tail: bool) -> Result<(), Error> {
+        let (names, inits) = loop_bindings(list.head())?;
+        let body: Vec<Val> = list
+            .tail()
+            .map(|ls| ls.values().collect())
+            .unwrap_or_default();
+        for form in body.iter() {
+            self.check_recur(form, names.len(), true)?;
+        }
+
+        for init in inits.iter() {
+            self.compile(init, false);
+        }
+        let start = self.emit(Op::Scope(names.len()));
+        let scope = Scope {
+            is_loop: true,
+            ..Scope::new(names, self.scope.clone())
+        };
+        self.definitions(scope, &body, false);
+        self.emit(Op::EndLoop(start));
+        self.ret(tail);
+        Ok(())
+    }
+
+    // Loop bodies are checked before they are compiled, so a recur only has to
+    // make sure that it is inside a loop and not in a procedure called by one.
+    fn recur(&mut self, list: Option<Rc<List>>, tail: bool) -> Result<(), Error> {
+        if !self.scope.as_ref().is_some_and(|scope| scope.in_loop()) {
+            let form = Val::from(List::new(Val::symbol("recur"), list));
+            return Err(Error::ArgType("recur", "in tail position of a loop", form));
+        }
+        let args: Vec<Val> = list.map(|ls| ls.values().collect()).unwrap_or_default();
+        for arg in args.iter() {
+            self.compile(arg, false);
+        }
+        self.emit(Op::Recur(args.len()));
+        self.ret(tail);
+        Ok(())
+    }
+
+    fn macro_expand(&mut self, list: Rc<List>, tail: bool) -> Result<(), Error> {
+        // The expr must be a list with a symbol at the head, which is checked
+        // to be bound to a macro when the form is evaluated
+        let expr = list.first().ok_or(Error::Arity("macro-expand"))?;
+        match &expr {
+            Val::List(ls) if matches!(ls.head(), Val::Symbol(_)) => {
+                self.emit(Op::MacroExpand(expr.clone(), self.scope.clone()));
+                self.ret(tail);
+                Ok(())
+            }
+            _ => Err(Error::ArgType("macro-expand", "(macro expr)", expr.clone())),
+        }
+    }
+
+    // (try body ... (catch [:kind] e handler ...) ... (finally cleanup ...))
+    //
+    // If evaluating the body raises an error the first catch clause with a
+    // matching kind, or with no kind at all, binds the error as a value and
+    // evaluates its handler. The finally forms are always evaluated last, but
+    // their value is discarded. Since the body must be evaluated here in
+    // order to catch its errors, nothing in a try is in tail position.
+    fn try_form(&mut self, list: Rc<List>, tail: bool) -> Result<(), Error> {
+        let mut body = Vec::new();
+        let mut catches = Vec::new();
+        let mut finally: Option<Vec<Val>> = None;
+        for form in list.values() {
+            let clause = match &form {
+                Val::List(ls) => match ls.head() {
+                    Val::Symbol(s) if s.is("catch") || s.is("finally") => Some(ls.clone()),
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            match clause {
+                Some(_) if finally.is_some() => {
+                    return Err(Error::ArgType("try", "last clause to be finally", form))
+                }
+                Some(ls) if ls.head() == &Val::symbol("finally") => {
+                    finally = Some(
+                        ls.tail()
+                            .map(|ls| ls.values().collect())
+                            .unwrap_or_default(),
+                    );
+                }
+                Some(ls) => catches.push(CatchClause::new(ls)?),
+                None if catches.is_empty() && finally.is_none() => body.push(form),
+                None => return Err(Error::ArgType("try", "catch or finally clause", form)),
+            }
+        }
+        if catches.is_empty() && finally.is_none() {
+            self.sequence(&body, false);
+            self.ret(tail);
+            return Ok(());
+        }
+
+        // Errors raised by a handler are not caught by the same try, but the
+        // finally forms are still evaluated before they are raised again.
+        let mut handler = Handler::default();
+        let try_at = self.emit(Op::Try(Rc::new(Handler::default())));
+        self.sequence(&body, false);
+        self.emit(Op::EndTry);
+        let mut exits = vec![self.emit(Op::Jump(0))];
+        let mut handler_tries = Vec::new();
+        for clause in catches {
+            handler
+                .catches
+                .push((clause.kind.clone(), self.code.next()));
+            if finally.is_some() {
+                handler_tries.push(self.emit(Op::Try(Rc::new(Handler::default()))));
+            }
+            self.emit(Op::Scope(1));
+            let outer = self.enter(Scope::new(vec![clause.name], self.scope.clone()));
+            let forms: Vec<Val> = clause.body().collect();
+            self.sequence(&forms, false);
+            self.scope = outer;
+            self.emit(Op::Unscope);
+            if finally.is_some() {
+                self.emit(Op::EndTry);
+            }
+            exits.push(self.emit(Op::Jump(0)));
+        }
+        for exit in exits {
+            self.patch(exit, Op::Jump(self.code.next()));
+        }
+
+        if let Some(forms) = finally {
+            self.sequence(&forms, false);
+            self.emit(Op::Pop);
+            let end = self.emit(Op::Jump(0));
+            let fallback = self.code.next();
+            self.sequence(&forms, false);
+            self.emit(Op::Pop);
+            self.emit(Op::Rethrow);
+            self.patch(end, Op::Jump(self.code.next()));
+
+            handler.fallback = Some(fallback);
+            for at in handler_tries {
+                let handler = Handler {
+                    catches: vec![],
+                    fallback: Some(fallback),
+                };
+                self.patch(at, Op::Try(Rc::new(handler)));
+            }
+        }
+        self.patch(try_at, Op::Try(Rc::new(handler)));
+        self.ret(tail);
+        Ok(())
+    }
+
+    // Build the quasiquoted template, evaluating the forms that are unquoted at
+    // depth 1. Nested quasiquotes increase the depth, so their unquotes are left
+    // in place for when the inner template is evaluated.
+    fn quasi(&mut self, form: &Val, depth: usize) -> Result<(), Error> {
+        match form {
+            Val::List(ls) => match unquoted(ls) {
+                Some(("unquote", arg)) if depth == 1 => self.compile(&arg, false),
+                Some(("unquote-splicing", _)) if depth == 1 => {
+                    return Err(Error::ArgType(
+                        "unquote-splicing",
+                        "used inside a list or vector",
+                        form.clone(),
+                    ))
+                }
+                Some((name, arg)) => {
+                    self.constant(Val::symbol(name), false);
+                    self.quasi(&arg, depth - 1)?;
+                    self.emit(Op::Build(Shape::List, 2));
+                }
+                None => match ls.head() {
+                    Val::Symbol(s) if s.is("quasiquote") && ls.len() == 2 => {
+                        self.constant(ls.head().clone(), false);
+                        self.quasi(&ls.second().unwrap(), depth + 1)?;
+                        self.emit(Op::Build(Shape::List, 2));
+                    }
+                    _ => self.quasi_items(ls.values().collect(), Shape::List, depth)?,
+                },
+            },
+            Val::Vector(v) => {
+                let shape = match v.borrow().is_tuple() {
+                    true => Shape::Tuple,
+                    false => Shape::Vector,
+                };
+                let items = v.borrow().values().cloned().collect();
+                self.quasi_items(items, shape, depth)?;
+            }
+            Val::Map(m) => {
+                let entries: Vec<(Val, Val)> = m
+                    .borrow()
+                    .entries()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+                for (k, v) in entries.iter() {
+                    self.quasi(k, depth)?;
+                    self.quasi(v, depth)?;
+                }
+                let shape = match m.borrow().is_dict() {
+                    true => Shape::Dict,
+                    false => Shape::Map,
+                };
+                self.emit(Op::Build(shape, entries.len()));
+            }
+            _ => self.constant(form.clone(), false),
+        }
+        Ok(())
+    }
+
+    // The items of a list or vector in a template, with the values of any
+    // unquote-splicing forms at depth 1 spliced into them.
+    fn quasi_items(&mut self, items: Vec<Val>, shape: Shape, depth: usize) -> Result<(), Error> {
+        let mut spliced = Vec::with_capacity(items.len());
+        for item in items.iter() {
+            match item {
+                Val::List(ls) if depth == 1 => match unquoted(ls) {
+                    Some(("unquote-splicing", arg)) => {
+                        self.compile(&arg, false);
+                        spliced.push(true);
+                    }
+                    _ => {
+                        self.quasi(item, depth)?;
+                        spliced.push(false);
+                    }
+                },
+                _ => {
+                    self.quasi(item, depth)?;
+                    spliced.push(false);
+                }
+            }
+        }
+        match spliced.contains(&true) {
+            true => self.emit(Op::Splice(shape, spliced.into())),
+            false => self.emit(Op::Build(shape, items.len())),
+        };
+        Ok(())
+    }
+
+    // Calls //
+
+    // A use of a macro is expanded now if it can be. If expanding it raises an
+    // error it is compiled like a call, which expands it again when it is
+    // evaluated so the error is raised then.
+    fn call(&mut self, ls: &Rc<List>, tail: bool) -> Result<(), Error> {
+        let head = ls.head().clone();
+        let mac = match &head {
+            Val::Symbol(s) => self.macro_for(s),
+            Val::Macro(m) => Some(m.clone()),
+            _ => None,
+        };
+        if let Some(m) = mac {
+            let frames = self.vm.frame_count();
+            match self
+                .vm
+                .expand_macro(m.clone(), Val::List(ls.clone()), &self.scope)
+            {
+                Ok(expansion) => {
+                    // Only a macro used by name can be rebound
+                    let name = match &head {
+                        Val::Symbol(s) => Some(*s),
+                        _ => None,
+                    };
+                    let (scope, version) = (self.scope.clone(), self.vm.macro_version());
+                    let macro_use = |after| {
+                        let call = MacroCall::new(ls.clone(), scope.clone(), tail, after);
+                        let name = name?;
+                        Some(MacroUse::new(call, name, m.clone(), version))
+                    };
+                    let guard = macro_use(0).map(|used| self.emit(Op::Expanded(Rc::new(used))));
+                    self.compile(&expansion, tail);
+                    if let (Some(at), Some(used)) = (guard, macro_use(self.code.next())) {
+                        self.patch(at, Op::Expanded(Rc::new(used)));
+                    }
+                    return Ok(());
+                }
+                Err(_) => self.vm.truncate_frames(frames),
+            }
+        }
+
+        // Only a lambda form is sure not to evaluate to a macro
+        let check = match &head {
+            Val::Symbol(_) | Val::Macro(_) => true,
+            Val::List(f) => !matches!(f.head(), Val::Symbol(s) if s.is("lambda")),
+            Val::Procedure(_) | Val::Closure(_) => false,
+            _ => return Err(Error::NotAProcedure(head)),
+        };
+        self.compile(&head, false);
+        let scope = self.scope.clone();
+        let macro_call = |after| MacroCall::new(ls.clone(), scope.clone(), tail, after);
+        let macro_check = match check {
+            true => Some(self.emit(Op::MacroCheck(Rc::new(macro_call(0))))),
+            false => None,
+        };
+        let args: Vec<Val> = ls
+            .tail()
+            .map(|ls| ls.values().collect())
+            .unwrap_or_default();
+        // A name that is not bound yet may be a macro by the time the call is
+        // evaluated, so its arguments may not be forms to evaluate at all
+        let unsure = match &head {
+            Val::Symbol(s) if !self.is_local(s) => {
+                matches!(self.vm.global(s), None | Some(Val::Macro(_)))
+            }
+            Val::Macro(_) => true,
+            _ => false,
+        };
+        self.unsure += unsure as usize;
+        for arg in args.iter() {
+            self.compile(arg, false);
+        }
+        self.unsure -= unsure as usize;
+        match tail {
+            true => self.emit(Op::TailCall(args.len(), ls.clone())),
+            false => self.emit(Op::Call(args.len(), ls.clone())),
+        };
+        if let Some(at) = macro_check {
+            let after = self.code.next();
+            self.patch(at, Op::MacroCheck(Rc::new(macro_call(after))));
+        }
+        Ok(())
+    }
+
+    // The macro a symbol is bound to at the top level, unless it is bound in a
+    // scope of the code, where its value is not known until it runs.
+    fn macro_for(&self, sym: &Sym) -> Option<Rc<Macro>> {
+        if self.is_local(sym) {
+            return None;
+        }
+        match self.vm.global(sym) {
+            Some(Val::Macro(m)) => Some(m),
+            _ => None,
+        }
+    }
+
+    fn is_local(&self, sym: &Sym) -> bool {
+        self.scope
+            .as_ref()
+            .is_some_and(|scope| scope.address(*sym).is_some())
+    }
+
+    // Check that each recur in a form from a loop body is in tail position and
+    // has a value for each loop var. Macros in tail position are expanded to
+    // find the tail positions in their expansions. Nested loops check their
+    // own bodies when they are compiled.
+    fn check_recur(&self, form: &Val, arity: usize, tail: bool) -> Result<(), Error> {
+        let ls = match form {
+            Val::List(ls) => ls,
+            Val::Vector(vec) => {
+                for val in vec.borrow().values() {
+                    self.check_recur(val, arity, false)?;
+                }
+                return Ok(());
+            }
+            Val::Map(map) => {
+                for (key, val) in map.borrow().entries() {
+                    self.check_recur(key, arity, false)?;
+                    self.check_recur(val, arity, false)?;
+                }
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+
+        let forms: Vec<Val> = ls.values().collect();
+        let check_all = |forms: &[Val], tail_last: bool| -> Result<(), Error> {
+            for (i, form) in forms.iter().enumerate() {
+                let tail = tail_last && i == forms.len() - 1;
+                self.check_recur(form, arity, tail)?;
+            }
+            Ok(())
+        };
+        let result = match ls.head() {
+            Val::Symbol(s) if s.is("recur") => {
+                if !tail {
+                    Err(Error::ArgType(
+                        "recur",
+                        "in tail position of a loop",
+                        form.clone(),
+                    ))
+                } else if forms.len() - 1 != arity {
+                    Err(Error::Arity("recur"))
+                } else {
+                    check_all(&forms[1..], false)
+                }
+            }
+            Val::Symbol(s) if s.is("quote") || s.is("quasiquote") => Ok(()),
+            Val::Symbol(s) if s.is("loop") => check_all(&forms[1..2.min(forms.len())], false),
+            Val::Symbol(s) if s.is("if") => {
+                check_all(&forms[1..2.min(forms.len())], false)?;
+                for form in forms.iter().skip(2) {
+                    self.check_recur(form, arity, tail)?;
+                }
+                Ok(())
+            }
+            Val::Symbol(s) if s.is("do") => check_all(&forms[1..], tail),
+            Val::Symbol(s) if tail && !s.is_special_form() => match self.macro_for(s) {
+                Some(m) => {
+                    let expansion = self.vm.expand_macro(m, form.clone(), &self.scope)?;
+                    self.check_recur(&expansion, arity, tail)
+                }
+                None => check_all(&forms, false),
+            },
+            // The body of a lambda that is applied right away, like the
+            // expansion of a let, is in the same tail position as the call.
+            Val::List(f) if matches!(f.head(), Val::Symbol(s) if s.is("lambda")) => {
+                let lambda: Vec<Val> = f.values().collect();
+                check_all(&lambda[2.min(lambda.len())..], tail)?;
+                check_all(&forms[1..], false)
+            }
+            _ => check_all(&forms, false),
+        };
+        result.map_err(|e| e.at(ls.span()))
+    }
+
+    // Expansion //
+
+    // Expand every macro use in a form, and the uses in their expansions,
+    // without compiling it. Names are bound in scopes the same way as when the
+    // form is compiled, so a local name that shadows a macro is not expanded.
+    // Quoted forms and the definitions of macros are left as they are.
+    pub fn expand_all(mut self, form: &Val) -> Result<Val, Error> {
+        self.expand(form)
+    }
+
+    fn expand(&mut self, form: &Val) -> Result<Val, Error> {
+        let ls = match form {
+            Val::List(ls) => ls,
+            Val::Vector(v) => {
+                let vals: Vec<Val> = v.borrow().values().cloned().collect();
+                let vals = self.expand_each(&vals)?;
+                return Ok(match v.borrow().is_tuple() {
+                    true => Val::from(Vector::tuple(vals)),
+                    false => Val::from(vals),
+                });
+            }
+            Val::Map(m) => {
+                let mut entries = Vec::new();
+                for (k, v) in m.borrow().entries() {
+                    entries.push((k.clone(), v.clone()));
+                }
+                let mut expanded = Vec::with_capacity(entries.len());
+                for (k, v) in entries {
+                    expanded.push((self.expand(&k)?, self.expand(&v)?));
+                }
+                return Ok(match m.borrow().is_dict() {
+                    true => Val::from(Map::dict(&expanded)?),
+                    false => Val::from(Map::map(&expanded)?),
+                });
+            }
+            _ => return Ok(form.clone()),
+        };
+
+        let forms: Vec<Val> = ls.values().collect();
+        let mac = match ls.head() {
+            Val::Symbol(s) if s.is_special_form() => None,
+            Val::Symbol(s) => self.macro_for(s),
+            Val::Macro(m) => Some(m.clone()),
+            _ => None,
+        };
+        if let Some(m) = mac {
+            let expansion = self.vm.expand_macro(m, form.clone(), &self.scope)?;
+            return self.expand(&expansion);
+        }
+
+        let expanded = match ls.head() {
+            Val::Symbol(s) if s.is("quote") || s.is("quasiquote") || s.is("macro-expand") => {
+                return Ok(form.clone())
+            }
+            Val::Symbol(s) if s.is("defmacro") || s.is("macro-rules") => return Ok(form.clone()),
+            Val::Symbol(s) if s.is("lambda") => match (ls.second(), ls.tail()) {
+                (Some(Val::List(_)), _) => {
+                    let mut expanded = vec![forms[0].clone()];
+                    for clause in forms[1..].iter() {
+                        expanded.push(self.expand_clause(clause)?);
+                    }
+                    expanded
+                }
+                (_, Some(clause)) => {
+                    let mut expanded = vec![forms[0].clone()];
+                    if let Val::List(clause) = self.expand_clause(&Val::List(clause))? {
+                        expanded.extend(clause.values());
+                    }
+                    expanded
+                }
+                (_, None) => return Ok(form.clone()),
+            },
+            Val::Symbol(s) if s.is("def") || s.is("defn") => self.expand_definition(ls, &forms)?,
+            Val::Symbol(s) if s.is("loop") => match forms.get(1).map(loop_bindings) {
+                Some(Ok((names, inits))) => {
+                    let bindings: Vec<Val> = names
+                        .iter()
+                        .zip(self.expand_each(&inits)?)
+                        .map(|(name, init)| Val::list_from_vec(&[Val::Symbol(*name), init]))
+                        .collect();
+                    let scope = Scope {
+                        is_loop: true,
+                        ..Scope::new(names, self.scope.clone())
+                    };
+                    let mut expanded = vec![forms[0].clone(), Val::from(bindings)];
+                    expanded.extend(self.expand_body(scope, &forms[2..])?);
+                    expanded
+                }
+                _ => return Ok(form.clone()),
+            },
+            Val::Symbol(s) if s.is("try") => {
+                let mut expanded = vec![forms[0].clone()];
+                for form in forms[1..].iter() {
+                    expanded.push(self.expand_try_clause(form)?);
+                }
+                expanded
+            }
+            Val::Symbol(s) if s.is_special_form() => {
+                let mut expanded = vec![forms[0].clone()];
+                expanded.extend(self.expand_each(&forms[1..])?);
+                expanded
+            }
+            _ => self.expand_each(&forms)?,
+        };
+        Ok(Macro::at_use(Val::list_from_vec(&expanded), form))
+    }
+
+    fn expand_each(&mut self, forms: &[Val]) -> Result<Vec<Val>, Error> {
+        forms.iter().map(|form| self.expand(form)).collect()
+    }
+
+    // Expand the body of a ([params ...] body ...) clause in the scope of its
+    // params. A clause with bad params is left as it is.
+    fn expand_clause(&mut self, clause: &Val) -> Result<Val, Error> {
+        let ls = match clause {
+            Val::List(ls) => ls,
+            _ => return Ok(clause.clone()),
+        };
+        let formals = match lambda_clause(ls) {
+            Ok(clause) => clause.formals,
+            Err(_) => return Ok(clause.clone()),
+        };
+        let forms: Vec<Val> = ls.values().collect();
+        let scope = Scope::new(formals.names(), self.scope.clone());
+        let mut expanded = vec![forms[0].clone()];
+        expanded.extend(self.expand_body(scope, &forms[1..])?);
+        Ok(Macro::at_use(Val::list_from_vec(&expanded), clause))
+    }
+
+    // The body of a try and its catch and finally clauses. A catch clause's
+    // handler is expanded in the scope of the name the error is bound to.
+    fn expand_try_clause(&mut self, form: &Val) -> Result<Val, Error> {
+        let ls = match form {
+            Val::List(ls) => ls,
+            _ => return Ok(form.clone()),
+        };
+        let forms: Vec<Val> = ls.values().collect();
+        let expanded = match ls.head() {
+            Val::Symbol(s) if s.is("catch") => match CatchClause::new(ls.clone()) {
+                Ok(clause) => {
+                    let start = forms.len() - clause.body().count();
+                    let scope = Scope::new(vec![clause.name], self.scope.clone());
+                    let mut expanded = forms[..start].to_vec();
+                    expanded.extend(self.expand_body(scope, &forms[start..])?);
+                    expanded
+                }
+                Err(_) => return Ok(form.clone()),
+            },
+            Val::Symbol(s) if s.is("finally") => {
+                let mut expanded = vec![forms[0].clone()];
+                expanded.extend(self.expand_each(&forms[1..])?);
+                expanded
+            }
+            _ => return self.expand(form),
+        };
+        Ok(Macro::at_use(Val::list_from_vec(&expanded), form))
+    }
+
+    // (def name value), (def (name params ...) body ...) or
+    // (defn name [params ...] body ...)
+    fn expand_definition(&mut self, ls: &List, forms: &[Val]) -> Result<Vec<Val>, Error> {
+        let def = match Definition::new(ls) {
+            Ok(def) => def,
+            Err(_) => return Ok(forms.to_vec()),
+        };
+        if !def.function {
+            let mut expanded = forms[..2].to_vec();
+            expanded.extend(self.expand_each(&forms[2..])?);
+            return Ok(expanded);
+        }
+        let params = match &def.value {
+            Val::List(lambda) => lambda.second(),
+            _ => None,
+        };
+        let formals = match params.map(|params| lambda_clause(&List::new(params, None))) {
+            Some(Ok(clause)) => clause.formals,
+            _ => return Ok(forms.to_vec()),
+        };
+        // The body starts after the params of defn or the signature of def
+        let start = match ls.head() {
+            Val::Symbol(s) if s.is("defn") => 3,
+            _ => 2,
+        };
+        let scope = Scope::new(formals.names(), self.scope.clone());
+        let mut expanded = forms[..start].to_vec();
+        expanded.extend(self.expand_body(scope, &forms[start..])?);
+        Ok(expanded)
+    }
+
+    // Expand forms in a new scope, which their leading definitions are bound in
+    // as well
+    fn expand_body(&mut self, mut scope: Scope, forms: &[Val]) -> Result<Vec<Val>, Error> {
+        for form in forms.iter().take_while(|form| is_define(form)) {
+            if let Val::List(ls) = form {
+                if let Ok(def) = Definition::new(ls) {
+                    scope.names.push(def.name);
+                }
+            }
+        }
+        let outer = self.enter(scope);
+        let expanded = self.expand_each(forms);
+        self.scope = outer;
+        expanded
+    }
+}
+
+// If the list is an (unquote x) or (unquote-splicing x) form get its name and
+// the unquoted form.
+fn unquoted(list: &List) -> Option<(&'static str, Val)> {
+    if list.len() != 2 {
+        return None;
+    }
+    match list.head() {
+        Val::Symbol(s) if s.is("unquote") => Some(("unquote", list.second()?)),
+        Val::Symbol(s) if s.is("unquote-splicing") => Some(("unquote-splicing", list.second()?)),
+        _ => None,
+    }
+}
+
+// Lambda /////////////////////////////////////////////////////////////////////
+
+// A lambda with a list of clauses instead of params, like
+// (lambda ([x] body ...) ([x y] body ...)), has a clause for each number of
+// arguments it can be applied to.
+pub fn lambda_clauses(list: &List) -> Result<Vec<Clause>, Error> {
+    if !matches!(list.head(), Val::List(_)) {
+        return Ok(vec![lambda_clause(list)?]);
+    }
+    let mut clauses: Vec<Clause> = Vec::new();
+    for form in list.values() {
+        let (clause, span) = match form {
+            Val::List(ref ls) => (lambda_clause(ls)?, ls.span()),
+            val => {
+                return Err(Error::ArgType(
+                    "lambda",
+                    "a ([params ...] body ...) clause",
+                    val,
+                ))
+            }
+        };
+        if shadowed(&clause.formals, &clauses) {
+            return Err(Error::ShadowedClause(form).at(span));
+        }
+        clauses.push(clause);
+    }
+    Ok(clauses)
+}
+
+// Whether every argument count the formals accept is accepted by an earlier
+// clause, so they could never be applied. Past the most required and &opt
+// params of any of them, more arguments do not change what formals accept.
+fn shadowed(formals: &Formals, earlier: &[Clause]) -> bool {
+    let most = earlier
+        .iter()
+        .map(|clause| &clause.formals)
+        .chain([formals])
+        .map(|formals| {
+            let arity = formals.arity();
+            arity.required + arity.optional
+        })
+        .max()
+        .unwrap_or(0);
+    (0..=most + 1)
+        .filter(|args| formals.accepts(*args))
+        .all(|args| earlier.iter().any(|clause| clause.formals.accepts(args)))
+}
+
+fn lambda_clause(list: &List) -> Result<Clause, Error> {
+    let formals = match list.head().clone() {
+        Val::Symbol(s) => Formals::Collect(s),
+        Val::Vector(vec) => Formals::from_vector(vec.borrow().values(), list.head())?,
+        Val::Empty => Formals::Fixed(vec![]),
+        _ => {
+            return Err(Error::ArgType(
+                "lambda",
+                "symbol or vector of params",
+                list.head().clone(),
+            ))
+        }
+    };
+    Ok(Clause::new(formals, list.tail()))
+}
+
+// Loop ///////////////////////////////////////////////////////////////////////
+
+// The vars and init forms of the [(var init) ...] bindings of a loop
+fn loop_bindings(bindings: &Val) -> Result<(Vec<Sym>, Vec<Val>), Error> {
+    let vec = match bindings {
+        Val::Vector(vec) => vec,
+        val => return Err(Error::ArgType("loop", "vector of bindings", val.clone())),
+    };
+    let mut names = Vec::new();
+    let mut inits = Vec::new();
+    for binding in vec.borrow().values() {
+        match binding {
+            Val::List(ls) if ls.len() == 2 => match (ls.head(), ls.second()) {
+                (Val::Symbol(s), Some(init)) => {
+                    names.push(*s);
+                    inits.push(init);
+                }
+                _ => return Err(Error::ArgType("loop", "(var init)", binding.clone())),
+            },
+            _ => return Err(Error::ArgType("loop", "(var init)", binding.clone())),
+        }
+    }
+    Ok((names, inits))
+}
+
+// Definition ////////////////////////////////////////////////////////////////
+
+// A definition made by (def name value), (def (name params ...) body ...) or
+// (defn name [params ...] body ...). The value of a function definition is a
+// lambda form.
+pub struct Definition {
+    pub name: Sym,
+    pub value: Val,
+    pub function: bool,
+}
+
+impl Definition {
+    pub fn new(list: &List) -> Result<Definition, Error> {
+        let form = match list.head() {
+            Val::Symbol(s) if s.is("defn") => "defn",
+            _ => "def",
+        };
+        let ls = list.tail().ok_or(Error::Arity(form))?;
+
+        match (form, ls.head().clone()) {
+            ("def", Val::Symbol(name)) => Ok(Definition {
+                name,
+                value: ls.second().ok_or(Error::Arity("def"))?,
+                function: false,
+            }),
+            // (def (name params ...) body ...)
+            ("def", Val::List(sig)) => {
+                let name = match sig.head() {
+                    Val::Symbol(s) => *s,
+                    val => return Err(Error::ArgType("def", "symbol", val.clone())),
+                };
+                let params = match sig.tail() {
+                    Some(params) => params.values().collect(),
+                    None => vec![],
+                };
+                Ok(Definition::function(name, Val::from(params), ls.tail()))
+            }
+            // (defn name [params ...] body ...)
+            ("defn", Val::Symbol(name)) => {
+                let rest = ls.tail().ok_or(Error::Arity("defn"))?;
+                Ok(Definition::function(name, rest.head().clone(), rest.tail()))
+            }
+            (form, val) => Err(Error::ArgType(form, "symbol", val)),
+        }
+    }
+
+    fn function(name: Sym, params: Val, body: Option<Rc<List>>) -> Definition {
+        Definition {
+            name,
+            value: Val::from(List::new(
+                Val::symbol("lambda"),
+                Some(Rc::new(List::new(params, body))),
+            )),
+            function: true,
+        }
+    }
+}
+
+// Whether a form is a definition
+fn is_define(form: &Val) -> bool {
+    match form {
+        Val::List(ls) => matches!(ls.head(), Val::Symbol(s) if s.is("def") || s.is("defn")),
+        _ => false,
+    }
+}
+
+// Catch Clause ///////////////////////////////////////////////////////////////
+
+// (catch e handler ...) or (catch :kind e handler ...)
+struct CatchClause {
+    kind: Option<Val>,
+    name: Sym,
+    body: Option<Rc<List>>,
+}
+
+impl CatchClause {
+    fn new(clause: Rc<List>) -> Result<CatchClause, Error> {
+        let form = Val::List(clause.clone());
+        let (kind, rest) = match clause.second() {
+            Some(kind @ Val::Keyword(_)) => (Some(kind), clause.tail().and_then(|ls| ls.tail())),
+            _ => (None, clause.tail()),
+        };
+        let rest = rest.ok_or(Error::ArgType(
+            "catch",
+            "(catch [:kind] symbol body ...)",
+            form.clone(),
+        ))?;
+        let name = match rest.head() {
+            Val::Symbol(s) => *s,
+            _ => {
+                return Err(Error::ArgType(
+                    "catch",
+                    "(catch [:kind] symbol body ...)",
+                    form,
+                ))
+            }
+        };
+        Ok(CatchClause {
+            kind,
+            name,
+            body: rest.tail(),
+        })
+    }
+
+    fn body(&self) -> impl Iterator<Item = Val> {
+        self.body.clone().into_iter().flat_map(|ls| ls.values())
+    }
+}
+
+// Tests //////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpret::null_env;
+    use crate::io::StringReader;
+
+    fn compile(text: &str) -> Code {
+        let env = null_env();
+        let mut vm = Vm::new(env.clone());
+        let forms = StringReader::new(text).read_forms().unwrap();
+        let (last, defs) = forms.split_last().unwrap();
+        for form in defs {
+            vm.eval_top_level(form.clone()).unwrap();
+        }
+        Compiler::new(&vm, None).form(last)
+    }
+
+    #[test]
+    fn test_call() {
+        let code = compile("(f 1)");
+        assert!(matches!(
+            &code.ops[..],
+            [
+                Op::Global(_),
+                Op::MacroCheck(call),
+                Op::Const(0),
+                Op::TailCall(1, _)
+            ] if call.tail && call.after == 4
+        ));
+        assert_eq!(code.consts, vec![Val::from(1)]);
+    }
+
+    #[test]
+    fn test_if() {
+        let code = compile("(if #t 1 2)");
+        assert!(matches!(
+            code.ops[..],
+            [
+                Op::Const(_),
+                Op::JumpIfFalse(4),
+                Op::Const(_),
+                Op::Return,
+                Op::Const(_),
+                Op::Return
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_locals_are_addressed_by_slot() {
+        let code = compile("(loop [(a 1)] (loop [(b 2) (a 3)] (f a b)))");
+        assert!(matches!(
+            &code.ops[..],
+            [
+                Op::Const(_),
+                Op::Scope(1),
+                Op::Const(_),
+                Op::Const(_),
+                Op::Scope(2),
+                Op::Global(f),
+                Op::MacroCheck(..),
+                Op::Local(_, 0, 1),
+                Op::Local(_, 0, 0),
+                Op::Call(2, _),
+                Op::EndLoop(4),
+                Op::EndLoop(1),
+                Op::Return
+            ] if f.is("f")
+        ));
+    }
+
+    #[test]
+    fn test_macros_are_expanded() {
+        let code = compile("(defmacro one [] 1) (one)");
+        assert!(matches!(
+            &code.ops[..],
+            [Op::Expanded(used), Op::Const(0), Op::Return]
+                if used.name.is("one") && used.call.after == 3
+        ));
+        assert_eq!(code.consts, vec![Val::from(1)]);
+    }
+
+    #[test]
+    fn test_errors_are_raised_when_evaluated() {
+        let code = compile("(if)");
+        assert!(matches!(code.ops[..], [Op::Raise(Error::Arity("if"))]));
+    }
+}
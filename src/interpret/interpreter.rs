@@ -1,4 +1,4 @@
-use crate::data::ExternalRep;
+use crate::data::{Error, ExternalRep, Val};
 use crate::interpret::{null_env, Vm};
 use crate::io::reader::StringReader;
 use crate::rusp_libs::std::RUSP_LIB_STD;
@@ -6,8 +6,6 @@ use crate::rusp_libs::std::RUSP_LIB_STD;
 // TODO setup proper stdlib with core procedure environment creation and with
 // library procedure/macro rusp files. These files need to end with rusp rather
 // than scm and not be named SCM_* anymore.
-// TODO errors need to be printed as display and not debug when their to_string
-// or display rep is implemented.
 
 pub struct Interpreter {
     ready: bool,
@@ -34,18 +32,51 @@ impl Interpreter {
         if !self.ready {
             panic!("not initialized");
         }
+        let forms = match StringReader::new(text).read_spanned_forms() {
+            Ok(forms) => forms,
+            Err(e) => return e.to_string(),
+        };
+        let mut result = Val::None;
+        for (form, span) in forms {
+            match self.vm.eval_top_level_at(form, Some(span)) {
+                Ok(val) => result = val,
+                Err(e) => return e.to_string(),
+            }
+        }
+        result.to_external()
+    }
+
+    // Evaluate a whole program, like the contents of a script file, with args
+    // bound to *command-line-args* as a list of strings. The path is used as
+    // the source in the location of errors. Each form is evaluated as soon as
+    // it is read, so the forms before one that cannot be read still run.
+    pub fn eval_script(&mut self, path: &str, text: &str, args: &[String]) -> Result<Val, Error> {
+        if !self.ready {
+            panic!("not initialized");
+        }
+        let args: Vec<Val> = args.iter().map(|a| Val::from(a.as_str())).collect();
+        self.vm
+            .define("*command-line-args*", Val::list_from_vec(&args));
+
+        let mut reader = StringReader::with_source(text, path);
+        let mut result = Val::None;
+        while let Some((form, span)) = reader.read_next_spanned()? {
+            result = self.vm.eval_top_level_at(form, Some(span))?;
+        }
+        Ok(result)
+    }
 
-        match StringReader::new(text).read_forms() {
-            Ok(forms) => match self.vm.eval_forms(&forms) {
-                Ok(val) => val.to_external(),
-                Err(e) => format!("{:?}", e),
-            },
-            Err(e) => format!("{:?}", e),
+    // Evaluate forms that have already been read. The same vm is used for every
+    // call so definitions persist between calls.
+    pub fn eval_forms(&mut self, forms: &[Val]) -> Result<Val, Error> {
+        if !self.ready {
+            panic!("not initialized");
         }
+        self.vm.eval_forms(forms)
     }
 
     fn load_std(&mut self) {
-        let lib_std_forms = match StringReader::new(RUSP_LIB_STD).read_forms() {
+        let lib_std_forms = match StringReader::with_source(RUSP_LIB_STD, "std.rusp").read_forms() {
             Ok(forms) => forms,
             Err(e) => panic!("failed to read RUSP_LIB_STD: Err: {:?}", e),
         };
@@ -47,8 +47,23 @@ pub fn null_env() -> Environ {
         new_proc("<", procedures::math::less_than),
         new_proc(">", procedures::math::greater_than),
         new_proc("+", procedures::math::sum),
+        new_proc("-", procedures::math::difference),
+        new_proc("*", procedures::math::product),
+        new_proc("/", procedures::math::quotient),
+        // equivalence
+        new_proc("eqv?", procedures::equivalence::is_eqv),
         // errors
         new_proc("throw", procedures::errors::throw),
+        new_proc("error?", procedures::errors::is_error),
+        new_proc("error-kind", procedures::errors::error_kind),
+        new_proc("error-message", procedures::errors::error_message),
+        new_proc("error-irritants", procedures::errors::error_irritants),
+        new_proc("error->map", procedures::errors::error_to_map),
+        // io
+        new_proc("print", procedures::io::print),
+        new_proc("println", procedures::io::println),
+        // symbols
+        new_proc("gensym", procedures::symbols::gensym),
         // vars
         new_proc("var", procedures::vars::var),
         new_proc("deref", procedures::vars::deref),
@@ -57,11 +72,8 @@ pub fn null_env() -> Environ {
     Rc::new(env)
 }
 
-fn new_proc(name: &str, func: fn(&[Val]) -> Result<Val, Error>) -> (Rc<Str>, Val) {
-    (
-        Rc::new(Str::from(name)),
-        Val::from(Procedure::new(name, func)),
-    )
+fn new_proc(name: &str, func: fn(&[Val]) -> Result<Val, Error>) -> (Sym, Val) {
+    (Sym::from(name), Val::from(Procedure::new(name, func)))
 }
 
 /*
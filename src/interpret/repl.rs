@@ -0,0 +1,150 @@
+use crate::data::{ExternalRep, ScanError, Val};
+use crate::interpret::Interpreter;
+use crate::io::reader::StringReader;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::path::PathBuf;
+
+const PROMPT: &str = "rusp> ";
+const CONTINUE_PROMPT: &str = "...   ";
+const HISTORY_FILE: &str = ".rusp_history";
+
+// Repl ///////////////////////////////////////////////////////////////////////
+
+// Lines are collected into a buffer until the buffer reads as complete forms.
+// An incomplete form is reported by the reader as an Eof error, in which case
+// we keep prompting for more lines. Once the forms are complete they are
+// evaluated one at a time with the same interpreter, so definitions persist
+// for the whole session.
+pub struct Repl {
+    interpreter: Interpreter,
+    history: Option<PathBuf>,
+}
+
+pub enum Input {
+    Forms(Vec<Val>),
+    Incomplete,
+    Invalid(ScanError),
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl {
+            interpreter: Interpreter::new().init(),
+            history: std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE)),
+        }
+    }
+
+    pub fn run(&mut self) -> Result<(), ReadlineError> {
+        let mut editor = DefaultEditor::new()?;
+        if let Some(path) = &self.history {
+            // A missing history file just means this is the first session
+            editor.load_history(path).ok();
+        }
+
+        let mut buffer = String::new();
+        loop {
+            let prompt = if buffer.is_empty() {
+                PROMPT
+            } else {
+                CONTINUE_PROMPT
+            };
+
+            match editor.readline(prompt) {
+                Ok(line) => {
+                    buffer.push_str(&line);
+                    buffer.push('\n');
+                    match Repl::read(&buffer) {
+                        Input::Incomplete => continue,
+                        Input::Invalid(e) => eprintln!("{e}"),
+                        Input::Forms(forms) => {
+                            editor.add_history_entry(buffer.trim_end())?;
+                            for line in self.eval(&forms) {
+                                println!("{line}");
+                            }
+                        }
+                    }
+                    buffer.clear();
+                }
+                // Ctrl-C abandons the current input, but not the session
+                Err(ReadlineError::Interrupted) => buffer.clear(),
+                // Ctrl-D ends the session
+                Err(ReadlineError::Eof) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        if let Some(path) = &self.history {
+            editor.save_history(path)?;
+        }
+        Ok(())
+    }
+
+    pub fn read(text: &str) -> Input {
+        match StringReader::new(text).read_forms() {
+            Ok(forms) => Input::Forms(forms),
+            Err(e) if e.is_eof() => Input::Incomplete,
+            Err(e) => Input::Invalid(e),
+        }
+    }
+
+    // Evaluates each form and returns the printed result of each. Evaluation
+    // stops at the first error, which is the last line returned.
+    pub fn eval(&mut self, forms: &[Val]) -> Vec<String> {
+        let mut lines = Vec::with_capacity(forms.len());
+        for form in forms.iter() {
+            match self.interpreter.eval_forms(std::slice::from_ref(form)) {
+                Ok(val) => lines.push(val.to_external()),
+                Err(e) => {
+                    lines.push(e.to_string());
+                    break;
+                }
+            }
+        }
+        lines
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Repl {
+        Repl::new()
+    }
+}
+
+// Testing ////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reading_incomplete_input() {
+        assert!(matches!(Repl::read("(def a\n"), Input::Incomplete));
+        assert!(matches!(Repl::read("(def a [1 2\n3]\n"), Input::Incomplete));
+        assert!(matches!(Repl::read("\"hello"), Input::Incomplete));
+        assert!(matches!(Repl::read("(def a 5))"), Input::Invalid(_)));
+        match Repl::read("(def a 5)\n(+ a 1)\n") {
+            Input::Forms(forms) => assert_eq!(forms.len(), 2),
+            _ => panic!("expected complete forms"),
+        }
+    }
+
+    #[test]
+    fn test_eval_keeps_definitions_between_inputs() {
+        let mut repl = Repl::new();
+        let forms = match Repl::read("(def a 5) (+ a 1)") {
+            Input::Forms(forms) => forms,
+            _ => panic!("expected complete forms"),
+        };
+        assert_eq!(repl.eval(&forms), vec!["#none", "6"]);
+
+        let forms = match Repl::read("(+ a 10) (undeclared-thing) 99") {
+            Input::Forms(forms) => forms,
+            _ => panic!("expected complete forms"),
+        };
+        let lines = repl.eval(&forms);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "15");
+        assert_eq!(lines[1], "1:11: Error: undeclared symbol: undeclared-thing");
+    }
+}
@@ -1,7 +1,8 @@
 use crate::data::*;
+use crate::interpret::compiler::{lambda_clauses, Compiler, Definition};
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
-// TODO implement proper defines for functions
 // TODO make sure everything in the vm is tested
 // TODO the reader needs to create the right collections given the new syntax
 // TODO ensure that syntax expectations are appropriate, i.e. if we use vectors
@@ -17,19 +18,41 @@ use std::rc::Rc;
 // TODO ensure that all structures are fully tested and working as expected with
 // as many methods for easy procedure creation as possible.
 
+// Forms are compiled into code that is run on a stack machine. The frames are
+// the procedures and closures currently being applied, which make up the
+// backtrace of an error. A closure applied in tail position replaces the frame
+// of the closure that applied it, so a loop written with tail calls only ever
+// has one frame. When an error is raised the frames are left in place so that
+// the error can be given a backtrace once it reaches the top level.
+//
+// The macro version counts how many times a macro bound at the top level has
+// been rebound, so compiled uses of macros know when to check their names.
 pub struct Vm {
     env: Environ,
+    frames: RefCell<Vec<Frame>>,
+    macro_version: Cell<usize>,
 }
 
 impl Vm {
     pub fn new(env: Environ) -> Vm {
-        Vm { env: env }
+        let mut vm = Vm {
+            env,
+            frames: RefCell::new(Vec::new()),
+            macro_version: Cell::new(0),
+        };
+        vm.define("macroexpand-all", expand_all_closure(vm.env.clone()));
+        vm
+    }
+
+    // Bind a value in the top level environment, as if by def.
+    pub fn define(&mut self, name: &str, val: Val) {
+        self.bind_global(Sym::from(name), val);
     }
 
     // Evaluate forms /////////////////////////////////////////////////////////
 
     pub fn eval_forms(&mut self, forms: &[Val]) -> Result<Val, Error> {
-        if forms.len() < 1 {
+        if forms.is_empty() {
             return Ok(Val::None);
         }
 
@@ -40,86 +63,139 @@ impl Vm {
     }
 
     pub fn eval_top_level(&mut self, form: Val) -> Result<Val, Error> {
-        //println!("{form}");
-        match form {
-            Val::List(ref ls) => match ls.head() {
-                Val::Symbol(s) if s.is("def") => self.eval_define(ls.tail()),
-                Val::Symbol(s) if s.is("macro-rules") => self.eval_macro_rules(ls.tail()),
-                _ => self.eval(form, self.env.clone()),
+        self.eval_top_level_at(form, None)
+    }
+
+    // Evaluate a form read from source at the span, which locates errors in a
+    // form that does not carry its own span, like a symbol
+    pub fn eval_top_level_at(&mut self, form: Val, span: Option<Rc<Span>>) -> Result<Val, Error> {
+        self.top_level(form, span).map_err(|e| {
+            let frames = self.frames.take();
+            if frames.is_empty() {
+                e
+            } else {
+                Error::Trace(frames, Box::new(e))
+            }
+        })
+    }
+
+    // A macro use at the top level is expanded before it is evaluated, so that
+    // it can expand to a definition, and each form in a do is a top level form
+    fn top_level(&mut self, form: Val, span: Option<Rc<Span>>) -> Result<Val, Error> {
+        let ls = match form {
+            Val::List(ref ls) => ls.clone(),
+            _ => return self.eval(form, self.env.clone(), span),
+        };
+        match ls.head() {
+            Val::Symbol(s) if s.is("def") || s.is("defn") => {
+                self.eval_define(&ls).map_err(|e| e.at(ls.span()))
+            }
+            Val::Symbol(s) if s.is("macro-rules") => self
+                .eval_macro_rules(ls.tail())
+                .map_err(|e| e.at(ls.span())),
+            Val::Symbol(s) if s.is("defmacro") => {
+                self.eval_defmacro(ls.tail()).map_err(|e| e.at(ls.span()))
+            }
+            Val::Symbol(s) if s.is("do") => {
+                let mut val = Val::None;
+                for form in ls.tail().into_iter().flat_map(|ls| ls.values()) {
+                    let span = ls.element_span(&form);
+                    val = self.top_level(form, span)?;
+                }
+                Ok(val)
+            }
+            Val::Symbol(s) => match self.global(s) {
+                Some(Val::Macro(m)) => {
+                    let expansion = self
+                        .expand_macro(m, form.clone(), &None)
+                        .map_err(|e| e.at(ls.span()))?;
+                    self.top_level(Macro::at_use(expansion, &form), None)
+                }
+                _ => self.eval(form, self.env.clone(), None),
             },
-            _ => self.eval(form, self.env.clone()),
+            _ => self.eval(form, self.env.clone(), None),
         }
     }
 
-    fn eval(&self, form: Val, env: Environ) -> Result<Val, Error> {
-        let mut expr = form.clone();
-        let mut env = env.clone();
+    // Compile a form and run it at the top level
+    fn eval(&self, form: Val, env: Environ, span: Option<Rc<Span>>) -> Result<Val, Error> {
+        let code = Compiler::new(self, None).at(span).form(&form);
+        self.run(Rc::new(code), env, None)
+    }
 
+    fn run(&self, code: Rc<Code>, env: Environ, slots: Option<Rc<Slots>>) -> Result<Val, Error> {
+        let mut machine = Machine {
+            vm: self,
+            stack: Vec::new(),
+            calls: vec![Activation {
+                code,
+                pc: 0,
+                env,
+                slots,
+                frames: self.frame_count(),
+            }],
+            handlers: Vec::new(),
+        };
         loop {
-            expr = match expr {
-                Val::Symbol(s) => match env.lookup(&s) {
-                    Some(v) => v,
-                    None => return Err(Error::Undeclared(s.to_string())),
-                },
-                Val::List(ref ls) => match ls.head().clone() {
-                    Val::Symbol(s) if self.is_special_form(s.clone()) => {
-                        self.eval_special(s.to_string().as_str(), ls.tail(), env)?
-                    }
-                    Val::Symbol(_) | Val::Procedure(_) | Val::Closure(_) | Val::List(_) => {
-                        self.eval_call(ls.clone(), env)?
-                    }
-                    Val::Macro(m) => m.expand(expr, env.clone())?,
-                    head => return Err(Error::NotAProcedure(head)),
-                },
-                // Unlike some other literals [] cannot be syntactic sugar for (vector ...)
-                // Because we expect vectors to be in the data when read as
-                // we use them for structure in forms like lambda. This means that
-                // we have to evaluate them when we see them in other places
-                // like we would any other form. If all forms they are used for
-                // as structure are special forms or macros this should be fine
-                // but I expect there will be times where vectors will end up
-                // being evaluated more than once, which will be a problem.
-                Val::Vector(v) => {
-                    let evalled = v
-                        .borrow()
-                        .values()
-                        .map(|val| self.eval(val.clone(), env.clone()))
-                        .collect::<Result<Vec<Val>, Error>>()?;
-                    if v.borrow().is_tuple() {
-                        Val::from(Vector::tuple(evalled))
-                    } else {
-                        Val::from(Vector::from(evalled))
-                    }
-                }
-                Val::TailCall(_) | Val::Undefined => panic!("should not be evaluated: {expr}"),
-                _ => expr,
-            };
-
-            // if it is a tail call resolve it, otherwise return the result
-            match expr {
-                Val::TailCall(tail) => {
-                    env = tail.env.clone();
-                    expr = tail.expr.clone();
-                }
-                _ => return Ok(expr),
+            match machine.execute() {
+                Ok(val) => return Ok(val),
+                Err(err) => machine.unwind(err)?,
             }
         }
     }
 
     // Eval Helpers ///////////////////////////////////////////////////////////
 
-    fn eval_define(&mut self, list: Option<Rc<List>>) -> Result<Val, Error> {
-        let ls = list.ok_or(Error::Arity("def"))?;
-        let name = match ls.first().ok_or(Error::Arity("def"))? {
-            Val::Symbol(s) => s,
-            val => return Err(Error::ArgType("def", "symbol", val)),
-        };
-        let value = ls.second().ok_or(Error::Arity("def"))?;
+    // Look up a name that is not bound in a scope of the code. A symbol renamed
+    // by a macro expansion that is not bound by the expansion refers to the
+    // symbol it was renamed from where the macro was defined. Macros can only
+    // be defined at the top level, so that is where it is. A definition's
+    // name is bound to Undefined until its value is evaluated.
+    fn lookup(&self, sym: &Sym, env: &Environ) -> Result<Val, Error> {
+        match env.lookup(sym) {
+            Some(Val::Undefined) => return Err(Error::Unassigned(sym.to_string())),
+            Some(val) => return Ok(val),
+            None => (),
+        }
+        match sym.renamed_from() {
+            Some(name) => self
+                .env
+                .lookup(&name)
+                .ok_or(Error::Undeclared(name.to_string())),
+            None => Err(Error::Undeclared(sym.to_string())),
+        }
+    }
+
+    // The value a symbol has at the top level
+    pub(super) fn global(&self, sym: &Sym) -> Option<Val> {
+        match self.env.lookup(sym) {
+            Some(val) => Some(val),
+            None => self.env.lookup(&sym.renamed_from()?),
+        }
+    }
+
+    // Every binding at the top level is made here
+    fn bind_global(&self, name: Sym, val: Val) {
+        if let Some(Val::Macro(_)) = self.env.lookup(&name) {
+            self.macro_version.set(self.macro_version.get() + 1);
+        }
+        self.env.insert(name, val);
+    }
+
+    pub(super) fn macro_version(&self) -> usize {
+        self.macro_version.get()
+    }
 
-        // TODO this ignores a defn or scheme like function define
-        self.env.insert(name.clone(), Val::Undefined);
-        self.env
-            .insert(name.clone(), self.eval(value, self.env.clone())?);
+    fn eval_define(&mut self, list: &List) -> Result<Val, Error> {
+        let def = Definition::new(list)?;
+        self.bind_global(def.name, Val::Undefined);
+        let span = list.element_span(&def.value);
+        let val = match self.eval(def.value.clone(), self.env.clone(), span)? {
+            // Function definitions give their closure the name they define
+            Val::Closure(c) if def.function => named(&c, def.name),
+            val => val,
+        };
+        self.bind_global(def.name, val);
         Ok(Val::None)
     }
 
@@ -138,193 +214,725 @@ impl Vm {
                 .borrow()
                 .values()
                 .map(|val| match val {
-                    Val::Symbol(s) => Ok(s.clone()),
-                    _ => return Err(Error::ArgType("macro-rules", "symbol", val.clone())),
+                    Val::Symbol(s) => Ok(*s),
+                    _ => Err(Error::ArgType("macro-rules", "symbol", val.clone())),
                 })
-                .collect::<Result<Vec<Rc<Str>>, Error>>()?,
+                .collect::<Result<Vec<Sym>, Error>>()?,
             val => return Err(Error::ArgType("macro-rules", "symbol", val)),
         };
 
         // get list of rules and create the macro
         let rules = args.tail().ok_or(Error::Arity("macro-rules"))?;
-        let mac = Val::from(Macro::new(name.clone(), &syms, rules)?);
-        self.env.insert(name, mac);
+        let mac = Val::from(Macro::new(name, &syms, rules, self.env.clone())?);
+        self.bind_global(name, mac);
         Ok(Val::None)
     }
 
-    fn is_special_form(&self, s: Rc<Str>) -> bool {
-        match s.to_string().as_str() {
-            "if" | "lambda" | "macro-expand" => true,
-            _ => false,
-        }
+    // (defmacro name [params] body ...)
+    //
+    // The params and body are the same as a lambda's. When the macro is used the
+    // closure is applied to the unevaluated arguments and the value it returns
+    // is evaluated in place of the use.
+    fn eval_defmacro(&mut self, list: Option<Rc<List>>) -> Result<Val, Error> {
+        let ls = list.ok_or(Error::Arity("defmacro"))?;
+        let name = match ls.first().ok_or(Error::Arity("defmacro"))? {
+            Val::Symbol(s) => s,
+            val => return Err(Error::ArgType("defmacro", "symbol", val)),
+        };
+        let lambda = ls.tail().ok_or(Error::Arity("defmacro"))?;
+
+        let closure = Closure::with_clauses(
+            Some(Str::clone(&name.name())),
+            self.env.clone(),
+            lambda_clauses(&lambda)?,
+        );
+        let mac = Macro::procedural(name, Rc::new(closure));
+        self.bind_global(name, Val::from(mac));
+        Ok(Val::None)
     }
 
-    fn eval_special(&self, name: &str, list: Option<Rc<List>>, env: Environ) -> Result<Val, Error> {
-        match list {
-            Some(ls) => match name {
-                "if" => self.eval_if(ls, env),
-                "lambda" => self.eval_lambda(ls, env),
-                "macro-expand" => self.eval_macro_expand(ls, env),
-                _ => panic!("not a special form: {name}"),
-            },
-            None => panic!("empty special form"),
-        }
+    // Macros with rules expand themselves, but a transformer is a closure that
+    // has to be applied to the unevaluated arguments of the use. Rules only
+    // match a literal where it is not bound, so they are given the names bound
+    // in the scope of the use.
+    pub(super) fn expand_macro(
+        &self,
+        m: Rc<Macro>,
+        expr: Val,
+        scope: &Option<Rc<Scope>>,
+    ) -> Result<Val, Error> {
+        let (transformer, list) = match (m.transformer(), &expr) {
+            (Some(transformer), Val::List(list)) => (transformer, list.clone()),
+            _ => {
+                let env = match scope {
+                    Some(scope) => {
+                        let env = Env::add_scope(self.env.clone());
+                        for name in scope.all_names() {
+                            env.insert(name, Val::Undefined);
+                        }
+                        env
+                    }
+                    None => self.env.clone(),
+                };
+                return m.expand(expr, env);
+            }
+        };
+
+        let args: Vec<Val> = match list.tail() {
+            Some(ls) => ls.values().collect(),
+            None => vec![],
+        };
+        let base = self.frame_count();
+        self.push_frame(&m.name().to_string(), list);
+        let (code, slots) = self.bind(&transformer, args)?;
+        let expansion = self.run(code, transformer.env.clone(), Some(slots))?;
+        self.truncate_frames(base);
+        Ok(Macro::at_use(expansion, &expr))
     }
 
-    fn eval_if(&self, list: Rc<List>, env: Environ) -> Result<Val, Error> {
-        let cond = list.first().ok_or(Error::Arity("if"))?;
-        let true_branch = list.second().ok_or(Error::Arity("if"))?;
-        let false_branch = list.third();
+    fn push_frame(&self, name: &str, form: Rc<List>) {
+        self.frames.borrow_mut().push(Frame::new(name, form));
+    }
 
-        if self.eval(cond, env.clone())?.is_true() {
-            Ok(Val::tail_call(env, true_branch))
-        } else {
-            match false_branch {
-                Some(expr) => Ok(Val::tail_call(env, expr)),
-                None => Ok(Val::None),
-            }
-        }
+    pub(super) fn frame_count(&self) -> usize {
+        self.frames.borrow().len()
     }
 
-    fn eval_lambda(&self, list: Rc<List>, env: Environ) -> Result<Val, Error> {
-        let formals = match list.head().clone() {
-            Val::Symbol(s) => Formals::Collect(s),
-            Val::Vector(vec) => self.formals_from_vector(vec.borrow().values())?,
-            Val::Empty => Formals::Fixed(vec![]),
-            _ => {
-                return Err(Error::ArgType(
-                    "lambda",
-                    "symbol or vector of symbols",
-                    list.head().clone(),
+    pub(super) fn truncate_frames(&self, len: usize) {
+        self.frames.borrow_mut().truncate(len);
+    }
+
+    // Applications ///////////////////////////////////////////////////////////
+
+    // Bind the arguments of a closure to its params in the slots of a new
+    // scope, and get the code for the body of the clause they were bound for.
+    // The slots are filled in the same order as the names of the formals, so
+    // when the params are all symbols the arguments are the slots.
+    fn bind(&self, closure: &Closure, args: Vec<Val>) -> Result<(Rc<Code>, Rc<Slots>), Error> {
+        let clause = match closure.clause(args.len()) {
+            Some(clause) => clause,
+            None => {
+                return Err(Error::ClosureArity(
+                    closure_name(closure),
+                    closure.expected_args(),
+                    args.len(),
                 ))
             }
         };
-        Ok(Val::from(Closure::new(None, env, formals, list.tail())))
-    }
-
-    fn eval_macro_expand(&self, list: Rc<List>, env: Environ) -> Result<Val, Error> {
-        // got list = (expr)
-        // extract expr from the list
-        let expr = list.first().ok_or(Error::Arity("macro-expand"))?;
-        // get the macro at the start
-        // TODO gotta be a cleaner way to deal with this nested expr
-        match &expr {
-            // The expr must also be a list to be a macro application
-            Val::List(ls) => match ls.first() {
-                // The first element must be a symbol that is bound to a macro
-                // in the environment
-                Some(Val::Symbol(s)) => {
-                    match env.lookup(&s).ok_or(Error::ArgType(
-                        "macro-expand",
-                        "macro",
-                        expr.clone(),
-                    ))? {
-                        Val::Macro(m) => m.expand(expr, env),
-                        _ => Err(Error::ArgType("macro-expand", "macro", expr.clone())),
-                    }
-                }
-                _ => Err(Error::ArgType("macro-expand", "(macro expr)", expr.clone())),
-            },
-            _ => Err(Error::ArgType("macro-expand", "(macro expr)", expr.clone())),
+        let symbols = |params: &[Param]| params.iter().all(|p| matches!(p, Param::Symbol(_)));
+        let slots = match &clause.formals {
+            Formals::Fixed(params) if symbols(params) => {
+                Rc::new(Slots::new(args, closure.slots.clone()))
+            }
+            _ => {
+                let slots = Rc::new(Slots::new(
+                    Vec::with_capacity(args.len()),
+                    closure.slots.clone(),
+                ));
+                let bound = Bound {
+                    closure,
+                    clause,
+                    slots: &slots,
+                };
+                self.bind_args(&bound, &args)?;
+                slots
+            }
+        };
+
+        Ok((self.clause_code(clause)?, slots))
+    }
+
+    // The code for the body of a clause, which is compiled the first time a
+    // lambda with the clause is evaluated. A body that fails to compile is
+    // an error there rather than when it is applied, and is not kept.
+    fn clause_code(&self, clause: &Clause) -> Result<Rc<Code>, Error> {
+        if let Some(code) = clause.code() {
+            return Ok(code);
         }
+        let code = Compiler::new(self, clause.scope.clone()).body(clause)?;
+        Ok(clause.set_code(Rc::new(code)))
     }
 
-    fn formals_from_vector(&self, args: std::slice::Iter<'_, Val>) -> Result<Formals, Error> {
-        let mut vec = Vec::new();
-        let mut rest = false;
-        for val in args {
-            match val {
-                Val::Symbol(s) => {
-                    if rest {
-                        return Ok(Formals::Rest(vec, s.clone()));
-                    } else if s.is(".") {
-                        rest = true
-                    } else {
-                        vec.push(s.clone())
+    // Bind the arguments to their parameters according to the formals of the
+    // clause they were bound for
+    fn bind_args(&self, bound: &Bound, args: &[Val]) -> Result<(), Error> {
+        let slots = bound.slots;
+        match &bound.clause.formals {
+            Formals::Collect(_) => slots.push(Val::list_from_vec(args)),
+            Formals::Fixed(params) => {
+                for (param, arg) in params.iter().zip(args.iter()) {
+                    self.bind_param(param, arg.clone(), bound)?;
+                }
+            }
+            Formals::Rest(params, _) => {
+                for (param, arg) in params.iter().zip(args.iter()) {
+                    self.bind_param(param, arg.clone(), bound)?;
+                }
+                slots.push(Val::list_from_vec(&args[params.len()..]));
+            }
+            Formals::Optional(params, opts, keys) => {
+                for (param, arg) in params.iter().zip(args.iter()) {
+                    self.bind_param(param, arg.clone(), bound)?;
+                }
+                // Optional arguments end at the first keyword for a &key param
+                let mut rest = args[params.len()..].iter().peekable();
+                for opt in opts.iter() {
+                    let is_key = |arg: &&Val| keys.iter().any(|key| key.key == **arg);
+                    let val = match (rest.next_if(|arg| !is_key(arg)), &opt.default) {
+                        (Some(arg), _) => arg.clone(),
+                        (None, Some(default)) => self.eval_default(default, bound)?,
+                        (None, None) => Val::None,
+                    };
+                    slots.push(val);
+                }
+
+                // A keyword given more than once has its last value
+                let rest: Vec<&Val> = rest.collect();
+                let mut vals: Vec<Option<Val>> = vec![None; keys.len()];
+                for pair in rest.chunks(2) {
+                    match keys.iter().position(|key| key.key == *pair[0]) {
+                        Some(i) if pair.len() == 2 => vals[i] = Some(pair[1].clone()),
+                        _ => {
+                            return Err(Error::BadKeywordArg(
+                                closure_name(bound.closure),
+                                pair[0].clone(),
+                            ))
+                        }
                     }
                 }
-                _ => return Err(Error::BadArg(1)),
-            };
+                for (key, val) in keys.iter().zip(vals) {
+                    let val = match (val, &key.default) {
+                        (Some(val), _) => val,
+                        (None, Some(default)) => self.eval_default(default, bound)?,
+                        (None, None) => Val::None,
+                    };
+                    slots.push(val);
+                }
+            }
+        };
+        Ok(())
+    }
+
+    // Bind a param to its argument, destructuring the argument if the param is
+    // a pattern.
+    fn bind_param(&self, param: &Param, arg: Val, bound: &Bound) -> Result<(), Error> {
+        match param {
+            Param::Symbol(_) => bound.slots.push(arg),
+            Param::Seq(params, rest, form) => {
+                let vals: Vec<Val> = match &arg {
+                    Val::List(ls) => ls.values().collect(),
+                    Val::Vector(vec) => vec.borrow().values().cloned().collect(),
+                    Val::Empty => vec![],
+                    _ => return Err(Error::Destructure(form.clone(), arg)),
+                };
+                if vals.len() < params.len() || (rest.is_none() && vals.len() > params.len()) {
+                    return Err(Error::Destructure(form.clone(), arg));
+                }
+                for (param, val) in params.iter().zip(vals.iter()) {
+                    self.bind_param(param, val.clone(), bound)?;
+                }
+                if rest.is_some() {
+                    bound.slots.push(Val::list_from_vec(&vals[params.len()..]));
+                }
+            }
+            Param::Keys(keys, name_as, form) => {
+                let map = match &arg {
+                    Val::Map(map) => map.clone(),
+                    _ => return Err(Error::Destructure(form.clone(), arg)),
+                };
+                for key in keys.iter() {
+                    let found = map.borrow().get(key.key.clone());
+                    let val = match (found, &key.default) {
+                        (Some(val), _) => val,
+                        (None, Some(default)) => self.eval_default(default, bound)?,
+                        (None, None) => return Err(Error::Destructure(form.clone(), arg)),
+                    };
+                    bound.slots.push(val);
+                }
+                if name_as.is_some() {
+                    bound.slots.push(arg);
+                }
+            }
         }
-        Ok(Formals::Fixed(vec))
+        Ok(())
     }
 
-    fn eval_call(&self, list: Rc<List>, env: Environ) -> Result<Val, Error> {
-        let first = match list.first() {
-            Some(val) => self.eval(val, env.clone())?,
-            None => panic!("function call should not be empty: {list}"),
+    // Defaults are evaluated in the closure's scope, so they can refer to the
+    // params bound before them.
+    fn eval_default(&self, default: &Val, bound: &Bound) -> Result<Val, Error> {
+        let slot = bound.slots.len();
+        let code = match bound.clause.default_code(slot) {
+            Some(code) => code,
+            None => {
+                let mut names = bound.clause.formals.names();
+                names.truncate(slot);
+                let scope = Scope::new(names, bound.clause.scope.clone());
+                let code = Compiler::new(self, Some(Rc::new(scope))).form(default);
+                bound.clause.set_default_code(slot, Rc::new(code))
+            }
         };
+        self.run(code, bound.closure.env.clone(), Some(bound.slots.clone()))
+    }
+}
 
-        match first {
-            Val::Macro(m) => Ok(Val::tail_call(
-                env.clone(),
-                m.expand(Val::List(list), env.clone())?,
-            )),
-            _ => {
-                let args = match list.tail() {
-                    Some(ls) => ls
-                        .values()
-                        .map(|val| self.eval(val, env.clone()))
-                        .collect::<Result<Vec<Val>, Error>>()?,
-                    None => vec![],
-                };
-                match first {
-                    Val::Procedure(p) => {
-                        let f = p.func;
-                        f(&args)
+// macroexpand-all needs the vm to expand macros, so it is a closure whose code
+// is the op that does, rather than a builtin procedure
+fn expand_all_closure(env: Environ) -> Val {
+    let form = Sym::from("form");
+    let clause = Clause::new(Formals::Fixed(vec![Param::Symbol(form)]), None);
+    let mut code = Code::new();
+    code.emit(Op::Local(form, 0, 0), None);
+    code.emit(Op::ExpandAll, None);
+    code.emit(Op::Return, None);
+    clause.set_code(Rc::new(code));
+    let name = Some(Str::from("macroexpand-all"));
+    Val::from(Closure::with_clauses(name, env, vec![clause]))
+}
+
+// The clause of a closure that arguments are being bound for
+struct Bound<'a> {
+    closure: &'a Closure,
+    clause: &'a Clause,
+    slots: &'a Rc<Slots>,
+}
+
+// The name of a closure for errors
+fn closure_name(closure: &Closure) -> String {
+    match &closure.name {
+        Some(name) => name.to_string(),
+        None => "lambda".to_string(),
+    }
+}
+
+// A closure with the name of the definition it is the value of
+fn named(closure: &Closure, name: Sym) -> Val {
+    Val::from(Closure {
+        name: Some(Str::clone(&name.name())),
+        ..closure.clone()
+    })
+}
+
+// The name of the frame for a closure applied by a call form
+fn frame_name(closure: &Closure, form: &List) -> String {
+    match (&closure.name, form.head()) {
+        (Some(name), _) => name.to_string(),
+        (None, Val::Symbol(s)) => match s.renamed_from() {
+            Some(name) => name.to_string(),
+            None => s.to_string(),
+        },
+        (None, _) => "lambda".to_string(),
+    }
+}
+
+// Machine ////////////////////////////////////////////////////////////////////
+
+// The state of running some code. There is an activation for the code being
+// run and for each closure it is applying, with the current one last. Their
+// ops share the stack.
+struct Machine<'a> {
+    vm: &'a Vm,
+    stack: Vec<Val>,
+    calls: Vec<Activation>,
+    handlers: Vec<ActiveHandler>,
+}
+
+struct Activation {
+    code: Rc<Code>,
+    pc: usize,
+    // Where names that are not in a scope of the code are looked up
+    env: Environ,
+    slots: Option<Rc<Slots>>,
+    // The number of frames to go back to when it returns
+    frames: usize,
+}
+
+// A handler for a try whose body is being evaluated, with the state to go back
+// to when it handles an error
+struct ActiveHandler {
+    handler: Rc<Handler>,
+    calls: usize,
+    stack: usize,
+    slots: Option<Rc<Slots>>,
+    frames: usize,
+}
+
+impl Machine<'_> {
+    // Run ops until the first activation returns or an error is raised
+    fn execute(&mut self) -> Result<Val, Error> {
+        loop {
+            let act = self.act();
+            let code = act.code.clone();
+            let op = &code.ops[act.pc];
+            act.pc += 1;
+
+            match op {
+                Op::Const(idx) => self.stack.push(code.consts[*idx].clone()),
+                Op::Local(name, depth, slot) => match self.slots().get(*depth, *slot) {
+                    Val::Undefined => return Err(Error::Unassigned(name.to_string())),
+                    val => self.stack.push(val),
+                },
+                Op::Global(sym) => {
+                    let val = self.vm.lookup(sym, &self.act().env)?;
+                    self.stack.push(val);
+                }
+                Op::Declare(count) => {
+                    let slots = self.slots();
+                    for _ in 0..*count {
+                        slots.push(Val::Undefined);
+                    }
+                }
+                Op::Define(slot) => {
+                    let val = self.pop();
+                    self.slots().set(0, *slot, val);
+                }
+                Op::DefineGlobal(sym) => {
+                    let val = self.pop();
+                    self.vm.bind_global(*sym, val);
+                }
+                Op::Name(sym) => match self.pop() {
+                    Val::Closure(c) => self.stack.push(named(&c, *sym)),
+                    val => self.stack.push(val),
+                },
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Jump(pc) => self.act().pc = *pc,
+                Op::JumpIfFalse(pc) => {
+                    if !self.pop().is_true() {
+                        self.act().pc = *pc;
+                    }
+                }
+                Op::Lambda(clauses) => {
+                    for clause in clauses.iter() {
+                        self.vm.clause_code(clause)?;
+                    }
+                    let act = self.act();
+                    let closure = Closure {
+                        name: None,
+                        env: act.env.clone(),
+                        slots: act.slots.clone(),
+                        clauses: clauses.clone(),
+                    };
+                    self.stack.push(Val::from(closure));
+                }
+                Op::MacroCheck(call) => {
+                    if let Some(Val::Macro(m)) = self.stack.last() {
+                        let m = m.clone();
+                        self.pop();
+                        self.expand(m, call)?;
+                    }
+                }
+                Op::Expanded(used) => {
+                    if !used.is_checked(self.vm.macro_version()) {
+                        self.check_expanded(used);
+                    }
+                }
+                Op::MacroExpand(expr, scope) => {
+                    let val = self.macro_expand(expr, scope)?;
+                    self.stack.push(val);
+                }
+                Op::ExpandAll => {
+                    let form = self.pop();
+                    let val = Compiler::new(self.vm, None).expand_all(&form)?;
+                    self.stack.push(val);
+                }
+                Op::Call(argc, form) => self.call(*argc, form, false)?,
+                Op::TailCall(argc, form) => {
+                    self.call(*argc, form, true)?;
+                    if self.calls.is_empty() {
+                        return Ok(self.pop());
+                    }
+                }
+                Op::Return => {
+                    let val = self.pop();
+                    self.ret(val);
+                    if self.calls.is_empty() {
+                        return Ok(self.pop());
                     }
-                    Val::Closure(c) => self.apply_closure(c.clone(), &args),
-                    _ => Err(Error::NotAProcedure(first)),
                 }
+                Op::Scope(count) => {
+                    let vals = self.pop_n(*count);
+                    let act = self.act();
+                    act.slots = Some(Rc::new(Slots::new(vals, act.slots.take())));
+                }
+                Op::Unscope => self.unscope(),
+                Op::Build(shape, count) => {
+                    let vals = match shape {
+                        Shape::Map | Shape::Dict => self.pop_n(count * 2),
+                        _ => self.pop_n(*count),
+                    };
+                    self.stack.push(build(*shape, vals)?);
+                }
+                Op::Splice(shape, spliced) => {
+                    let vals = self.pop_n(spliced.len());
+                    let mut items = Vec::with_capacity(vals.len());
+                    for (val, splice) in vals.into_iter().zip(spliced.iter()) {
+                        match (val, splice) {
+                            (Val::List(ls), true) => items.extend(ls.values()),
+                            (Val::Vector(v), true) => items.extend(v.borrow().values().cloned()),
+                            (Val::Empty, true) => (),
+                            (val, true) => {
+                                return Err(Error::ArgType(
+                                    "unquote-splicing",
+                                    "list or vector",
+                                    val,
+                                ))
+                            }
+                            (val, false) => items.push(val),
+                        }
+                    }
+                    self.stack.push(build(*shape, items)?);
+                }
+                Op::Recur(count) => {
+                    let vals = self.pop_n(*count);
+                    self.stack.push(Val::Recur(Rc::new(vals)));
+                }
+                Op::EndLoop(start) => {
+                    self.unscope();
+                    if let Some(Val::Recur(vals)) = self.stack.last() {
+                        let vals = vals.clone();
+                        self.pop();
+                        self.stack.extend(vals.iter().cloned());
+                        self.act().pc = *start;
+                    }
+                }
+                Op::Try(handler) => {
+                    let handler = ActiveHandler {
+                        handler: handler.clone(),
+                        calls: self.calls.len(),
+                        stack: self.stack.len(),
+                        slots: self.act().slots.clone(),
+                        frames: self.vm.frame_count(),
+                    };
+                    self.handlers.push(handler);
+                }
+                Op::EndTry => {
+                    self.handlers.pop();
+                }
+                Op::Rethrow => match self.pop() {
+                    Val::Error(err) => return Err(Error::clone(&err)),
+                    val => panic!("only errors can be raised again: {val}"),
+                },
+                Op::Raise(err) => return Err(err.clone()),
             }
         }
     }
 
-    // Applications ///////////////////////////////////////////////////////////
+    // Errors are given the location of the op that raised them, or failing
+    // that of the calls they pass back out through, until they reach a try
+    // that handles them or the code that was being run.
+    fn unwind(&mut self, mut err: Error) -> Result<(), Error> {
+        loop {
+            let act = self
+                .calls
+                .last()
+                .expect("an error should be raised by a call");
+            err = err.at(act.code.spans[act.pc - 1].clone());
 
-    fn apply_closure(&self, closure: Rc<Closure>, args: &[Val]) -> Result<Val, Error> {
-        // Bind the arguments to their parameters according to the formals list
-        let bound_env = Env::add_scope(closure.env.clone());
-        match closure.formals.clone() {
-            Formals::Collect(symbol) => {
-                bound_env.insert(symbol.clone(), Val::list_from_vec(args));
-            }
-            Formals::Fixed(params) => {
-                if args.len() >= params.len() {
-                    for (i, symbol) in params.iter().enumerate() {
-                        bound_env.insert(symbol.clone(), args[i].clone());
+            while let Some(active) = self.handlers.pop_if(|h| h.calls == self.calls.len()) {
+                let pc = match active.handler.target(&err) {
+                    Some(pc) => {
+                        // A caught error does not need its backtrace
+                        self.vm.truncate_frames(active.frames);
+                        pc
                     }
-                } else {
-                    return Err(Error::Arity("closure"));
-                }
+                    None => match active.handler.fallback {
+                        Some(pc) => pc,
+                        None => continue,
+                    },
+                };
+                self.stack.truncate(active.stack);
+                self.stack.push(Val::error(err));
+                let act = self.act();
+                act.slots = active.slots;
+                act.pc = pc;
+                return Ok(());
             }
-            Formals::Rest(params, symbol) => {
-                let mut full_params = params.clone();
-                full_params.push(symbol);
 
-                let mut full_args: Vec<Val> = args[..params.len()].into();
-                let rest = Val::list_from_vec(args[params.len()..].into());
-                full_args.push(rest);
+            self.calls.pop();
+            if self.calls.is_empty() {
+                return Err(err);
+            }
+        }
+    }
 
-                for (i, symbol) in full_params.iter().enumerate() {
-                    bound_env.insert(symbol.clone(), full_args[i].clone());
+    // A closure applied in tail position replaces the current activation and
+    // its frame, and a procedure applied in tail position has its value
+    // returned straight away.
+    fn call(&mut self, argc: usize, form: &Rc<List>, tail: bool) -> Result<(), Error> {
+        let args = self.pop_n(argc);
+        match self.pop() {
+            Val::Procedure(p) => {
+                self.vm.push_frame(&p.name.to_string(), form.clone());
+                let f = p.func;
+                let val = f(&args)?;
+                self.vm.frames.borrow_mut().pop();
+                match tail {
+                    true => self.ret(val),
+                    false => self.stack.push(val),
+                }
+                Ok(())
+            }
+            Val::Closure(c) => {
+                let frames = match tail {
+                    true => self.act().frames,
+                    false => self.vm.frame_count(),
+                };
+                self.vm.truncate_frames(frames);
+                self.vm.push_frame(&frame_name(&c, form), form.clone());
+                let (code, slots) = self.vm.bind(&c, args)?;
+                let act = Activation {
+                    code,
+                    pc: 0,
+                    env: c.env.clone(),
+                    slots: Some(slots),
+                    frames,
+                };
+                match tail {
+                    true => *self.act() = act,
+                    false => self.calls.push(act),
                 }
+                Ok(())
+            }
+            val => Err(Error::NotAProcedure(val)),
+        }
+    }
+
+    // Expand a use of a macro that was not known when it was compiled and
+    // evaluate the expansion in place of the call. The call keeps the code for
+    // the expansion, so it is only expanded again for a different macro.
+    fn expand(&mut self, m: Rc<Macro>, call: &MacroCall) -> Result<(), Error> {
+        let code = match call.expansion(&m) {
+            Some(code) => code,
+            None => {
+                let form = Val::List(call.form.clone());
+                let expansion = self.vm.expand_macro(m.clone(), form, &call.scope)?;
+                let code = Compiler::new(self.vm, call.scope.clone()).form(&expansion);
+                let code = Rc::new(code);
+                call.set_expansion(m, code.clone());
+                code
             }
         };
+        self.evaluate_instead(code, call);
+        Ok(())
+    }
 
-        // Eval all body exressions but the last one
-        match closure.body {
-            Some(ref ls) => {
-                for (i, val) in ls.values().enumerate() {
-                    if i == ls.len() - 1 {
-                        return Ok(Val::tail_call(bound_env, val));
-                    } else {
-                        self.eval(val, bound_env.clone())?;
-                    }
+    // Check that the name a macro was used by when the use was compiled is
+    // still bound to the macro. If it is not the use is compiled again, and
+    // that code is evaluated in place of the old expansion.
+    fn check_expanded(&mut self, used: &MacroUse) {
+        let code = match used.recompiled() {
+            Some(code) => code,
+            None => match self.vm.global(&used.name) {
+                Some(Val::Macro(m)) if Rc::ptr_eq(&m, &used.mac) => {
+                    used.check(self.vm.macro_version());
+                    return;
+                }
+                _ => {
+                    let call = &used.call;
+                    let form = Val::List(call.form.clone());
+                    let code = Compiler::new(self.vm, call.scope.clone()).form(&form);
+                    used.set_recompiled(Rc::new(code))
                 }
-                unreachable!();
+            },
+        };
+        self.evaluate_instead(code, &used.call);
+    }
+
+    // Evaluate code in place of a call. In tail position the code replaces the
+    // code being run, otherwise its value is left on the stack for the ops
+    // after the call.
+    fn evaluate_instead(&mut self, code: Rc<Code>, call: &MacroCall) {
+        if call.tail {
+            let act = self.act();
+            act.code = code;
+            act.pc = 0;
+        } else {
+            let act = self.act();
+            act.pc = call.after;
+            let act = Activation {
+                code,
+                pc: 0,
+                env: act.env.clone(),
+                slots: act.slots.clone(),
+                frames: self.vm.frame_count(),
+            };
+            self.calls.push(act);
+        }
+    }
+
+    // (macro-expand (name args ...)) expands the use of the macro bound to name
+    fn macro_expand(&mut self, expr: &Val, scope: &Option<Rc<Scope>>) -> Result<Val, Error> {
+        let not_macro = || Error::ArgType("macro-expand", "macro", expr.clone());
+        let sym = match expr {
+            Val::List(ls) => match ls.head() {
+                Val::Symbol(s) => *s,
+                _ => return Err(not_macro()),
+            },
+            _ => return Err(not_macro()),
+        };
+        let val = match scope.as_ref().and_then(|scope| scope.address(sym)) {
+            Some((depth, slot)) => self.slots().get(depth, slot),
+            None => self
+                .vm
+                .lookup(&sym, &self.act().env)
+                .map_err(|_| not_macro())?,
+        };
+        match val {
+            Val::Macro(m) => self.vm.expand_macro(m, expr.clone(), scope),
+            _ => Err(not_macro()),
+        }
+    }
+
+    // Return a value from the current activation. When it is the first one
+    // the value is left on the stack for the machine to return.
+    fn ret(&mut self, val: Val) {
+        let act = self.calls.pop().expect("should return from an activation");
+        self.vm.truncate_frames(act.frames);
+        self.stack.push(val);
+    }
+
+    fn act(&mut self) -> &mut Activation {
+        self.calls.last_mut().expect("should have an activation")
+    }
+
+    // The slots of the current scope
+    fn slots(&mut self) -> &Slots {
+        self.act()
+            .slots
+            .as_deref()
+            .expect("should be in a scope with slots")
+    }
+
+    fn unscope(&mut self) {
+        let act = self.act();
+        act.slots = act.slots.as_ref().and_then(|slots| slots.outer());
+    }
+
+    fn pop(&mut self) -> Val {
+        self.stack.pop().expect("stack should not be empty")
+    }
+
+    fn pop_n(&mut self, count: usize) -> Vec<Val> {
+        self.stack.split_off(self.stack.len() - count)
+    }
+}
+
+// A new collection from values, with a key and a value for each map entry
+fn build(shape: Shape, vals: Vec<Val>) -> Result<Val, Error> {
+    match shape {
+        Shape::List => Ok(Val::list_from_vec(&vals)),
+        Shape::Vector => Ok(Val::from(Vector::from(vals))),
+        Shape::Tuple => Ok(Val::from(Vector::tuple(vals))),
+        Shape::Map | Shape::Dict => {
+            let mut map = Map::new();
+            for pair in vals.chunks(2) {
+                map.assoc(pair[0].clone(), pair[1].clone())?;
             }
-            None => Ok(Val::None),
+            if shape == Shape::Dict {
+                map.freeze();
+            }
+            Ok(Val::from(map))
         }
     }
 }
@@ -335,6 +943,7 @@ impl Vm {
 mod tests {
     use super::*;
     use crate::interpret::null_env;
+    use crate::io::StringReader;
 
     #[test]
     fn test_self_evaluating_data() {
@@ -397,13 +1006,13 @@ mod tests {
         let mut vm = Vm::new(null_env());
         let expr = Val::list_from_vec(&vec![Val::symbol("def"), Val::symbol("a"), Val::from(99)]);
         assert_eq!(vm.eval_top_level(expr), Ok(Val::None));
-        assert_eq!(vm.env.lookup(&Rc::new(Str::from("a"))), Some(Val::from(99)));
+        assert_eq!(vm.env.lookup(&Sym::from("a")), Some(Val::from(99)));
     }
 
     #[test]
     fn test_evaluating_symbol() {
         let mut vm = Vm::new(null_env());
-        vm.env.insert(Rc::new(Str::from("a")), Val::from(12));
+        vm.env.insert(Sym::from("a"), Val::from(12));
         assert_eq!(vm.eval_top_level(Val::symbol("a")), Ok(Val::from(12)));
         assert_eq!(
             vm.eval_top_level(Val::symbol("e")),
@@ -452,7 +1061,7 @@ mod tests {
         let closure = Val::from(Closure::new(
             None,
             vm.env.clone(),
-            Formals::Fixed(vec![Rc::new(Str::from("x"))]),
+            Formals::Fixed(vec![Param::Symbol(Sym::from("x"))]),
             Some(Rc::new(List::new(Val::symbol("x"), None))),
         ));
         assert_eq!(vm.eval_top_level(expr), Ok(closure));
@@ -465,7 +1074,7 @@ mod tests {
         let closure = Val::from(Closure::new(
             None,
             vm.env.clone(),
-            Formals::Fixed(vec![Rc::new(Str::from("x"))]),
+            Formals::Fixed(vec![Param::Symbol(Sym::from("x"))]),
             Some(Rc::new(List::new(Val::symbol("x"), None))),
         ));
         let expr = Val::list_from_vec(&vec![closure, Val::from(5)]);
@@ -493,8 +1102,8 @@ mod tests {
         let proc = Val::from(Procedure::new("test-proc", |args| {
             Ok(Val::from(Vec::from(args)))
         }));
-        vm.env.insert(Rc::new(Str::from("test-proc")), proc);
-        vm.env.insert(Rc::new(Str::from("a")), Val::from(10));
+        vm.env.insert(Sym::from("test-proc"), proc);
+        vm.env.insert(Sym::from("a"), Val::from(10));
 
         // (test-proc 5 a 15)
         let expr = Val::list_from_vec(&vec![
@@ -509,6 +1118,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_errors_are_located_at_the_innermost_form() {
+        let mut vm = Vm::new(null_env());
+        let forms = StringReader::with_source("(def a\n  (+ 1\n     (car b)))", "a.rusp")
+            .read_forms()
+            .unwrap();
+        match vm.eval_forms(&forms) {
+            Err(Error::Located(span, err)) => {
+                assert_eq!(span.to_string(), "a.rusp:3:7");
+                assert_eq!(*err, Error::Undeclared("car".to_string()));
+            }
+            result => panic!("expected a located error: {:?}", result),
+        }
+
+        // Forms that were not read from source have no location
+        let expr = Val::list_from_vec(&[Val::symbol("car"), Val::from(1)]);
+        assert_eq!(
+            vm.eval_top_level(expr),
+            Err(Error::Undeclared("car".to_string()))
+        );
+    }
+
     #[test]
     fn test_evaluating_forms() {
         let mut vm = Vm::new(null_env());
@@ -1,163 +1,232 @@
-use crate::data::{Error, List, Map, Val, Vector};
+use crate::data::{ExternalRep, List, Map, ScanError, Span, Sym, Val, Vector};
 use crate::io::scanner::{Scanner, Token};
 use std::rc::Rc;
 
 // TODO confirm that the reader identifies all proper stopping points when
 // parsing complex forms.
 
+// Lists are given the span of their opening token so that runtime errors can
+// report where the failing form came from, and the elements of lists, vectors
+// and maps are given the spans of their first tokens. Giving a source name,
+// like the path of a file, adds it to every span.
 #[derive(Debug)]
 pub struct StringReader {
     scanner: Scanner,
+    source: Option<Rc<str>>,
 }
 
 impl StringReader {
     pub fn new(string: &str) -> StringReader {
         StringReader {
             scanner: Scanner::new(string),
+            source: None,
         }
     }
 
-    pub fn read(&mut self) -> Result<Val, Error> {
-        let next = self.scanner.next()?;
-        self.read_helper(next)
+    pub fn with_source(string: &str, source: &str) -> StringReader {
+        StringReader {
+            scanner: Scanner::new(string),
+            source: Some(Rc::from(source)),
+        }
     }
 
-    pub fn read_forms(&mut self) -> Result<Vec<Val>, Error> {
+    pub fn read(&mut self) -> Result<Val, ScanError> {
+        let next = self.scanner.next().map_err(|e| e.at(self.span()))?;
+        self.read_helper(next).map_err(|e| e.at(self.span()))
+    }
+
+    // The next form in the text, or None once all of the text has been read
+    pub fn read_next(&mut self) -> Result<Option<Val>, ScanError> {
+        Ok(self.read_next_spanned()?.map(|(form, _)| form))
+    }
+
+    // The next form in the text with the span it was read from
+    pub fn read_next_spanned(&mut self) -> Result<Option<(Val, Rc<Span>)>, ScanError> {
+        match self.scanner.next().map_err(|e| e.at(self.span()))? {
+            Token::EOF => Ok(None),
+            next => Ok(Some(
+                self.read_spanned(next).map_err(|e| e.at(self.span()))?,
+            )),
+        }
+    }
+
+    pub fn read_forms(&mut self) -> Result<Vec<Val>, ScanError> {
+        Ok(self
+            .read_spanned_forms()?
+            .into_iter()
+            .map(|(form, _)| form)
+            .collect())
+    }
+
+    pub fn read_spanned_forms(&mut self) -> Result<Vec<(Val, Rc<Span>)>, ScanError> {
         let mut forms = Vec::new();
-        loop {
-            let next = self.scanner.next()?;
-            if next == Token::EOF {
-                break;
-            } else {
-                forms.push(self.read_helper(next)?)
-            }
+        while let Some(form) = self.read_next_spanned()? {
+            forms.push(form);
         }
         Ok(forms)
     }
 
-    fn read_helper(&mut self, token: Token) -> Result<Val, Error> {
-        match token {
-            Token::Identifier(s) => Ok(Val::Symbol(Rc::new(s))),
-            Token::Keyword(s) => Ok(Val::Keyword(Rc::new(s))),
-            Token::Boolean(b) => Ok(Val::Bool(b)),
-            Token::Number(num) => Ok(Val::Number(num)),
-            Token::Character(ch) => Ok(Val::Char(ch)),
-            Token::String(s) => Ok(Val::from(s)),
-            Token::LParen => self.read_list(),
-            Token::ListOpen => self.read_list_literal(),
-            Token::VecOpen => self.read_vector_val(),
-            Token::TupleOpen => self.read_tuple_val(),
-            Token::MapOpen => self.read_map("table"),
-            Token::DictOpen => self.read_map("dict"),
-            Token::Deref => self.read_deref(),
-            Token::None => Ok(Val::None),
-            tk => Err(Error::BadToken(self.scanner.line, tk.to_string())),
-        }
+    fn read_helper(&mut self, token: Token) -> Result<Val, ScanError> {
+        Ok(self.read_spanned(token)?.0)
     }
 
-    fn read_list(&mut self) -> Result<Val, Error> {
-        // ( was used by caller
-        let mut vec = Vec::new();
-        let mut val = self.scanner.next()?;
+    fn read_spanned(&mut self, token: Token) -> Result<(Val, Rc<Span>), ScanError> {
+        let span = self.span();
+        let val = match token {
+            Token::Identifier(s) => Val::Symbol(Sym::new(s)),
+            Token::Keyword(s) => Val::Keyword(Sym::new(s)),
+            Token::Boolean(b) => Val::Bool(b),
+            Token::Number(num) => Val::Number(num),
+            Token::Character(ch) => Val::Char(ch),
+            Token::String(s) => Val::from(s),
+            Token::LParen => self.read_list()?,
+            Token::ListOpen => self.read_list_literal()?,
+            Token::VecOpen => self.read_vector_val()?,
+            Token::TupleOpen => self.read_tuple_val()?,
+            Token::MapOpen => self.read_map(false)?,
+            Token::DictOpen => self.read_map(true)?,
+            Token::Deref => self.read_deref()?,
+            Token::Quote => self.read_quoted("quote")?,
+            Token::Quasiquote => self.read_quoted("quasiquote")?,
+            Token::Unquote => self.read_quoted("unquote")?,
+            Token::UnquoteSplicing => self.read_quoted("unquote-splicing")?,
+            Token::None => Val::None,
+            // Running out of tokens inside a form means the form is incomplete
+            Token::EOF => return Err(ScanError::Eof),
+            tk => return Err(ScanError::BadToken(tk.to_string())),
+        };
 
-        while val != Token::RParen {
-            vec.push(self.read_helper(val)?);
-            val = self.scanner.next()?;
+        match val {
+            Val::List(ls) => Ok((Val::from(List::clone(&ls).with_span(span.clone())), span)),
+            _ => Ok((val, span)),
         }
-        Ok(Val::list_from_vec(&vec))
     }
 
-    fn read_list_literal(&mut self) -> Result<Val, Error> {
-        // #( was used by caller
-        let mut vec = Vec::new();
+    // The span of the token that was just scanned
+    fn span(&self) -> Rc<Span> {
+        let (line, col) = self.scanner.position();
+        Rc::new(Span::new(self.source.clone(), line, col))
+    }
+
+    // The elements of a collection up to the token that closes it
+    fn read_elements(&mut self, close: Token) -> Result<Vec<(Val, Rc<Span>)>, ScanError> {
+        let mut elements = Vec::new();
         let mut val = self.scanner.next()?;
 
-        while val != Token::RParen {
-            vec.push(self.read_helper(val)?);
+        while val != close {
+            elements.push(self.read_spanned(val)?);
             val = self.scanner.next()?;
         }
+        Ok(elements)
+    }
+
+    fn read_list(&mut self) -> Result<Val, ScanError> {
+        // ( was used by caller
+        let elements = self.read_elements(Token::RParen)?;
+        Ok(spanned_list(elements, None))
+    }
+
+    fn read_list_literal(&mut self) -> Result<Val, ScanError> {
+        // #( was used by caller
+        let elements = self.read_elements(Token::RParen)?;
         // is syntactic sugar for (list ...)
-        match List::from_vec(&vec) {
-            Some(ls) => Ok(Val::from(List::new(Val::symbol("list"), Some(Rc::new(ls))))),
-            None => Ok(Val::Empty),
+        match elements.is_empty() {
+            true => Ok(Val::Empty),
+            false => Ok(spanned_list(elements, Some(Val::symbol("list")))),
         }
     }
 
-    fn read_vector_val(&mut self) -> Result<Val, Error> {
-        let vec = self.read_vector()?;
-        Ok(Val::from(vec))
+    fn read_vector_val(&mut self) -> Result<Val, ScanError> {
+        // [ was used by caller
+        let elements = self.read_elements(Token::VecClose)?;
+        let vals: Vec<Val> = elements.iter().map(|(val, _)| val.clone()).collect();
+        Ok(Val::from(Vector::from(vals).with_spans(elements)))
     }
 
-    fn read_tuple_val(&mut self) -> Result<Val, Error> {
+    fn read_tuple_val(&mut self) -> Result<Val, ScanError> {
         // #[ was used by caller
-        let vec = self.read_vector()?;
-        Ok(Val::from(Vector::tuple(vec)))
+        let elements = self.read_elements(Token::VecClose)?;
+        let vals = elements.iter().map(|(val, _)| val.clone()).collect();
+        Ok(Val::from(Vector::tuple(vals).with_spans(elements)))
     }
 
-    fn read_vector(&mut self) -> Result<Vec<Val>, Error> {
-        // [ was used by caller
-        let mut vec = Vec::new();
-        let mut val = self.scanner.next()?;
+    // Map literals are read as maps, like vectors, rather than as calls to
+    // table or dict. Their keys and values are evaluated when the map is.
+    fn read_map(&mut self, dict: bool) -> Result<Val, ScanError> {
+        // { or #{ was used by the caller, errors are located at it
+        let span = self.span();
+        let elements = self.read_elements(Token::MapClose)?;
+        let vals: Vec<Val> = elements.iter().map(|(val, _)| val.clone()).collect();
 
-        while val != Token::VecClose {
-            vec.push(self.read_helper(val)?);
-            val = self.scanner.next()?;
-        }
-        Ok(vec)
-    }
-
-    fn read_map(&mut self, kind: &str) -> Result<Val, Error> {
-        // { or #{ was used by the caller
-        let mut vals = vec![];
-        loop {
-            let val = self.scanner.next()?;
-            if val == Token::MapClose {
-                break;
-            } else {
-                vals.push(self.read_helper(val)?);
-            }
+        if vals.len() % 2 != 0 {
+            return Err(ScanError::OddMapPairs.at(span));
         }
 
-        if vals.len() == 0 {
-            let mut m = Map::new();
-            if kind == "dict" {
-                m.freeze()
-            }
-            Ok(Val::from(m))
-        } else {
-            Ok(Val::from(List::new(
-                Val::symbol(kind),
-                Some(Rc::new(
-                    List::from_vec(&vals).expect("vec should be covertable to list"),
-                )),
-            )))
+        let mut map = Map::new();
+        for pair in vals.chunks(2) {
+            map.assoc(pair[0].clone(), pair[1].clone())
+                .map_err(|_| ScanError::BadMapKey(pair[0].to_external()).at(span.clone()))?;
+        }
+        if dict {
+            map.freeze()
         }
+        Ok(Val::from(map.with_spans(elements)))
+    }
+
+    fn read_quoted(&mut self, name: &str) -> Result<Val, ScanError> {
+        // ' ` , or ,@ was used by the caller
+        // is syntactic sugar for (name datum)
+        let next = self.scanner.next()?;
+        let datum = self.read_spanned(next)?;
+        Ok(spanned_list(vec![datum], Some(Val::symbol(name))))
     }
 
     // TODO could use this as a reference for how to make [] etc syntactic
     // sugar instead of separate forms. I.e. so that when we read and evaluate
     // them we do not need to have a separate eval rule for collection
     // evaluation for each collection.
-    fn read_deref(&mut self) -> Result<Val, Error> {
+    fn read_deref(&mut self) -> Result<Val, ScanError> {
         // @ was used by caller
         let ident = self.scanner.next()?;
         // is syntactic sugar for (deref identifier)
         match ident {
-            Token::Identifier(id) => Ok(Val::list_from_vec(&vec![
-                Val::symbol("deref"),
-                Val::Symbol(Rc::new(id)),
-            ])),
-            _ => Err(Error::DerefNotIdent(ident.to_string())),
+            Token::Identifier(id) => Ok(spanned_list(
+                vec![(Val::Symbol(Sym::new(id)), self.span())],
+                Some(Val::symbol("deref")),
+            )),
+            Token::EOF => Err(ScanError::Eof),
+            _ => Err(ScanError::DerefNotIdent(ident.to_string())),
         }
     }
 }
 
+// A list of elements that were read, each in a cell with its span. A head that
+// was not read, like the name of the form a quote is sugar for, has no span.
+fn spanned_list(elements: Vec<(Val, Rc<Span>)>, head: Option<Val>) -> Val {
+    let mut list = None;
+    for (val, span) in elements.into_iter().rev() {
+        list = Some(Rc::new(List::new(val, list).with_head_span(span)));
+    }
+    match (head, list) {
+        (Some(head), list) => Val::from(List::new(head, list)),
+        (None, Some(list)) => Val::List(list),
+        (None, None) => Val::Empty,
+    }
+}
+
 // Tests //////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn read_error(text: &str) -> String {
+        StringReader::new(text)
+            .read_forms()
+            .unwrap_err()
+            .to_string()
+    }
+
     #[test]
     fn test_reading_values_parsed_by_the_scanner() {
         assert_eq!(StringReader::new("10").read(), Ok(Val::from(10)));
@@ -217,15 +286,147 @@ mod tests {
     #[test]
     fn test_reading_maps() {
         // This testing method only allows us to use 1 entry as it is an unordered map
-        let expr = "{:a 2 :b 3}";
-        let expect = "(table :a 2 :b 3)";
+        let expr = "{:a (+ 1 2)}";
         let result = StringReader::new(expr).read().unwrap().to_string();
-        assert_eq!(result, expect);
+        assert_eq!(result, expr);
+
+        let expr = "#{:a 2}";
+        let result = StringReader::new(expr).read().unwrap().to_string();
+        assert_eq!(result, expr);
 
-        let expr = "#{:a 2 :b 3}";
-        let expect = "(dict :a 2 :b 3)";
+        let expr = "{(f 1) 2}";
+        let result = StringReader::new(expr).read().unwrap().to_string();
+        assert_eq!(result, expr);
+
+        assert_eq!(
+            read_error("{:a 1 :b}"),
+            "1:1: Error: map literals need a value for every key"
+        );
+        assert_eq!(
+            read_error("[0 {[1] 2}]"),
+            "1:4: Error: invalid map key: [1]"
+        );
+    }
+
+    #[test]
+    fn test_reading_quotes() {
+        let expr = "('a `(b ,c ,@[d]) '{:a b})";
+        let expect =
+            "((quote a) (quasiquote (b (unquote c) (unquote-splicing [d]))) (quote {:a b}))";
         let result = StringReader::new(expr).read().unwrap().to_string();
         assert_eq!(result, expect);
+
+        assert_eq!(read_error("'"), "1:2: Error: unexpected EOF");
+    }
+
+    #[test]
+    fn test_reading_incomplete_forms() {
+        assert_eq!(read_error("(+ 1 2"), "1:7: Error: unexpected EOF");
+        assert_eq!(read_error("[1 2\n3"), "2:2: Error: unexpected EOF");
+        assert_eq!(read_error("{:a 1"), "1:6: Error: unexpected EOF");
+        assert_eq!(read_error("#(1 (2)"), "1:8: Error: unexpected EOF");
+        assert_eq!(read_error("@"), "1:2: Error: unexpected EOF");
+        assert!(StringReader::new("(+ 1").read_forms().unwrap_err().is_eof());
+        assert_eq!(
+            StringReader::new("(f)").read_forms().map(|v| v.len()),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn test_reading_error_locations() {
+        let err = StringReader::with_source("(f 1)\n  (g #jeff)", "test.rusp")
+            .read_forms()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "test.rusp:2:6: Error: unexpected character: j"
+        );
+        assert_eq!(err.span().map(|s| (s.line, s.col)), Some((2, 6)));
+    }
+
+    #[test]
+    fn test_reading_list_spans() {
+        let text = "(def a 5)\n\n  (f #(1 2)\n     (g '(:a 1) @b))";
+        let forms = StringReader::with_source(text, "test.rusp")
+            .read_forms()
+            .unwrap();
+
+        let span_of = |val: &Val| match val {
+            Val::List(ls) => ls.span().map(|s| s.to_string()),
+            _ => None,
+        };
+        assert_eq!(span_of(&forms[0]), Some("test.rusp:1:1".to_string()));
+        assert_eq!(span_of(&forms[1]), Some("test.rusp:3:3".to_string()));
+
+        let call = match &forms[1] {
+            Val::List(ls) => ls.as_ref().clone(),
+            _ => panic!("expected a list"),
+        };
+        assert_eq!(
+            span_of(&call.second().unwrap()),
+            Some("test.rusp:3:6".to_string())
+        );
+        let inner = match call.third().unwrap() {
+            Val::List(ls) => ls.as_ref().clone(),
+            _ => panic!("expected a list"),
+        };
+        assert_eq!(
+            span_of(&call.third().unwrap()),
+            Some("test.rusp:4:6".to_string())
+        );
+        assert_eq!(
+            span_of(&inner.second().unwrap()),
+            Some("test.rusp:4:9".to_string())
+        );
+        assert_eq!(
+            span_of(&inner.third().unwrap()),
+            Some("test.rusp:4:17".to_string())
+        );
+
+        // Spans do not take part in equality
+        assert_eq!(forms[0], StringReader::new("(def a 5)").read().unwrap());
+        assert_eq!(
+            span_of(&StringReader::new("(a)").read().unwrap()),
+            Some("1:1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_reading_element_spans() {
+        let text = "a\n (f b\n  [1 c {:d e}])";
+        let forms = StringReader::new(text).read_spanned_forms().unwrap();
+        let at = |span: Option<Rc<Span>>| span.map(|s| s.to_string());
+        assert_eq!(at(Some(forms[0].1.clone())), Some("1:1".to_string()));
+
+        let call = match &forms[1].0 {
+            Val::List(ls) => ls.clone(),
+            _ => panic!("expected a list"),
+        };
+        assert_eq!(
+            at(call.element_span(&Val::symbol("b"))),
+            Some("2:5".to_string())
+        );
+        let vector = call.third().unwrap();
+        assert_eq!(at(call.element_span(&vector)), Some("3:3".to_string()));
+        let vector = match vector {
+            Val::Vector(v) => v,
+            _ => panic!("expected a vector"),
+        };
+        let v = vector.borrow();
+        assert_eq!(
+            at(v.element_span(&Val::symbol("c"))),
+            Some("3:6".to_string())
+        );
+        let map = match v.get(2).unwrap() {
+            Val::Map(m) => m,
+            _ => panic!("expected a map"),
+        };
+        assert_eq!(
+            at(map.borrow().element_span(&Val::symbol("e"))),
+            Some("3:12".to_string())
+        );
+        assert_eq!(at(call.element_span(&Val::symbol("zz"))), None);
     }
 
     #[test]
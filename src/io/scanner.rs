@@ -1,4 +1,4 @@
-use crate::data::{Char, DisplayRep, Error, Num, Str};
+use crate::data::{Char, DisplayRep, Num, ScanError, Str};
 use std::fmt;
 
 // TODO missing some tests for the additional syntax
@@ -7,11 +7,13 @@ use std::fmt;
 //
 // \ at the start of an ident is a char
 //   \slash instead of (or in addition to) \\, \null, \tab, \space, \newline
+//   \u{hex} for the char with a hex code
 // : at the start of an ident is a keyword
 // " starts a string
-//   \t, \n, \0, \\, \" are the only escape sequences
+//   \t, \n, \0, \\, \" and \u{hex} are the only escape sequences
 // # starts special constructs and is not allowed at the start of identifiers otherwise
 // @ is for deref
+// ' is for quote, ` for quasiquote, , for unquote and ,@ for unquote-splicing
 //
 // ( is a function application
 // #( is a list literal like (list ...)
@@ -24,6 +26,7 @@ use std::fmt;
 // #t, #f, #true and #false are boolean literals
 // #none is none/nil
 // #() is the empty list
+// #! at the very start of the text is a shebang line and is ignored
 //
 // Otherwise everything else is a valid identifier or number
 
@@ -47,6 +50,10 @@ pub enum Token {
     MapClose,
     DictOpen,
     Deref,
+    Quote,
+    Quasiquote,
+    Unquote,
+    UnquoteSplicing,
     None,
     EOF,
 }
@@ -70,6 +77,10 @@ impl fmt::Display for Token {
             Token::MapClose => write!(f, "}}"),
             Token::DictOpen => write!(f, "#{{"),
             Token::Deref => write!(f, "@"),
+            Token::Quote => write!(f, "'"),
+            Token::Quasiquote => write!(f, "`"),
+            Token::Unquote => write!(f, ","),
+            Token::UnquoteSplicing => write!(f, ",@"),
             Token::None => write!(f, "#none"),
             Token::EOF => write!(f, "EOF"),
         }
@@ -82,30 +93,35 @@ impl fmt::Display for Token {
 //
 // Scanner ////////////////////////////////////////////////////////////////////
 
+// Scans utf-8 text a char at a time, so columns count chars rather than bytes.
 #[derive(Debug)]
 pub struct Scanner {
     pub line: usize,
+    col: usize,
+    start: (usize, usize),
     idx: usize,
-    text: Vec<u8>,
+    text: Vec<char>,
 }
 
 impl Scanner {
     pub fn new(text: &str) -> Scanner {
         Scanner {
             line: 1,
+            col: 0,
+            start: (1, 1),
             idx: 0,
-            text: text.into(),
+            text: text.chars().collect(),
         }
     }
 
-    pub fn next(&mut self) -> Result<Token, Error> {
+    pub fn next(&mut self) -> Result<Token, ScanError> {
         self.skip_whitespace();
+        self.start = (self.line, self.col + 1);
         if self.eof() {
             return Ok(Token::EOF);
         }
 
-        let byte = self.next_byte();
-        let ch = byte as char;
+        let ch = self.next_char();
 
         match ch {
             '(' => Ok(Token::LParen),
@@ -120,17 +136,26 @@ impl Scanner {
             }
             '#' => self.scan_hash(),
             '@' => Ok(Token::Deref),
+            '\'' => Ok(Token::Quote),
+            '`' => Ok(Token::Quasiquote),
+            ',' => match self.peek_char() {
+                '@' => {
+                    self.next_char();
+                    Ok(Token::UnquoteSplicing)
+                }
+                _ => Ok(Token::Unquote),
+            },
             '\\' => self.scan_char(),
             ':' => self.scan_keyword(),
             '"' => self.scan_string(),
-            '+' | '-' | '.' => self.scan_peculiar_identifier(byte),
+            '+' | '-' | '.' => self.scan_peculiar_identifier(ch),
             _ => {
-                if is_digit(byte) {
-                    self.scan_number(byte)
-                } else if is_initial(byte) {
-                    self.scan_identifier(byte)
+                if is_digit(ch) {
+                    self.scan_number(ch)
+                } else if is_initial(ch) {
+                    self.scan_identifier(ch)
                 } else {
-                    Err(Error::BadChar(self.line, byte as char))
+                    Err(ScanError::BadChar(ch))
                 }
             }
         }
@@ -138,262 +163,296 @@ impl Scanner {
 
     fn skip_whitespace(&mut self) {
         while !self.eof() {
-            let byte = self.peek_byte();
-            if !is_whitespace(byte) {
+            let ch = self.peek_char();
+            if !is_whitespace(ch) {
                 break;
-            } else if byte as char == '\n' {
+            }
+            self.next_char();
+            if ch == '\n' {
                 self.line += 1;
+                self.col = 0;
             }
-            self.next_byte();
         }
     }
 
+    // The line and column of the first char of the last token scanned
+    pub fn position(&self) -> (usize, usize) {
+        self.start
+    }
+
+    // Stops before the newline so that skip_whitespace still counts the line
     fn skip_comment(&mut self) {
-        while !self.eof() && self.next_byte() as char != '\n' {}
+        while !self.eof() && self.peek_char() != '\n' {
+            self.next_char();
+        }
     }
 
     // Token Parsers //
 
-    fn scan_hash(&mut self) -> Result<Token, Error> {
-        let byte = self.next_byte();
-        let ch = byte as char;
+    fn scan_hash(&mut self) -> Result<Token, ScanError> {
+        let ch = self.next_char();
 
         match ch {
             '(' => Ok(Token::ListOpen),
             '[' => Ok(Token::TupleOpen),
             '{' => Ok(Token::DictOpen),
-            'n' => self.scan_none(byte),
-            't' | 'f' | 'T' | 'F' => self.scan_bool(byte),
-            'b' | 'd' | 'o' | 'x' => self.scan_special_number(byte),
-            _ => Err(Error::BadChar(self.line, ch)),
+            // A #! on the first line is a shebang so scripts can be executed
+            '!' if self.idx == 2 => {
+                self.skip_comment();
+                self.next()
+            }
+            'n' => self.scan_none(ch),
+            't' | 'f' | 'T' | 'F' => self.scan_bool(ch),
+            'b' | 'd' | 'o' | 'x' => self.scan_special_number(ch),
+            _ => Err(ScanError::BadChar(ch)),
         }
     }
 
-    fn scan_none(&mut self, byte: u8) -> Result<Token, Error> {
-        let bytes = self.scan_bytes_lower(byte);
-        match as_string(&bytes).as_str() {
+    fn scan_none(&mut self, ch: char) -> Result<Token, ScanError> {
+        let chars = self.scan_chars_lower(ch);
+        match as_string(&chars).as_str() {
             "none" => Ok(Token::None),
-            ident => Err(Error::BadIdentifier(self.line, ident.to_string())),
+            ident => Err(ScanError::BadIdentifier(ident.to_string())),
         }
     }
 
-    fn scan_keyword(&mut self) -> Result<Token, Error> {
-        let bytes = self.scan_bytes_lower(':' as u8);
-        Ok(Token::Keyword(Str::from(&bytes[..])))
+    fn scan_keyword(&mut self) -> Result<Token, ScanError> {
+        let chars = self.scan_chars_lower(':');
+        Ok(Token::Keyword(Str::from(as_string(&chars))))
     }
 
-    fn scan_char(&mut self) -> Result<Token, Error> {
-        let byte = self.next_byte();
+    fn scan_char(&mut self) -> Result<Token, ScanError> {
+        let ch = self.next_char();
 
-        // If it is a named char get the name and create the ascii byte
-        if is_letter(byte) && is_letter(self.peek_byte()) {
-            let bytes = self.scan_bytes_lower(byte);
-            match as_string(&bytes).as_str() {
-                "newline" => Ok(Token::Character(Char::LineFeed)),
-                "null" => Ok(Token::Character(Char::Null)),
-                "space" => Ok(Token::Character(Char::Space)),
-                "tab" => Ok(Token::Character(Char::Tab)),
-                "unsup" => Ok(Token::Character(Char::Unsupported)),
-                "slash" => Ok(Token::Character(Char::from('\\'))),
-                name => Err(Error::BadIdentifier(self.line, format!("\\{}", name))),
+        // \u{hex} is the char with the hex code
+        if ch == 'u' && self.peek_char() == '{' {
+            let code = self.scan_code()?;
+            if self.eof() || is_delimeter(self.peek_char()) {
+                return Ok(Token::Character(code));
             }
+            let next = self.next_char();
+            let rest = as_string(&self.scan_chars(next));
+            let hex = u32::from(code.to_char());
+            return Err(ScanError::BadIdentifier(format!("\\u{{{hex:x}}}{rest}")));
+        }
 
-        // Else treat it as a single byte character and create the ascii byte
-        } else {
-            self.new_ascii_char(byte)
+        // A char followed by a delimeter is itself, a delimeter is always
+        // itself, and anything else runs to the next delimeter and is the name
+        // of a char
+        if is_delimeter(ch) || self.eof() || is_delimeter(self.peek_char()) {
+            return self.new_char(ch);
+        }
+        let chars = self.scan_chars_lower(ch);
+        let name = as_string(&chars);
+        match Char::from_name(&name) {
+            Some(ch) => Ok(Token::Character(ch)),
+            None => Err(ScanError::BadIdentifier(format!("\\{}", name))),
         }
     }
 
-    fn scan_bool(&mut self, byte: u8) -> Result<Token, Error> {
-        let bytes = self.scan_bytes_lower(byte);
-        match as_string(&bytes).as_str() {
+    fn scan_bool(&mut self, ch: char) -> Result<Token, ScanError> {
+        let chars = self.scan_chars_lower(ch);
+        match as_string(&chars).as_str() {
             "t" | "true" => Ok(Token::Boolean(true)),
             "f" | "false" => Ok(Token::Boolean(false)),
-            name => Err(Error::BadIdentifier(self.line, format!("#{}", name))),
+            name => Err(ScanError::BadIdentifier(format!("#{}", name))),
         }
     }
 
-    fn scan_string(&mut self) -> Result<Token, Error> {
-        let mut bytes = Vec::new();
+    fn scan_string(&mut self) -> Result<Token, ScanError> {
+        let mut chars = Vec::new();
 
         loop {
             if self.eof() {
-                return Err(Error::Eof(self.line));
+                return Err(ScanError::Eof);
             }
 
-            let byte = self.next_byte();
-            match byte as char {
+            let ch = self.next_char();
+            match ch {
                 '"' => break,
-                '\\' => match self.next_byte() as char {
-                    '0' => bytes.push('\0' as u8),
-                    'n' => bytes.push('\n' as u8),
-                    't' => bytes.push('\t' as u8),
-                    '\\' => bytes.push('\\' as u8),
-                    '"' => bytes.push('"' as u8),
-                    b => return Err(Error::BadEscape(self.line, format!("\\{}", b))),
+                '\\' => match self.next_char() {
+                    '0' => chars.push('\0'),
+                    'n' => chars.push('\n'),
+                    't' => chars.push('\t'),
+                    '\\' => chars.push('\\'),
+                    '"' => chars.push('"'),
+                    'u' if self.peek_char() == '{' => chars.push(self.scan_code()?.to_char()),
+                    c => return Err(ScanError::BadEscape(format!("\\{}", c))),
                 },
-                '\n' => return Err(Error::MultiLineString(self.line)),
-                _ => bytes.push(byte),
+                '\n' => return Err(ScanError::MultiLineString),
+                _ => chars.push(ch),
             }
         }
 
-        Ok(Token::String(Str::from(&bytes[..])))
+        Ok(Token::String(Str::from(as_string(&chars))))
+    }
+
+    // The char for the {hex} after a \u
+    fn scan_code(&mut self) -> Result<Char, ScanError> {
+        self.next_char();
+        let mut hex = String::new();
+        while !self.eof() && !is_delimeter(self.peek_char()) {
+            hex.push(self.next_char());
+        }
+        if self.next_char() != '}' {
+            return Err(ScanError::BadEscape(format!("\\u{{{hex}")));
+        }
+        match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+            Some(ch) => Ok(Char::from(ch)),
+            None => Err(ScanError::BadEscape(format!("\\u{{{hex}}}"))),
+        }
     }
 
-    fn scan_number(&mut self, byte: u8) -> Result<Token, Error> {
-        let bytes = self.scan_bytes_lower(byte);
-        self.new_number(&bytes)
+    fn scan_number(&mut self, ch: char) -> Result<Token, ScanError> {
+        let chars = self.scan_chars_lower(ch);
+        self.new_number(&chars)
     }
 
-    fn scan_special_number(&mut self, byte: u8) -> Result<Token, Error> {
-        let mut bytes = vec!['#' as u8, byte];
-        self.scan_bytes_lower_vec(&mut bytes);
-        self.new_number(&bytes)
+    fn scan_special_number(&mut self, ch: char) -> Result<Token, ScanError> {
+        let mut chars = vec!['#', ch];
+        self.scan_chars_lower_vec(&mut chars);
+        self.new_number(&chars)
     }
 
-    fn scan_peculiar_identifier(&mut self, byte: u8) -> Result<Token, Error> {
-        match byte as char {
-            '+' | '-' => match self.peek_byte() as char {
-                'i' | '0'..='9' => self.scan_number(byte),
-                next if is_delimeter(next as u8) => self.new_identifier(&vec![byte]),
-                ch => Err(Error::BadChar(self.line, ch)),
+    fn scan_peculiar_identifier(&mut self, ch: char) -> Result<Token, ScanError> {
+        match ch {
+            '+' | '-' => match self.peek_char() {
+                'i' | '0'..='9' => self.scan_number(ch),
+                next if is_delimeter(next) => self.new_identifier(&vec![ch]),
+                next => Err(ScanError::BadChar(next)),
             },
-            '.' => match self.peek_byte() as char {
+            '.' => match self.peek_char() {
                 '.' => self.scan_dots(),
-                next if is_delimeter(next as u8) || self.eof() => self.new_identifier(&vec![byte]),
-                ch => Err(Error::BadChar(self.line, ch)),
+                next if is_delimeter(next) || self.eof() => self.new_identifier(&vec![ch]),
+                next => Err(ScanError::BadChar(next)),
             },
             ch => panic!("should be one of [+, -, .]: {ch}"),
         }
     }
 
-    fn scan_dots(&mut self) -> Result<Token, Error> {
-        let bytes = self.scan_bytes('.' as u8);
-        match as_string(&bytes).as_str() {
-            "..." => self.new_identifier(&bytes),
-            ident => Err(Error::BadIdentifier(self.line, ident.to_owned())),
+    fn scan_dots(&mut self) -> Result<Token, ScanError> {
+        let chars = self.scan_chars('.');
+        match as_string(&chars).as_str() {
+            "..." => self.new_identifier(&chars),
+            ident => Err(ScanError::BadIdentifier(ident.to_owned())),
         }
     }
 
-    fn scan_identifier(&mut self, byte: u8) -> Result<Token, Error> {
-        let bytes = self.scan_bytes_lower(byte);
-        if bytes.iter().all(|b| is_subsequent(*b)) {
-            self.new_identifier(&bytes)
+    fn scan_identifier(&mut self, ch: char) -> Result<Token, ScanError> {
+        let chars = self.scan_chars_lower(ch);
+        if chars.iter().all(|c| is_subsequent(*c)) {
+            self.new_identifier(&chars)
         } else {
-            Err(Error::BadIdentifier(self.line, as_string(&bytes)))
+            Err(ScanError::BadIdentifier(as_string(&chars)))
         }
     }
 
-    // Byte collection helpers //
+    // Char collection helpers //
 
     pub fn eof(&self) -> bool {
         self.idx >= self.text.len()
     }
 
-    fn next_byte(&mut self) -> u8 {
+    fn next_char(&mut self) -> char {
         if self.eof() {
-            0
+            '\0'
         } else {
             let idx = self.idx;
             self.idx += 1;
+            self.col += 1;
             self.text[idx]
         }
     }
 
-    fn peek_byte(&self) -> u8 {
+    fn peek_char(&self) -> char {
         if self.eof() {
-            0
+            '\0'
         } else {
             self.text[self.idx]
         }
     }
 
-    // Scan bytes until reaching a delimeter char
-    fn scan_bytes(&mut self, start: u8) -> Vec<u8> {
-        let mut bytes = vec![start];
+    // Scan chars until reaching a delimeter char
+    fn scan_chars(&mut self, start: char) -> Vec<char> {
+        let mut chars = vec![start];
         while !self.eof() {
-            let byte = self.peek_byte();
-            if is_delimeter(byte) {
+            let ch = self.peek_char();
+            if is_delimeter(ch) {
                 break;
             } else {
-                bytes.push(byte);
-                self.next_byte();
+                chars.push(ch);
+                self.next_char();
             }
         }
-        bytes
+        chars
     }
 
-    // Scan bytes until reaching a delimeter char LOWER CASE them all
-    fn scan_bytes_lower(&mut self, start: u8) -> Vec<u8> {
-        let mut bytes = vec![lower_case(start)];
-        self.scan_bytes_lower_vec(&mut bytes);
-        bytes
+    // Scan chars until reaching a delimeter char LOWER CASE them all
+    fn scan_chars_lower(&mut self, start: char) -> Vec<char> {
+        let mut chars = start.to_lowercase().collect();
+        self.scan_chars_lower_vec(&mut chars);
+        chars
     }
 
-    fn scan_bytes_lower_vec(&mut self, bytes: &mut Vec<u8>) {
+    fn scan_chars_lower_vec(&mut self, chars: &mut Vec<char>) {
         while !self.eof() {
-            let byte = self.peek_byte();
-            if is_delimeter(byte) {
+            let ch = self.peek_char();
+            if is_delimeter(ch) {
                 break;
             } else {
-                bytes.push(lower_case(byte));
-                self.next_byte();
+                chars.extend(ch.to_lowercase());
+                self.next_char();
             }
         }
     }
 
     // Token Helpers //
 
-    fn new_ascii_char(&self, byte: u8) -> Result<Token, Error> {
-        match Char::from(byte) {
-            Char::Unsupported => Err(Error::BadChar(self.line, byte as char)),
-            ascii => Ok(Token::Character(ascii)),
+    // Control chars can only be written by name or code
+    fn new_char(&self, ch: char) -> Result<Token, ScanError> {
+        match ch.is_control() {
+            true => Err(ScanError::BadChar(ch)),
+            false => Ok(Token::Character(Char::from(ch))),
         }
     }
 
-    fn new_identifier(&self, bytes: &Vec<u8>) -> Result<Token, Error> {
-        Ok(Token::Identifier(Str::from(&bytes[..])))
+    fn new_identifier(&self, chars: &[char]) -> Result<Token, ScanError> {
+        Ok(Token::Identifier(Str::from(as_string(chars))))
     }
 
-    fn new_number(&self, bytes: &Vec<u8>) -> Result<Token, Error> {
-        let s: String = bytes.iter().map(|b| *b as char).collect();
+    fn new_number(&self, chars: &[char]) -> Result<Token, ScanError> {
+        let s = as_string(chars);
         match s.parse::<Num>() {
             Ok(n) => Ok(Token::Number(n)),
-            Err(_) => Err(Error::BadNumber(self.line, s)), // TODO change to ArithErr when ready
+            Err(_) => Err(ScanError::BadNumber(s)), // TODO change to ArithErr when ready
         }
     }
 }
 
 // Predicates /////////////////////////////////////////////////////////////////
 
-pub fn is_whitespace(ch: u8) -> bool {
-    match ch as char {
-        ' ' | '\t' | '\n' | '`' | ',' | '\'' => true,
-        _ => false,
-    }
+pub fn is_whitespace(ch: char) -> bool {
+    ch.is_whitespace()
 }
 
-pub fn is_delimeter(ch: u8) -> bool {
+pub fn is_delimeter(ch: char) -> bool {
     is_whitespace(ch)
-        || match ch as char {
-            '(' | ')' | '"' | ';' | '[' | ']' | '{' | '}' => true,
+        || match ch {
+            '(' | ')' | '"' | ';' | '[' | ']' | '{' | '}' | '\'' | '`' | ',' => true,
             _ => false,
         }
 }
 
-pub fn is_initial(ch: u8) -> bool {
-    is_letter(ch) || is_special_initial(ch)
+pub fn is_initial(ch: char) -> bool {
+    is_letter(ch) || is_special_initial(ch) || is_other_initial(ch)
 }
 
-pub fn is_letter(ch: u8) -> bool {
-    match ch as char {
-        'a'..='z' | 'A'..='Z' => true,
-        _ => false,
-    }
+pub fn is_letter(ch: char) -> bool {
+    ch.is_ascii_alphabetic()
 }
 
-pub fn is_special_initial(ch: u8) -> bool {
-    match ch as char {
+pub fn is_special_initial(ch: char) -> bool {
+    match ch {
         '!' | '$' | '%' | '@' | '&' | '*' | '/' | ':' | '<' | '=' | '>' | '?' | '^' | '_' | '~' => {
             true
         }
@@ -401,19 +460,21 @@ pub fn is_special_initial(ch: u8) -> bool {
     }
 }
 
-pub fn is_subsequent(ch: u8) -> bool {
+// Any char outside of ascii that is not whitespace or a control char, like λ
+pub fn is_other_initial(ch: char) -> bool {
+    !ch.is_ascii() && !ch.is_whitespace() && !ch.is_control()
+}
+
+pub fn is_subsequent(ch: char) -> bool {
     is_initial(ch) || is_digit(ch) || is_special_subsequent(ch)
 }
 
-pub fn is_digit(ch: u8) -> bool {
-    match ch as char {
-        '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => true,
-        _ => false,
-    }
+pub fn is_digit(ch: char) -> bool {
+    ch.is_ascii_digit()
 }
 
-pub fn is_special_subsequent(ch: u8) -> bool {
-    match ch as char {
+pub fn is_special_subsequent(ch: char) -> bool {
+    match ch {
         '+' | '-' | '.' => true,
         _ => false,
     }
@@ -421,16 +482,8 @@ pub fn is_special_subsequent(ch: u8) -> bool {
 
 // Helpers ////////////////////////////////////////////////////////////////////
 
-fn as_string(bytes: &Vec<u8>) -> String {
-    bytes.into_iter().map(|b| *b as char).collect()
-}
-
-fn lower_case(byte: u8) -> u8 {
-    if byte.is_ascii() {
-        byte.to_ascii_lowercase()
-    } else {
-        byte
-    }
+fn as_string(chars: &[char]) -> String {
+    chars.iter().collect()
 }
 
 // Testing ////////////////////////////////////////////////////////////////////
@@ -456,24 +509,129 @@ mod tests {
         assert_eq!(s.next(), Ok(Token::EOF));
     }
 
+    #[test]
+    fn test_scanning_quotes() {
+        let mut s = Scanner::new("'a `(b ,c ,@d)");
+        assert_eq!(s.next(), Ok(Token::Quote));
+        assert_eq!(s.next(), Ok(Token::Identifier(Str::from("a"))));
+        assert_eq!(s.next(), Ok(Token::Quasiquote));
+        assert_eq!(s.next(), Ok(Token::LParen));
+        assert_eq!(s.next(), Ok(Token::Identifier(Str::from("b"))));
+        assert_eq!(s.next(), Ok(Token::Unquote));
+        assert_eq!(s.next(), Ok(Token::Identifier(Str::from("c"))));
+        assert_eq!(s.next(), Ok(Token::UnquoteSplicing));
+        assert_eq!(s.next(), Ok(Token::Identifier(Str::from("d"))));
+        assert_eq!(s.next(), Ok(Token::RParen));
+        assert_eq!(s.next(), Ok(Token::EOF));
+    }
+
+    #[test]
+    fn test_scanning_comments_counts_lines() {
+        let mut s = Scanner::new("; comment\n(; another\n)");
+        assert_eq!(s.next(), Ok(Token::LParen));
+        assert_eq!(s.line, 2);
+        assert_eq!(s.next(), Ok(Token::RParen));
+        assert_eq!(s.line, 3);
+    }
+
+    #[test]
+    fn test_scanning_shebang() {
+        let mut s = Scanner::new("#!/usr/bin/env rusp\n(1)");
+        assert_eq!(s.next(), Ok(Token::LParen));
+        assert_eq!(s.line, 2);
+
+        // Only allowed at the very start
+        let mut s = Scanner::new("(1) #!/usr/bin/env rusp");
+        assert_eq!(s.next(), Ok(Token::LParen));
+        assert_eq!(s.next(), Ok(Token::Number(Num::Int(1))));
+        assert_eq!(s.next(), Ok(Token::RParen));
+        assert_eq!(s.next(), Err(ScanError::BadChar('!')));
+    }
+
+    #[test]
+    fn test_scanning_token_positions() {
+        let mut s = Scanner::new("(def a\n  ; comment\n   [1 \"two\"])");
+        assert_eq!(s.next(), Ok(Token::LParen));
+        assert_eq!(s.position(), (1, 1));
+        assert_eq!(s.next(), Ok(Token::Identifier(Str::from("def"))));
+        assert_eq!(s.position(), (1, 2));
+        assert_eq!(s.next(), Ok(Token::Identifier(Str::from("a"))));
+        assert_eq!(s.position(), (1, 6));
+        assert_eq!(s.next(), Ok(Token::VecOpen));
+        assert_eq!(s.position(), (3, 4));
+        assert_eq!(s.next(), Ok(Token::Number(Num::Int(1))));
+        assert_eq!(s.position(), (3, 5));
+        assert_eq!(s.next(), Ok(Token::String(Str::from("two"))));
+        assert_eq!(s.position(), (3, 7));
+        assert_eq!(s.next(), Ok(Token::VecClose));
+        assert_eq!(s.position(), (3, 12));
+    }
+
     // Tokens that start with # (excluding numbers) //
 
     #[test]
     fn test_scanning_single_chars() {
-        let bytes = (33..127).collect::<Vec<u8>>();
-        for b in bytes.iter() {
-            let mut s = Scanner::new(&format!("\\{}", *b as char));
-            assert_eq!(s.next(), Ok(Token::Character(Char::from(*b))));
+        for ch in ('!'..='~').chain(['λ', 'é', '中', '😀']) {
+            let mut s = Scanner::new(&format!("\\{}", ch));
+            assert_eq!(s.next(), Ok(Token::Character(Char::from(ch))));
         }
     }
 
     #[test]
     fn test_scanning_named_chars() {
-        let mut s = Scanner::new("\\space \\tab \\NEWLINE \\null");
-        assert_eq!(s.next(), Ok(Token::Character(Char::Space)));
-        assert_eq!(s.next(), Ok(Token::Character(Char::Tab)));
-        assert_eq!(s.next(), Ok(Token::Character(Char::LineFeed)));
-        assert_eq!(s.next(), Ok(Token::Character(Char::Null)));
+        let mut s = Scanner::new("\\space \\tab \\NEWLINE \\null \\slash");
+        assert_eq!(s.next(), Ok(Token::Character(Char::from(' '))));
+        assert_eq!(s.next(), Ok(Token::Character(Char::from('\t'))));
+        assert_eq!(s.next(), Ok(Token::Character(Char::from('\n'))));
+        assert_eq!(s.next(), Ok(Token::Character(Char::from('\0'))));
+        assert_eq!(s.next(), Ok(Token::Character(Char::from('\\'))));
+    }
+
+    #[test]
+    fn test_scanning_char_codes() {
+        let mut s = Scanner::new("\\u{3bb} \\u{1F600} \\u{0} \\u");
+        assert_eq!(s.next(), Ok(Token::Character(Char::from('λ'))));
+        assert_eq!(s.next(), Ok(Token::Character(Char::from('😀'))));
+        assert_eq!(s.next(), Ok(Token::Character(Char::from('\0'))));
+        assert_eq!(s.next(), Ok(Token::Character(Char::from('u'))));
+
+        assert_eq!(
+            Scanner::new("\\u{d800}").next(),
+            Err(ScanError::BadEscape("\\u{d800}".to_owned()))
+        );
+        assert_eq!(
+            Scanner::new("\\u{xyz}").next(),
+            Err(ScanError::BadEscape("\\u{xyz}".to_owned()))
+        );
+        assert_eq!(
+            Scanner::new("\\u{3bb").next(),
+            Err(ScanError::BadEscape("\\u{3bb".to_owned()))
+        );
+        assert_eq!(
+            Scanner::new("\\u{3bb}x").next(),
+            Err(ScanError::BadIdentifier("\\u{3bb}x".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_scanning_chars_up_to_a_delimeter() {
+        let mut s = Scanner::new("\\λ) \\( \\(( \\é\n\\a");
+        assert_eq!(s.next(), Ok(Token::Character(Char::from('λ'))));
+        assert_eq!(s.next(), Ok(Token::RParen));
+        assert_eq!(s.next(), Ok(Token::Character(Char::from('('))));
+        assert_eq!(s.next(), Ok(Token::Character(Char::from('('))));
+        assert_eq!(s.next(), Ok(Token::LParen));
+        assert_eq!(s.next(), Ok(Token::Character(Char::from('é'))));
+        assert_eq!(s.next(), Ok(Token::Character(Char::from('a'))));
+
+        // more than one char must be the name of a char
+        for text in ["\\λλ", "\\中文", "\\aλ", "\\λa", "\\1a", "\\a1", "\\😀!"] {
+            assert_eq!(
+                Scanner::new(text).next(),
+                Err(ScanError::BadIdentifier(text.to_lowercase())),
+                "{text}"
+            );
+        }
     }
 
     #[test]
@@ -481,11 +639,11 @@ mod tests {
         let mut s = Scanner::new("\\jERsey");
         assert_eq!(
             s.next(),
-            Err(Error::BadIdentifier(1, "\\jersey".to_owned()))
+            Err(ScanError::BadIdentifier("\\jersey".to_owned()))
         );
 
         let mut s = Scanner::new("\\\x02");
-        assert_eq!(s.next(), Err(Error::BadChar(1, '\x02')));
+        assert_eq!(s.next(), Err(ScanError::BadChar('\x02')));
     }
 
     #[test]
@@ -495,16 +653,16 @@ mod tests {
         assert_eq!(s.next(), Ok(Token::Boolean(false)));
         assert_eq!(s.next(), Ok(Token::Boolean(true)));
         assert_eq!(s.next(), Ok(Token::Boolean(false)));
-        assert_eq!(s.next(), Err(Error::BadIdentifier(1, "#flase".to_owned())));
+        assert_eq!(s.next(), Err(ScanError::BadIdentifier("#flase".to_owned())));
     }
 
     #[test]
     fn test_scanning_invalid_hash_identifier() {
         let mut s = Scanner::new("#jeff");
-        assert_eq!(s.next(), Err(Error::BadChar(1, 'j')));
+        assert_eq!(s.next(), Err(ScanError::BadChar('j')));
 
         let mut s = Scanner::new("#1234");
-        assert_eq!(s.next(), Err(Error::BadChar(1, '1')));
+        assert_eq!(s.next(), Err(ScanError::BadChar('1')));
     }
 
     // Identifiers //
@@ -519,21 +677,28 @@ mod tests {
             s.next(),
             Ok(Token::Identifier(Str::from("<>!@%&^*_-+=:?~/$0123456789")))
         );
+
+        let mut s = Scanner::new("λ naïve ΣΑΣ x→y :ключ");
+        assert_eq!(s.next(), Ok(Token::Identifier(Str::from("λ"))));
+        assert_eq!(s.next(), Ok(Token::Identifier(Str::from("naïve"))));
+        assert_eq!(s.next(), Ok(Token::Identifier(Str::from("σασ"))));
+        assert_eq!(s.next(), Ok(Token::Identifier(Str::from("x→y"))));
+        assert_eq!(s.next(), Ok(Token::Keyword(Str::from(":ключ"))));
     }
 
     #[test]
     fn test_scanning_invalid_identifiers() {
         assert_eq!(
             Scanner::new("\\hello").next(),
-            Err(Error::BadIdentifier(1, "\\hello".to_owned()))
+            Err(ScanError::BadIdentifier("\\hello".to_owned()))
         );
         assert_eq!(
             Scanner::new("hel#lo").next(),
-            Err(Error::BadIdentifier(1, "hel#lo".to_owned()))
+            Err(ScanError::BadIdentifier("hel#lo".to_owned()))
         );
         assert_eq!(
             Scanner::new("hel\\lo").next(),
-            Err(Error::BadIdentifier(1, "hel\\lo".to_owned()))
+            Err(ScanError::BadIdentifier("hel\\lo".to_owned()))
         );
     }
 
@@ -548,16 +713,16 @@ mod tests {
     #[test]
     fn test_scanning_invalid_peculiar_identifiers() {
         let mut s = Scanner::new("+hello");
-        assert_eq!(s.next(), Err(Error::BadChar(1, 'h')));
+        assert_eq!(s.next(), Err(ScanError::BadChar('h')));
 
         let mut s = Scanner::new("-hello");
-        assert_eq!(s.next(), Err(Error::BadChar(1, 'h')));
+        assert_eq!(s.next(), Err(ScanError::BadChar('h')));
 
         let mut s = Scanner::new(".hello");
-        assert_eq!(s.next(), Err(Error::BadChar(1, 'h')));
+        assert_eq!(s.next(), Err(ScanError::BadChar('h')));
 
         let mut s = Scanner::new("....");
-        assert_eq!(s.next(), Err(Error::BadIdentifier(1, "....".to_owned())));
+        assert_eq!(s.next(), Err(ScanError::BadIdentifier("....".to_owned())));
     }
 
     // Numbers //
@@ -646,11 +811,11 @@ mod tests {
     fn parse_invalid_numbers() {
         assert_eq!(
             Scanner::new("123jkl").next(),
-            Err(Error::BadNumber(1, "123jkl".to_owned()))
+            Err(ScanError::BadNumber("123jkl".to_owned()))
         );
         assert_eq!(
             Scanner::new("123.345.890").next(),
-            Err(Error::BadNumber(1, "123.345.890".to_owned()))
+            Err(ScanError::BadNumber("123.345.890".to_owned()))
         );
     }
 
@@ -666,25 +831,35 @@ mod tests {
             Scanner::new("\"\\0 \\t \\\\ \\n \\\" \"").next(),
             Ok(Token::String(Str::from("\0 \t \\ \n \" ")))
         );
+        assert_eq!(
+            Scanner::new("\"λx → 中文 😀\"").next(),
+            Ok(Token::String(Str::from("λx → 中文 😀")))
+        );
+        assert_eq!(
+            Scanner::new("\"\\u{3bb}x \\u{1f600}\"").next(),
+            Ok(Token::String(Str::from("λx 😀")))
+        );
     }
 
     #[test]
     fn scan_invalid_strings() {
         assert_eq!(
             Scanner::new("\"Hello, \n World!\"").next(),
-            Err(Error::MultiLineString(1))
+            Err(ScanError::MultiLineString)
         );
-        assert_eq!(Scanner::new("\"Hello, World!").next(), Err(Error::Eof(1)),);
-        // Note because the unicode char spans more than one byte the passed
-        // \u{2000} is not what we expect as the bad byte/char
+        assert_eq!(Scanner::new("\"Hello, World!").next(), Err(ScanError::Eof),);
         assert_eq!(
             Scanner::new("\"\u{2000}\x02\"").next(),
-            Ok(Token::String(Str::from(&vec![24u8, 24u8, 24u8, 24u8][..])))
+            Ok(Token::String(Str::from("\u{2000}\x02")))
+        );
+        assert_eq!(
+            Scanner::new("\"\\u{110000}\"").next(),
+            Err(ScanError::BadEscape("\\u{110000}".to_string()))
         );
         // Not exhaustive
         assert_eq!(
             Scanner::new("\"Hello, \\h World!\"").next(),
-            Err(Error::BadEscape(1, "\\h".to_string()))
+            Err(ScanError::BadEscape("\\h".to_string()))
         );
     }
 }
@@ -1,5 +1,59 @@
-//use rusp::*;
+use rusp::interpret::{Interpreter, Repl};
 
+const USAGE: &str = "usage: rusp [run] [file.rusp [args ...]]";
+
+// With no arguments start the repl. Otherwise run the given file, passing any
+// remaining arguments to the program. `run` is optional so that a script with
+// a shebang line like `#!/usr/bin/env rusp` can be executed directly.
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let code = match args.first().map(|s| s.as_str()) {
+        None => repl(),
+        Some("-h") | Some("--help") => {
+            println!("{USAGE}");
+            0
+        }
+        Some("run") => match args.get(1) {
+            Some(path) => run(path, &args[2..]),
+            None => {
+                eprintln!("{USAGE}");
+                2
+            }
+        },
+        Some(path) => run(path, &args[1..]),
+    };
+    std::process::exit(code);
+}
+
+fn repl() -> i32 {
+    match Repl::new().run() {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("rusp: {e}");
+            1
+        }
+    }
+}
+
+fn run(path: &str, args: &[String]) -> i32 {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("rusp: cannot read {path}: {e}");
+            return 1;
+        }
+    };
+
+    match Interpreter::new().init().eval_script(path, &text, args) {
+        Ok(_) => 0,
+        // Located errors already name the file
+        Err(e) if e.span().is_some() => {
+            eprintln!("rusp: {e}");
+            1
+        }
+        Err(e) => {
+            eprintln!("rusp: {path}: {e}");
+            1
+        }
+    }
 }
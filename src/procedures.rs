@@ -1,7 +1,10 @@
 pub mod collections;
+pub mod equivalence;
 pub mod errors;
+pub mod io;
 pub mod lists;
 pub mod maps;
 pub mod math;
+pub mod symbols;
 pub mod vars;
 pub mod vectors;
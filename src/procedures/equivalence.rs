@@ -0,0 +1,64 @@
+use crate::data::{Error, Val};
+use std::rc::Rc;
+
+// Predicates //
+
+// Whether two values are the same value. Atoms are compared by value, with
+// numbers only equal if they are both exact or both inexact. Everything else
+// is only eqv? to itself, so two strings or lists with the same contents are
+// not eqv? unless they are the same object.
+pub fn is_eqv(args: &[Val]) -> Result<Val, Error> {
+    match args.len() {
+        2 => Ok(Val::Bool(eqv(&args[0], &args[1]))),
+        _ => Err(Error::Arity("eqv?")),
+    }
+}
+
+fn eqv(a: &Val, b: &Val) -> bool {
+    match (a, b) {
+        (Val::Number(x), Val::Number(y)) => x.is_flt() == y.is_flt() && x == y,
+        (Val::Symbol(_), Val::Symbol(_))
+        | (Val::Keyword(_), Val::Keyword(_))
+        | (Val::Bool(_), Val::Bool(_))
+        | (Val::Char(_), Val::Char(_))
+        | (Val::Empty, Val::Empty)
+        | (Val::None, Val::None) => a == b,
+        (Val::String(x), Val::String(y)) => Rc::ptr_eq(x, y),
+        (Val::List(x), Val::List(y)) => Rc::ptr_eq(x, y),
+        (Val::Vector(x), Val::Vector(y)) => Rc::ptr_eq(x, y),
+        (Val::Map(x), Val::Map(y)) => Rc::ptr_eq(x, y),
+        (Val::Procedure(x), Val::Procedure(y)) => Rc::ptr_eq(x, y),
+        (Val::Closure(x), Val::Closure(y)) => Rc::ptr_eq(x, y),
+        (Val::Macro(x), Val::Macro(y)) => Rc::ptr_eq(x, y),
+        (Val::Var(x), Val::Var(y)) => Rc::ptr_eq(x, y),
+        (Val::Error(x), Val::Error(y)) => Rc::ptr_eq(x, y),
+        _ => false,
+    }
+}
+
+// Testing ////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eqv() {
+        let t = Val::Bool(true);
+        let f = Val::Bool(false);
+        assert_eq!(is_eqv(&[Val::from(1), Val::from(1)]), Ok(t.clone()));
+        assert_eq!(is_eqv(&[Val::from(1), Val::from(1.0)]), Ok(f.clone()));
+        assert_eq!(is_eqv(&[Val::from(1.5), Val::from(1.5)]), Ok(t.clone()));
+        assert_eq!(
+            is_eqv(&[Val::keyword("a"), Val::keyword(":a")]),
+            Ok(t.clone())
+        );
+        assert_eq!(is_eqv(&[Val::symbol("a"), Val::symbol("b")]), Ok(f.clone()));
+        assert_eq!(is_eqv(&[Val::from('a'), Val::from('a')]), Ok(t.clone()));
+        assert_eq!(is_eqv(&[Val::from("a"), Val::from("a")]), Ok(f.clone()));
+        let s = Val::from("a");
+        assert_eq!(is_eqv(&[s.clone(), s]), Ok(t));
+        assert_eq!(is_eqv(&[Val::from(1), Val::from('a')]), Ok(f));
+        assert_eq!(is_eqv(&[Val::from(1)]), Err(Error::Arity("eqv?")));
+    }
+}
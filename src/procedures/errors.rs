@@ -1,7 +1,14 @@
-use crate::data::{Error, Val};
+use crate::data::{Error, Map, Val};
 
+// Raising //
+
+// A single error value is raised again as it is, keeping its kind and location
 pub fn throw(args: &[Val]) -> Result<Val, Error> {
     match args.len() {
+        1 => match args[0].clone() {
+            Val::Error(err) => Err(err.as_ref().clone()),
+            val => Err(Error::ArgType("throw", "error", val)),
+        },
         2.. => {
             match args[0].clone() {
                 Val::Keyword(_) => (),
@@ -20,3 +27,77 @@ pub fn throw(args: &[Val]) -> Result<Val, Error> {
         _ => Err(Error::Arity("throw")),
     }
 }
+
+// Information //
+
+pub fn is_error(args: &[Val]) -> Result<Val, Error> {
+    match args.len() {
+        1 => match args[0].clone() {
+            Val::Error(_) => Ok(Val::Bool(true)),
+            _ => Ok(Val::Bool(false)),
+        },
+        _ => Err(Error::Arity("error?")),
+    }
+}
+
+pub fn error_kind(args: &[Val]) -> Result<Val, Error> {
+    match args.len() {
+        1 => match args[0].clone() {
+            Val::Error(err) => Ok(err.kind()),
+            val => Err(Error::ArgType("error-kind", "error", val)),
+        },
+        _ => Err(Error::Arity("error-kind")),
+    }
+}
+
+pub fn error_message(args: &[Val]) -> Result<Val, Error> {
+    match args.len() {
+        1 => match args[0].clone() {
+            Val::Error(err) => Ok(Val::from(err.message().as_str())),
+            val => Err(Error::ArgType("error-message", "error", val)),
+        },
+        _ => Err(Error::Arity("error-message")),
+    }
+}
+
+pub fn error_irritants(args: &[Val]) -> Result<Val, Error> {
+    match args.len() {
+        1 => match args[0].clone() {
+            Val::Error(err) => Ok(Val::list_from_vec(&err.irritants())),
+            val => Err(Error::ArgType("error-irritants", "error", val)),
+        },
+        _ => Err(Error::Arity("error-irritants")),
+    }
+}
+
+// Conversion //
+
+// {:kind :message :irritants} with :procedure and :location when they are known
+pub fn error_to_map(args: &[Val]) -> Result<Val, Error> {
+    match args.len() {
+        1 => match args[0].clone() {
+            Val::Error(err) => {
+                let mut map = Map::map(&[
+                    (Val::keyword("kind"), err.kind()),
+                    (Val::keyword("message"), Val::from(err.message().as_str())),
+                    (
+                        Val::keyword("irritants"),
+                        Val::list_from_vec(&err.irritants()),
+                    ),
+                ])?;
+                if let Some(name) = err.procedure() {
+                    map.assoc(Val::keyword("procedure"), Val::symbol(name))?;
+                }
+                if let Some(span) = err.span() {
+                    map.assoc(
+                        Val::keyword("location"),
+                        Val::from(span.to_string().as_str()),
+                    )?;
+                }
+                Ok(Val::from(map))
+            }
+            val => Err(Error::ArgType("error->map", "error", val)),
+        },
+        _ => Err(Error::Arity("error->map")),
+    }
+}
@@ -0,0 +1,24 @@
+use crate::data::{DisplayRep, Error, Val};
+use std::io::Write;
+
+// Output //
+
+// Stdout is line buffered, so without a newline the text is flushed by hand,
+// e.g. for a prompt printed before reading input
+pub fn print(args: &[Val]) -> Result<Val, Error> {
+    print!("{}", display_all(args));
+    let _ = std::io::stdout().flush();
+    Ok(Val::None)
+}
+
+pub fn println(args: &[Val]) -> Result<Val, Error> {
+    println!("{}", display_all(args));
+    Ok(Val::None)
+}
+
+fn display_all(args: &[Val]) -> String {
+    args.iter()
+        .map(|v| v.to_display())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
@@ -92,3 +92,68 @@ pub fn add(left: &Val, right: &Val) -> Result<Val, Error> {
 pub fn sum(args: &[Val]) -> Result<Val, Error> {
     args.iter().fold(Ok(Val::from(0)), |acc, x| add(&acc?, x))
 }
+
+pub fn sub(left: &Val, right: &Val) -> Result<Val, Error> {
+    match (left, right) {
+        (Val::Number(l), Val::Number(r)) => Ok(Val::Number(l.sub(r)?)),
+        (Val::Number(_), v) => Err(Error::ArgType("sub", "number", v.clone())),
+        (v, Val::Number(_)) => Err(Error::ArgType("sub", "number", v.clone())),
+        _ => Err(Error::ArgType(
+            "sub",
+            "number",
+            Val::from(vec![left.clone(), right.clone()]),
+        )),
+    }
+}
+
+// With one argument the argument is negated
+pub fn difference(args: &[Val]) -> Result<Val, Error> {
+    match args.len() {
+        0 => Err(Error::Arity("-")),
+        1 => sub(&Val::from(0), &args[0]),
+        _ => args[1..]
+            .iter()
+            .try_fold(args[0].clone(), |acc, x| sub(&acc, x)),
+    }
+}
+
+pub fn mult(left: &Val, right: &Val) -> Result<Val, Error> {
+    match (left, right) {
+        (Val::Number(l), Val::Number(r)) => Ok(Val::Number(l.mult(r)?)),
+        (Val::Number(_), v) => Err(Error::ArgType("mult", "number", v.clone())),
+        (v, Val::Number(_)) => Err(Error::ArgType("mult", "number", v.clone())),
+        _ => Err(Error::ArgType(
+            "mult",
+            "number",
+            Val::from(vec![left.clone(), right.clone()]),
+        )),
+    }
+}
+
+pub fn product(args: &[Val]) -> Result<Val, Error> {
+    args.iter().try_fold(Val::from(1), |acc, x| mult(&acc, x))
+}
+
+pub fn div(left: &Val, right: &Val) -> Result<Val, Error> {
+    match (left, right) {
+        (Val::Number(l), Val::Number(r)) => Ok(Val::Number(l.div(r)?)),
+        (Val::Number(_), v) => Err(Error::ArgType("div", "number", v.clone())),
+        (v, Val::Number(_)) => Err(Error::ArgType("div", "number", v.clone())),
+        _ => Err(Error::ArgType(
+            "div",
+            "number",
+            Val::from(vec![left.clone(), right.clone()]),
+        )),
+    }
+}
+
+// With one argument the argument is inverted
+pub fn quotient(args: &[Val]) -> Result<Val, Error> {
+    match args.len() {
+        0 => Err(Error::Arity("/")),
+        1 => div(&Val::from(1), &args[0]),
+        _ => args[1..]
+            .iter()
+            .try_fold(args[0].clone(), |acc, x| div(&acc, x)),
+    }
+}
@@ -0,0 +1,50 @@
+use crate::data::{Error, Sym, Val};
+
+// Creation //
+
+// A symbol that is not equal to any other symbol, made from an optional prefix
+// string or symbol. Gensyms cannot be read, so they can be bound in the
+// expansion of a defmacro without capturing the symbols given to it.
+pub fn gensym(args: &[Val]) -> Result<Val, Error> {
+    let prefix = match args.len() {
+        0 => "g".to_string(),
+        1 => match &args[0] {
+            Val::String(s) => s.borrow().to_string(),
+            Val::Symbol(s) => s.to_string(),
+            val => return Err(Error::ArgType("gensym", "string or symbol", val.clone())),
+        },
+        _ => return Err(Error::Arity("gensym")),
+    };
+    Ok(Val::Symbol(Sym::gensym(&prefix)))
+}
+
+// Testing ////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gensym() {
+        let a = gensym(&[]).unwrap();
+        let b = gensym(&[]).unwrap();
+        assert_ne!(a, b);
+        assert!(a.to_string().starts_with("g#"));
+        assert!(gensym(&[Val::from("tmp")])
+            .unwrap()
+            .to_string()
+            .starts_with("tmp#"));
+        assert!(gensym(&[Val::symbol("x")])
+            .unwrap()
+            .to_string()
+            .starts_with("x#"));
+        assert_eq!(
+            gensym(&[Val::from(1)]),
+            Err(Error::ArgType("gensym", "string or symbol", Val::from(1)))
+        );
+        assert_eq!(
+            gensym(&[Val::symbol("x"), Val::symbol("y")]),
+            Err(Error::Arity("gensym"))
+        );
+    }
+}
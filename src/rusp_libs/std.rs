@@ -10,16 +10,11 @@ pub const RUSP_LIB_STD: &str = r#";; Derived Expressions ;;;;;;;;;;;;;;;;;;;;;;;
   [(let* [binding binding* ...] body body* ...)
    (let [binding] (let* [binding* ...] body body* ...))])
 
-;; This feels extra scuffed, using 0 for the dummy value, because #none would
-;; not work with the template and there is no undefined value.
-;
-; (macro-rules letrec* []
-;   [(letrec* [] body body* ...)
-;    (let [] body body* ...)]  
-;   [(letrec* [(name init) ...] body body* ...)
-;    (let [(name init) ...]
-;       (let* [(name init) ...]
-;         body body* ...))])
+;; The inits are evaluated in order in a scope that already binds every name,
+;; so they can refer to each other, e.g. in mutually recursive procedures.
+(macro-rules letrec* []
+  [(letrec* [(name init) ...] body body* ...)
+   ((lambda [] (def name init) ... body body* ...))])
 
 (macro-rules and []
   [(and) #t]
@@ -27,56 +22,103 @@ pub const RUSP_LIB_STD: &str = r#";; Derived Expressions ;;;;;;;;;;;;;;;;;;;;;;;
   [(and test test* ...)
    (if test (and test* ...) #f)])
 
-;; Note for macros that create bindings like this naming needs to be
-;; very specific so that it will not clash with variables defined in
-;; the outside environment. For example, if we use x below then non-negative?
-;; will not work as it binds x and x in the or expansion will refer to it
-;; rather than the desired x. This is a problem with macros right now and they
-;; require a rewrite.
+;; The x bound here is renamed when or is expanded, so it will not capture an
+;; x passed to or, like the one in non-negative?.
 (macro-rules or []
   [(or) #f]
   [(or test) test]
   [(or test test* ...)
-   (let [(!!-or-x-!! test)]
-     (if !!-or-x-!! !!-or-x-!! (or test* ...)))])
+   (let [(x test)]
+     (if x x (or test* ...)))])
+
+(macro-rules when []
+  [(when test body body* ...)
+   (if test (do body body* ...))])
+
+(macro-rules unless []
+  [(unless test body body* ...)
+   (if test #none (do body body* ...))])
+
+;; A clause is (test body ...), (test => f) to call f with the value of the
+;; test, or (test) to return the value of the test. An else clause must be last.
+(macro-rules cond [else =>]
+  [(cond) (if #f #f)]
+  [(cond (else body body* ...))
+   (do body body* ...)]
+  [(cond (test => f) clause ...)
+   (let [(x test)]
+     (if x (f x) (cond clause ...)))]
+  [(cond (test) clause ...)
+   (or test (cond clause ...))]
+  [(cond (test body body* ...) clause ...)
+   (if test (do body body* ...) (cond clause ...))])
+
+;; A clause is ((datum ...) body ...) and runs if the key is eqv? to one of the
+;; datums, which are not evaluated. An else clause must be last. case-check
+;; checks every clause before the key is bound, and expands a bad one to (case)
+;; so that it is reported as an error in case. The clauses are then tested by
+;; case-dispatch with the key evaluated once.
+(macro-rules case-dispatch [else]
+  [(case-dispatch key)
+   (if #f #f)]
+  [(case-dispatch key (else body body* ...))
+   (do body body* ...)]
+  [(case-dispatch key ((datum ...) body body* ...) clause ...)
+   (if (or (eqv? key 'datum) ...)
+     (do body body* ...)
+     (case-dispatch key clause ...))])
+
+(macro-rules case-check [else]
+  [(case-check key (clause ...))
+   (let [(k key)] (case-dispatch k clause ...))]
+  [(case-check key clauses (else body body* ...))
+   (case-check key clauses)]
+  [(case-check key clauses ((datum ...) body body* ...) clause ...)
+   (case-check key clauses clause ...)]
+  [(case-check key clauses clause ...)
+   (case)])
+
+(macro-rules case []
+  [(case key clause ...)
+   (case-check key (clause ...) clause ...)])
 
 
 ;; Booleans ;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
 
-(def not (lambda [x] (if x #f #t)))
+(defn not [x] (if x #f #t))
 
 
 ;; Collections ;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
 
-(def first (lambda [xs] (nth 0 xs)))
-(def second (lambda [xs] (nth 1 xs)))
-(def third (lambda [xs] (nth 2 xs)))
-(def fourth (lambda [xs] (nth 3 xs)))
-(def fifth (lambda [xs] (nth 4 xs)))
+(defn first [xs] (nth 0 xs))
+(defn second [xs] (nth 1 xs))
+(defn third [xs] (nth 2 xs))
+(defn fourth [xs] (nth 3 xs))
+(defn fifth [xs] (nth 4 xs))
 
-(def empty? (lambda [xs] (= 0 (length xs))))
+(defn empty? [xs] (= 0 (length xs)))
 
 
 ;; Math/Numbers ;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
 
-(def exact? (lambda [x] (and (number? x) (not (float? x)))))
-(def zero? (lambda [x] (and (number? x) (= 0 x))))
-(def positive? (lambda [x] (and (number? x) (> x 0))))
-(def negative? (lambda [x] (and (number? x) (< x 0))))
-(def non-negative? (lambda [x] (or (zero? x) (positive? x))))
+(defn exact? [x] (and (number? x) (not (float? x))))
+(defn zero? [x] (and (number? x) (= 0 x)))
+(defn positive? [x] (and (number? x) (> x 0)))
+(defn negative? [x] (and (number? x) (< x 0)))
+(defn non-negative? [x] (or (zero? x) (positive? x)))
 
-(def inc (lambda [x] (+ x 1)))
+(defn inc [x] (+ x 1))
 
 
 ;; Vars ;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
 ;; TODO these could use some more specific error handling
 
-(def swap! (lambda [x v]
-             (let [(a (deref x))]
-               (set! x v)
-               a)))
+(defn swap! [x v]
+  (let [(a (deref x))]
+    (set! x v)
+    a))
 
-(def update! (lambda [x f]
-             (let [(a (deref x))]
-               (set! x (f a))
-               a)))"#;
\ No newline at end of file
+(defn update! [x f]
+  (let [(a (deref x))]
+    (set! x (f a))
+    a))"#;
\ No newline at end of file
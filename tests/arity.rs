@@ -0,0 +1,162 @@
+mod helpers;
+use helpers::eval_assert;
+
+#[test]
+fn test_exact_arity() {
+    eval_assert(
+        "(defn f [x] x) (f 1 2)",
+        "1:16: Error in f: expected 1 argument, got 2\n  in f at 1:16",
+    );
+    eval_assert(
+        "(defn f [x y] x) (f 1)",
+        "1:18: Error in f: expected 2 arguments, got 1\n  in f at 1:18",
+    );
+    eval_assert(
+        "((lambda [] 1) 2)",
+        "1:1: Error in lambda: expected 0 arguments, got 1\n  in lambda at 1:1",
+    );
+    eval_assert(
+        "(defn f [x . xs] x) (f)",
+        "1:21: Error in f: expected at least 1 argument, got 0\n  in f at 1:21",
+    );
+    eval_assert("(defn f [x . xs] xs) (f 1)", "#()");
+    eval_assert("(defn f xs xs) (f)", "#()");
+}
+
+#[test]
+fn test_arity_errors_can_be_caught() {
+    eval_assert(
+        "(defn f [x] x) (try (f) (catch :arity e (error-irritants e)))",
+        "(0)",
+    );
+    eval_assert(
+        "(defn f [x] x) (try (f) (catch e (error-message e)))",
+        "\"expected 1 argument, got 0\"",
+    );
+}
+
+#[test]
+fn test_multi_arity() {
+    eval_assert(
+        "(def f (lambda ([] 0) ([x] x) ([x y] (+ x y)))) (list (f) (f 5) (f 1 2))",
+        "(0 5 3)",
+    );
+    eval_assert(
+        "(defn f ([x] (f x 10)) ([x y] (* x y))) (list (f 2) (f 2 3))",
+        "(20 6)",
+    );
+    eval_assert(
+        "(defn f ([x] :one) ([x . xs] :many)) (list (f 1) (f 1 2 3))",
+        "(:one :many)",
+    );
+    eval_assert("(defn f ([[a b]] (+ a b)) ([a b] (* a b))) (f [1 2])", "3");
+    eval_assert(
+        "(defn f ([] 0) ([x y] 2) ([x y z . r] 3)) (f 1)",
+        "1:43: Error in f: expected 0, 2 or at least 3 arguments, got 1\n  in f at 1:43",
+    );
+    eval_assert(
+        "(lambda ([x] x) 5)",
+        "1:1: Error in lambda: 5 must be a ([params ...] body ...) clause",
+    );
+}
+
+#[test]
+fn test_optional_args() {
+    eval_assert(
+        "(defn f [a &opt b (c 3)] (list a b c)) (list (f 1) (f 1 2) (f 1 2 4))",
+        "((1 #none 3) (1 2 3) (1 2 4))",
+    );
+    eval_assert("(defn f [a &opt (b (* a 2))] b) (f 4)", "8");
+    eval_assert(
+        "(defn f [a &opt (b (* a 2))] b) (list (f 1) (f 2) (f 3 4))",
+        "(2 4 4)",
+    );
+    eval_assert(
+        "(defn f [a &opt b] a) (f 1 2 3)",
+        "1:23: Error in f: expected 1 to 2 arguments, got 3\n  in f at 1:23",
+    );
+}
+
+#[test]
+fn test_keyword_args() {
+    eval_assert(
+        "(defn connect [host &key (port 80) (timeout (* port 2))] (list host port timeout))
+         (list (connect :h) (connect :h :port 8080) (connect :h :timeout 5 :port 1))",
+        "((:h 80 160) (:h 8080 16160) (:h 1 5))",
+    );
+    eval_assert("(defn f [&key a] a) (f)", "#none");
+    eval_assert("(defn f [&key a] a) (f :a 1 :a 2)", "2");
+    eval_assert(
+        "(defn f [&opt (a 1) &key b] (list a b)) (list (f) (f 5) (f 5 :b 2))",
+        "((1 #none) (5 #none) (5 2))",
+    );
+    eval_assert(
+        "(defn f [&opt (a 1) &key b] (list a b)) (list (f :b 2) (f :c))",
+        "((1 2) (:c #none))",
+    );
+}
+
+#[test]
+fn test_keyword_arg_errors() {
+    eval_assert(
+        "(defn f [&key a] a) (f :b 1)",
+        "1:21: Error in f: invalid keyword argument: :b\n  in f at 1:21",
+    );
+    eval_assert(
+        "(defn f [&key a] a) (f :a)",
+        "1:21: Error in f: invalid keyword argument: :a\n  in f at 1:21",
+    );
+    eval_assert(
+        "(defn f [&key a] a) (f 5 6)",
+        "1:21: Error in f: invalid keyword argument: 5\n  in f at 1:21",
+    );
+    eval_assert(
+        "(defn f [x &key a] a) (f)",
+        "1:23: Error in f: expected at least 1 argument, got 0\n  in f at 1:23",
+    );
+    eval_assert(
+        "(lambda [&key a &opt b] 1)",
+        "1:1: Error: invalid parameter: [&key a &opt b]",
+    );
+    eval_assert(
+        "(lambda [a . b &key c] 1)",
+        "1:1: Error: invalid parameter: [a . b &key c]",
+    );
+    eval_assert(
+        "(lambda [&opt (a)] 1)",
+        "1:1: Error: invalid parameter: [&opt (a)]",
+    );
+}
+
+#[test]
+fn test_shadowed_clauses() {
+    eval_assert(
+        "(lambda ([x] 1) ([y] 2))",
+        "1:17: Error: earlier clauses accept every argument count of ([y] 2)",
+    );
+    eval_assert(
+        "(lambda ([x] 1) ([x y] 2) ([x &opt y] 3))",
+        "1:27: Error: earlier clauses accept every argument count of ([x &opt y] 3)",
+    );
+    eval_assert(
+        "(lambda ([. xs] 1) ([x] 2))",
+        "1:20: Error: earlier clauses accept every argument count of ([x] 2)",
+    );
+    eval_assert(
+        "(lambda ([x &key k] 1) ([x y z] 2))",
+        "1:24: Error: earlier clauses accept every argument count of ([x y z] 2)",
+    );
+    eval_assert(
+        "(defmacro m ([] 1) ([] 2))",
+        "1:20: Error: earlier clauses accept every argument count of ([] 2)",
+    );
+    eval_assert(
+        "(defn f [] (lambda ([] 1) ([] 2)))",
+        "1:27: Error: earlier clauses accept every argument count of ([] 2)",
+    );
+    // a clause that accepts a count no earlier clause does is applied
+    eval_assert(
+        "(def f (lambda ([x] 1) ([x &opt y z] 2) ([. xs] 3))) [(f 1) (f 1 2) (f) (f 1 2 3 4)]",
+        "[1 2 3 3]",
+    );
+}
@@ -0,0 +1,120 @@
+mod helpers;
+use helpers::eval_assert;
+
+#[test]
+fn test_defn() {
+    eval_assert("(defn f [x] (* x 2)) f", "#<procedure f>");
+    eval_assert("(defn f [x] (* x 2)) (f 4)", "8");
+    eval_assert("(defn f [] 5) (f)", "5");
+    eval_assert("(defn f xs xs) (f 1 2)", "(1 2)");
+    eval_assert("(defn f [x . xs] xs) (f 1 2 3)", "(2 3)");
+    eval_assert("inc", "#<procedure inc>");
+}
+
+#[test]
+fn test_function_defines() {
+    eval_assert("(def (g x y) (+ x y)) g", "#<procedure g>");
+    eval_assert("(def (g x y) (+ x y)) (g 1 2)", "3");
+    eval_assert("(def (g) 5) (g)", "5");
+    eval_assert("(def (g . xs) xs) (g 1 2)", "(1 2)");
+    eval_assert(
+        "(def (fact n) (if (= n 0) 1 (* n (fact (- n 1))))) (fact 5)",
+        "120",
+    );
+    // Only function definitions name their closures
+    eval_assert("(def f (lambda [x] x)) f", "#<closure>");
+}
+
+#[test]
+fn test_named_closures_in_backtraces() {
+    eval_assert(
+        "(defn bad [x] (/ x 0)) (def also bad) (also 1)",
+        "1:15: Error: division by zero\n  in / at 1:15\n  in bad at 1:39",
+    );
+}
+
+#[test]
+fn test_definition_errors() {
+    eval_assert("(defn 5 [x] x)", "1:1: Error in defn: 5 must be symbol");
+    eval_assert("(def (5 x) x)", "1:1: Error in def: 5 must be symbol");
+    eval_assert("(defn f)", "1:1: Error in defn: incorrect argument count");
+    eval_assert("(def)", "1:1: Error in def: incorrect argument count");
+}
+
+#[test]
+fn test_internal_defines() {
+    eval_assert("((lambda [] (def a 5) a))", "5");
+    eval_assert("((lambda [x] (def y (* x 2)) (+ x y)) 3)", "9");
+    eval_assert("((lambda [] (defn f [x] (* x 2)) (f 4)))", "8");
+    eval_assert("((lambda [] (def (f x) (* x 2)) f))", "#<procedure f>");
+    eval_assert("(let [(a 1)] (def b (+ a 1)) (def c (+ b 1)) c)", "3");
+    eval_assert("(def a 1) ((lambda [] (def a 2) a)) a", "1");
+    eval_assert(
+        "(defn evens [n]
+           (defn even? [n] (if (= n 0) #t (odd? (- n 1))))
+           (defn odd? [n] (if (= n 0) #f (even? (- n 1))))
+           (even? n))
+         (list (evens 10) (evens 7))",
+        "(#t #f)",
+    );
+}
+
+#[test]
+fn test_internal_defines_from_macros() {
+    eval_assert(
+        "(defmacro defx [v] `(def x ,v)) ((lambda [] (defx 5) x))",
+        "5",
+    );
+    eval_assert(
+        "(defmacro defx [v] `(def x ,v)) ((lambda [] (defx 5) x)) (list x)",
+        "1:64: Error: undeclared symbol: x",
+    );
+    eval_assert(
+        "(macro-rules defconst [] [(defconst n v) (def n v)])
+         (defn f [] (defconst a 1) (defconst b (+ a 1)) b)
+         (f)",
+        "2",
+    );
+    // a name bound by the body is not a macro use
+    eval_assert(
+        "(defmacro defx [v] `(def x ,v)) ((lambda [defx] (defx 5)) list)",
+        "(5)",
+    );
+}
+
+#[test]
+fn test_internal_define_errors() {
+    eval_assert(
+        "((lambda [] (def a b) (def b 1) a))",
+        "1:13: Error: b used before its definition\n  in lambda at 1:1",
+    );
+    eval_assert("(def a a)", "1:6: Error: a used before its definition");
+    eval_assert(
+        "((lambda [] (defn 5 [] 1) 1))",
+        "1:13: Error in defn: 5 must be symbol",
+    );
+    // Only leading forms are definitions
+    eval_assert(
+        "((lambda [] 1 (def a 2) a))",
+        "1:16: Error: undeclared symbol: def\n  in lambda at 1:1",
+    );
+}
+
+#[test]
+fn test_lexical_scope() {
+    eval_assert(
+        "(defn adder [a] (lambda [b] (lambda [c] (+ a b c)))) (((adder 1) 2) 3)",
+        "6",
+    );
+    eval_assert(
+        "(defn f [x] (let [(x (+ x 1))] (lambda [] x))) ((f 1))",
+        "2",
+    );
+    eval_assert("(defn f [car] car) (f 3)", "3");
+    eval_assert("(defn f [x] (def x 2) x) (f 1)", "2");
+    eval_assert("(defn f [] (def g (lambda [] h)) (def h 5) (g)) (f)", "5");
+    eval_assert(
+        "(loop [(i 0) (f #f)] (if (= i 3) (f) (recur (+ i 1) (lambda [] i))))",
+        "2",
+    );
+}
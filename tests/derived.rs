@@ -21,24 +21,31 @@ fn test_let_star_macro() {
 }
 
 #[test]
-#[ignore]
 fn test_letrec_star_macro() {
-    // This will not work until the macro system is re-written
-    // and we can properly capture and expand something like (hello world) ...
     eval_assert("letrec*", "#<macro letrec*>");
-    eval_assert("(macro-expand (letrec* [] (+ 1 2)))", "(let [] (+ 1 2))");
     eval_assert(
-        "(macro-expand (letrec* [(a 5)] (+ 1 2)))",
-        "(let [] (+ 1 2))",
+        "(macro-expand (letrec* [] (+ 1 2)))",
+        "((lambda [] (+ 1 2)))",
+    );
+    eval_assert(
+        "(macro-expand (letrec* [(a 5) (b 6)] (+ a b)))",
+        "((lambda [] (def a 5) (def b 6) (+ a b)))",
     );
     eval_assert(
         "(letrec* [(f (lambda [x y]
-                        (if (>= x y)
+                        (if (= x y)
                           y
-                          (f (inc x)))))]
+                          (f (inc x) y))))]
            (f 0 10))",
         "10",
     );
+    eval_assert("(letrec* [(a 5) (b (+ a 1))] (* a b))", "30");
+    eval_assert(
+        "(letrec* [(even? (lambda [n] (if (= n 0) #t (odd? (- n 1)))))
+                   (odd? (lambda [n] (if (= n 0) #f (even? (- n 1)))))]
+           [(even? 10) (odd? 7) (even? 3)])",
+        "[#t #t #f]",
+    );
 }
 
 #[test]
@@ -69,3 +76,161 @@ fn test_or_macro() {
     eval_assert("(or #f #none #f)", "#f");
     eval_assert("(or #f #f #f)", "#f");
 }
+
+#[test]
+fn test_do() {
+    eval_assert("(do)", "#none");
+    eval_assert("(do 1)", "1");
+    eval_assert("(def a (var 0)) (do (set! a 5) (+ (deref a) 1))", "6");
+    eval_assert(
+        "(defn count [n] (if (= n 0) :done (do n (count (- n 1))))) (count 100000)",
+        ":done",
+    );
+}
+
+#[test]
+fn test_when_and_unless_macros() {
+    eval_assert("(when (= 1 1) 1 2)", "2");
+    eval_assert("(when (= 1 2) 1 2)", "#none");
+    eval_assert("(unless (= 1 2) 1 2)", "2");
+    eval_assert("(unless (= 1 1) 1 2)", "#none");
+    eval_assert("(unless #t 1)", "#none");
+}
+
+#[test]
+fn test_cond_macro() {
+    eval_assert("(cond)", "#none");
+    eval_assert("(cond ((= 1 2) :a) ((= 1 1) :b) (else :c))", ":b");
+    eval_assert("(cond ((= 1 2) :a) (else 1 :c))", ":c");
+    eval_assert("(cond ((= 1 2) :a))", "#none");
+    eval_assert("(cond (5 => inc))", "6");
+    eval_assert("(cond (#f) (7))", "7");
+    eval_assert("(let [(else #f)] (cond (else 1) (#t 2)))", "2");
+    eval_assert(
+        "(defn count [n] (cond ((= n 0) :done) (else (count (- n 1))))) (count 100000)",
+        ":done",
+    );
+}
+
+#[test]
+fn test_case_macro() {
+    eval_assert(
+        "(case (+ 1 1) ((1) :one) ((2 3) :two-or-three) (else :other))",
+        ":two-or-three",
+    );
+    eval_assert("(case :b ((:a) 1) ((:b) 2))", "2");
+    eval_assert("(case \\b ((\\a) 1) ((\\b) 2))", "2");
+    eval_assert("(case 9 ((:a) 1) (else 0))", "0");
+    eval_assert("(case 9 ((:a) 1))", "#none");
+    eval_assert("(case 1.0 ((1) :exact) (else :inexact))", ":inexact");
+    eval_assert(
+        "(def n (var 0)) (case (do (set! n (+ (deref n) 1)) 3) ((1) 1) ((2) 2) ((3) 3)) (deref n)",
+        "1",
+    );
+    eval_assert("(def k 5) (case k ((5) k) (else 0))", "5");
+    eval_assert("(case [1] ((1) :a) (else :no))", ":no");
+    // datums are not evaluated
+    eval_assert("(case 'a ((a) 1) (else 2))", "1");
+    eval_assert("(def x 5) (case 5 ((x) :hit) (else :miss))", ":miss");
+    eval_assert("(def x 5) (case 'x ((x) :hit) (else :miss))", ":hit");
+    // malformed clauses are errors in case
+    eval_assert(
+        "(case 1 (1 :a))",
+        "1:1: Error in case: no macro rule matches",
+    );
+    eval_assert(
+        "(case 2 ((1) :a) (else :b) ((2) :c))",
+        "1:1: Error in case: no macro rule matches",
+    );
+}
+
+#[test]
+fn test_macros_do_not_capture_variables() {
+    // or binds a variable of its own, which must not capture the x given to it
+    eval_assert("(non-negative? 0)", "#t");
+    eval_assert("(non-negative? 5)", "#t");
+    eval_assert("(non-negative? -5)", "#f");
+    eval_assert("(let [(x #f)] (or x 5))", "5");
+    eval_assert("(let [(x 3)] (or #f x))", "3");
+    eval_assert(
+        "(macro-rules swap [] [(swap a b) (let [(tmp @a)] (set! a @b) (set! b tmp))])
+         (def tmp (var 1))
+         (def other (var 2))
+         (swap tmp other)
+         [@tmp @other]",
+        "[2 1]",
+    );
+}
+
+#[test]
+fn test_macros_keep_their_definition_bindings() {
+    // if and let still refer to the special form and macro inside the expansion
+    eval_assert(
+        "(macro-rules my-if [] [(my-if c a b) (let [(t c)] (if t a b))])
+         (let [(t #f)] (my-if #t t 2))",
+        "#f",
+    );
+    eval_assert(
+        "(def helper (lambda [x] (* x 2)))
+         (macro-rules double [] [(double x) (helper x)])
+         (double 21)",
+        "42",
+    );
+    eval_assert(
+        "(def helper (lambda [x] (* x 2)))
+         (macro-rules double [] [(double x) (helper x)])
+         (let [(helper (lambda [x] x))] (double 21))",
+        "42",
+    );
+    eval_assert("(macro-rules sym [] [(sym) 'x]) (sym)", "x");
+    eval_assert(
+        "(macro-rules pick [else] [(pick else x) x] [(pick y x) 0])
+         (macro-rules pick-else [] [(pick-else x) (pick else x)])
+         (pick-else 5)",
+        "5",
+    );
+}
+
+#[test]
+fn test_macros_with_nested_elipses() {
+    eval_assert(
+        "(macro-rules rotate [] [(rotate (a b ...) ...) '((b ... a) ...)])
+         (rotate (1 2 3) (4) (5 6))",
+        "((2 3 1) (4) (6 5))",
+    );
+    eval_assert(
+        "(macro-rules zip [] [(zip (a ...) (b ...)) '([a b] ...)])
+         (zip (1 2) (3 4))",
+        "([1 3] [2 4])",
+    );
+    eval_assert(
+        "(macro-rules zip [] [(zip (a ...) (b ...)) '([a b] ...)])
+         (zip (1 2) (3))",
+        "2:10: Error: a and b matched different numbers of forms but share a ...",
+    );
+}
+
+#[test]
+fn test_macros_with_maps() {
+    eval_assert(
+        "(macro-rules get-a [] [(get-a {:a x}) x] [(get-a other) :no-match])
+         [(get-a {:a (+ 1 2)}) (get-a {:a 1 :b 2}) (get-a [1])]",
+        "[3 :no-match :no-match]",
+    );
+    eval_assert(
+        "(macro-rules others [] [(others {:a x ... rest}) 'rest])
+         [(others #{:a 1 :b 2}) (others {:a 1 :c 3})]",
+        "[#{:b 2} {:c 3}]",
+    );
+    eval_assert(
+        "(macro-rules rename-a [] [(rename-a {:a x ... rest}) #{:x x ... rest}])
+         (let [(m (rename-a {:a 1 :b (+ 1 1)}))]
+           [(dict? m) (length m) (get m :x) (get m :b)])",
+        "[#t 2 1 2]",
+    );
+    eval_assert(
+        "(macro-rules entry [] [(entry k v) {k v}])
+         (entry :a (+ 1 2))",
+        "{:a 3}",
+    );
+}
@@ -0,0 +1,78 @@
+mod helpers;
+use helpers::eval_assert;
+
+#[test]
+fn test_destructuring_sequences() {
+    eval_assert("((lambda [[a b] c] (list a b c)) '(1 2) 3)", "(1 2 3)");
+    eval_assert("((lambda [[a b]] (list a b)) [1 2])", "(1 2)");
+    eval_assert("((lambda [[a b]] (list a b)) #[1 2])", "(1 2)");
+    eval_assert("((lambda [[]] 1) '())", "1");
+    eval_assert(
+        "((lambda [[a [b c]] . r] (list a b c r)) [1 [2 3]] 4 5)",
+        "(1 2 3 (4 5))",
+    );
+    eval_assert("((lambda [[a . r]] (list a r)) '(1 2 3))", "(1 (2 3))");
+    eval_assert("(defn f [[a b]] (+ a b)) (f [1 2])", "3");
+    eval_assert("(def (f [a b]) (+ a b)) (f [1 2])", "3");
+}
+
+#[test]
+fn test_destructuring_maps() {
+    eval_assert("((lambda [{:keys [x y]}] (list x y)) {:x 1 :y 2})", "(1 2)");
+    eval_assert("((lambda [{:keys [x]}] x) #{:x 1 :y 2})", "1");
+    eval_assert(
+        "((lambda [{:keys [x y] :or {y 10}}] (list x y)) {:x 1})",
+        "(1 10)",
+    );
+    eval_assert(
+        "((lambda [{:keys [x y] :or {y 10}}] (list x y)) {:x 1 :y 2})",
+        "(1 2)",
+    );
+    eval_assert("((lambda [{:as m}] m) {:x 1})", "{:x 1}");
+    eval_assert("((lambda [a {:keys [b] :or {b (* a 2)}}] b) 4 {})", "8");
+    eval_assert("((lambda [[{:keys [a]} b]] (+ a b)) [{:a 1} 2])", "3");
+}
+
+#[test]
+fn test_destructuring_in_let() {
+    eval_assert("(let [([a b] (list 1 2))] (+ a b))", "3");
+    eval_assert("(let [({:keys [a]} {:a 1}) ([b] [2])] (+ a b))", "3");
+    eval_assert("(let* [([a b] [1 2]) (c (+ a b))] c)", "3");
+}
+
+#[test]
+fn test_destructuring_errors() {
+    eval_assert(
+        "((lambda [[a b]] a) 5)",
+        "1:1: Error: cannot destructure 5 with [a b]\n  in lambda at 1:1",
+    );
+    eval_assert(
+        "((lambda [[a b]] a) [1])",
+        "1:1: Error: cannot destructure [1] with [a b]\n  in lambda at 1:1",
+    );
+    eval_assert(
+        "((lambda [[a b]] a) [1 2 3])",
+        "1:1: Error: cannot destructure [1 2 3] with [a b]\n  in lambda at 1:1",
+    );
+    eval_assert(
+        "((lambda [{:keys [x]}] x) {:y 1})",
+        "1:1: Error: cannot destructure {:y 1} with {:keys [x]}\n  in lambda at 1:1",
+    );
+    eval_assert(
+        "((lambda [{:keys [x]}] x) [1])",
+        "1:1: Error: cannot destructure [1] with {:keys [x]}\n  in lambda at 1:1",
+    );
+    eval_assert("(lambda [5] 1)", "1:1: Error: invalid parameter: 5");
+    eval_assert(
+        "(lambda [a . b c] 1)",
+        "1:1: Error: invalid parameter: [a . b c]",
+    );
+    eval_assert(
+        "(lambda [{:as 5}] 1)",
+        "1:1: Error: invalid parameter: {:as 5}",
+    );
+    eval_assert(
+        "((lambda [a . b]))",
+        "1:1: Error in lambda: expected at least 1 argument, got 0\n  in lambda at 1:1",
+    );
+}
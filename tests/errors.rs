@@ -0,0 +1,207 @@
+mod helpers;
+use helpers::eval_assert;
+
+#[test]
+fn test_try_without_errors() {
+    eval_assert("(try)", "#none");
+    eval_assert("(try 1 2 3)", "3");
+    eval_assert("(try (+ 1 2) (catch e 0))", "3");
+}
+
+#[test]
+fn test_catching_builtin_errors() {
+    eval_assert("(try (/ 1 0) (catch e :caught))", ":caught");
+    eval_assert(
+        "(try (/ 1 0) (catch e e))",
+        "#<error 1:6: Error: division by zero>",
+    );
+    eval_assert(
+        "(try (nth #(1 2) :a) (catch :divide-by-zero e 1) (catch :arg-type e 2))",
+        "2",
+    );
+    eval_assert("(try (undeclared-thing) (catch :undeclared e 3))", "3");
+}
+
+#[test]
+fn test_catching_thrown_errors() {
+    eval_assert("(try (throw :oops \"bad\") (catch :oops e 1))", "1");
+    eval_assert(
+        "(try (throw :oops \"bad\" 1 2) (catch e e))",
+        "#<error 1:6: Error :oops: bad 1 2>",
+    );
+    eval_assert(
+        "(def f (lambda [x] (if (= x 0) (throw :done \"zero\") (f (- x 1)))))
+         (try (f 100) (catch :done e :done))",
+        ":done",
+    );
+}
+
+#[test]
+fn test_uncaught_errors_pass_through() {
+    eval_assert(
+        "(try (throw :oops \"bad\") (catch :other e 1))",
+        "1:6: Error :oops: bad\n  in throw at 1:6",
+    );
+    eval_assert(
+        "(try (try (/ 1 0) (catch :oops e 1)) (catch :divide-by-zero e 2))",
+        "2",
+    );
+    // errors in a handler are not caught by the same try
+    eval_assert(
+        "(try (try (/ 1 0) (catch e (throw :again \"x\"))) (catch :again e 3))",
+        "3",
+    );
+}
+
+#[test]
+fn test_finally() {
+    eval_assert(
+        "(def v (var 0))
+         (try 1 (finally (set! v 10)))
+         @v",
+        "10",
+    );
+    eval_assert("(try 1 (finally 2))", "1");
+    eval_assert("(try (/ 1 0) (catch e 5) (finally 2))", "5");
+    eval_assert(
+        "(def v (var 0))
+         (try (try (throw :a \"x\") (finally (set! v 1))) (catch e @v))",
+        "1",
+    );
+}
+
+#[test]
+fn test_bad_try_forms() {
+    eval_assert(
+        "(try (catch e 1) 2)",
+        "1:1: Error in try: 2 must be catch or finally clause",
+    );
+    eval_assert(
+        "(try 1 (finally 2) (catch e 1))",
+        "1:1: Error in try: (catch e 1) must be last clause to be finally",
+    );
+    eval_assert(
+        "(try 1 (catch :kind))",
+        "1:1: Error in catch: (catch :kind) must be (catch [:kind] symbol body ...)",
+    );
+}
+
+#[test]
+fn test_error_values() {
+    eval_assert("(error? (try (/ 1 0) (catch e e)))", "#t");
+    eval_assert("(error? :divide-by-zero)", "#f");
+    eval_assert("(error-kind (try (/ 1 0) (catch e e)))", ":divide-by-zero");
+    eval_assert("(error-kind (try (+ 1 :a) (catch e e)))", ":arg-type");
+    eval_assert("(error-kind (try (= 1) (catch e e)))", ":arity");
+    eval_assert("(error-kind (try (what) (catch e e)))", ":undeclared");
+    eval_assert(
+        "(error-message (try (what) (catch e e)))",
+        "\"undeclared symbol: what\"",
+    );
+    eval_assert("(error-irritants (try (what) (catch e e)))", "(what)");
+    eval_assert("(error-irritants (try (/ 1 0) (catch e e)))", "#()");
+}
+
+#[test]
+fn test_thrown_error_values() {
+    eval_assert(
+        "(def e (try (throw :oops \"bad thing\" 1 :a) (catch e e)))
+         (list (error-kind e) (error-message e) (error-irritants e))",
+        "(:oops \"bad thing\" (1 :a))",
+    );
+    eval_assert(
+        "(error-kind 5)",
+        "1:1: Error in error-kind: 5 must be error\n  in error-kind at 1:1",
+    );
+}
+
+#[test]
+fn test_rethrowing_errors() {
+    eval_assert(
+        "(try (try (/ 1 0) (catch e (throw e))) (catch :divide-by-zero e :outer))",
+        ":outer",
+    );
+    // the original location is kept
+    eval_assert(
+        "(try (/ 1 0) (catch e (throw e)))",
+        "1:6: Error: division by zero\n  in throw at 1:23",
+    );
+}
+
+#[test]
+fn test_error_to_map() {
+    eval_assert(
+        "(def m (error->map (try (+ 1 :a) (catch e e))))
+         (list (get m :kind) (get m :message) (get m :irritants) (get m :procedure) (get m :location))",
+        "(:arg-type \":a must be number\" (:a) add \"1:25\")",
+    );
+    eval_assert(
+        "(def m (error->map (try (throw :oops \"bad\" 1) (catch e e))))
+         (list (get m :kind) (get m :message) (get m :irritants) (get m :procedure))",
+        "(:oops \"bad\" (1) #none)",
+    );
+}
+
+#[test]
+fn test_backtraces() {
+    eval_assert(
+        "(def f (lambda [x] (+ x :a)))
+         (def g (lambda [x] (+ 1 (f x))))
+         (g 1)",
+        "1:20: Error in add: :a must be number\n  in + at 1:20\n  in f at 2:34\n  in g at 3:10",
+    );
+    // tail calls replace the frame of their caller, so g is not in the trace
+    eval_assert(
+        "(def f (lambda [x] (if (= x 0) (throw :done \"end\") (f (- x 1)))))
+         (def g (lambda [] (f 1000)))
+         (g)",
+        "1:32: Error :done: end\n  in throw at 1:32\n  in f at 1:52",
+    );
+    // caught errors have no backtrace
+    eval_assert(
+        "(def f (lambda [x] (+ x :a)))
+         (try (f 1) (catch e 1))
+         (+ 1 (f 2))",
+        "1:20: Error in add: :a must be number\n  in + at 1:20\n  in f at 3:15",
+    );
+    // frames repeated by recursion are collapsed
+    eval_assert(
+        "(defn deep [n] (if (= n 0) (throw :bottom \"deep\") (+ 1 (deep (- n 1)))))
+         (deep 5000)",
+        "1:28: Error :bottom: deep\n  in throw at 1:28\n  in deep at 1:56\n  \
+         ... repeated 4999 more times\n  in deep at 2:10",
+    );
+}
+
+#[test]
+fn test_lambda_bodies_are_compiled_when_evaluated() {
+    eval_assert(
+        "(def f (lambda [] (if))) :defined",
+        "1:19: Error in if: incorrect argument count",
+    );
+    eval_assert(
+        "(defn f [x] (quote)) :defined",
+        "1:13: Error in quote: incorrect argument count",
+    );
+    // the operands of a macro defined after the body are not compiled as forms
+    eval_assert(
+        "(defn f [x] (swap-args (1 :a) x)) (defmacro swap-args [a b] (list 'list b (list 'quote a))) (f 2)",
+        "(2 (1 :a))",
+    );
+    // a body that fails to compile is an error each time it is evaluated
+    eval_assert(
+        "(defn make [] (lambda [] (if)))
+         (list (try (make) (catch e :caught)) (try (make) (catch e :caught)))",
+        "(:caught :caught)",
+    );
+}
+
+#[test]
+fn test_errors_in_atoms_are_located() {
+    eval_assert("1\n2\n  zz", "3:3: Error: undeclared symbol: zz");
+    eval_assert("(list 1\n [1 zz])", "2:5: Error: undeclared symbol: zz");
+    eval_assert("[1 2]\n[3 [4 zz]]", "2:7: Error: undeclared symbol: zz");
+    eval_assert("{:a 1\n :b zz}", "2:5: Error: undeclared symbol: zz");
+    eval_assert("(def a\n  zz)", "2:3: Error: undeclared symbol: zz");
+    eval_assert("(do 1\n    zz)", "2:5: Error: undeclared symbol: zz");
+}
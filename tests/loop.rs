@@ -0,0 +1,91 @@
+mod helpers;
+use helpers::eval_assert;
+
+#[test]
+fn test_loop() {
+    eval_assert("(loop [] 5)", "5");
+    eval_assert("(loop [(i 0)])", "#none");
+    eval_assert(
+        "(loop [(i 0) (acc 0)] (if (= i 1000000) acc (recur (+ i 1) (+ acc i))))",
+        "499999500000",
+    );
+    eval_assert(
+        "((lambda [n] (loop [(i n) (acc 1)] (if (= i 0) acc (recur (- i 1) (* acc i))))) 10)",
+        "3628800",
+    );
+    eval_assert("(def a 1) (loop [(a 2) (b a)] b)", "1");
+    eval_assert(
+        "(loop [(i 0)] (def x 5) (if (= i 2) x (recur (+ i 1))))",
+        "5",
+    );
+}
+
+#[test]
+fn test_recur_through_macros() {
+    eval_assert(
+        "(loop [(i 0)] (cond ((= i 100000) :done) (else (recur (+ i 1)))))",
+        ":done",
+    );
+    eval_assert(
+        "(def n (var 0)) (loop [(i 0)] (when (< i 3) (set! n (+ (deref n) i)) (recur (+ i 1)))) (deref n)",
+        "3",
+    );
+    eval_assert(
+        "(loop [(i 0)] (let [(j (+ i 1))] (if (= j 5) j (recur j))))",
+        "5",
+    );
+    eval_assert("(loop [(i 0)] (do 1 (if (< i 5) (recur (+ i 1)) i)))", "5");
+}
+
+#[test]
+fn test_nested_loops() {
+    eval_assert(
+        "(loop [(i 0)] (if (< i 3) (recur (+ i 1)) (loop [(j i)] (if (< j 6) (recur (+ j 1)) j))))",
+        "6",
+    );
+    eval_assert(
+        "(loop [(i 0) (acc 0)]
+           (if (= i 3)
+             acc
+             (recur (+ i 1) (+ acc (loop [(j 0)] (if (= j i) j (recur (+ j 1))))))))",
+        "3",
+    );
+}
+
+#[test]
+fn test_recur_errors() {
+    eval_assert(
+        "(loop [(i 0)] (+ 1 (recur i)))",
+        "1:20: Error in recur: (recur i) must be in tail position of a loop",
+    );
+    eval_assert(
+        "(loop [(i 0)] (if (recur 1) 1 2))",
+        "1:19: Error in recur: (recur 1) must be in tail position of a loop",
+    );
+    eval_assert(
+        "(loop [(i 0)] (lambda [] (recur 1)))",
+        "1:26: Error in recur: (recur 1) must be in tail position of a loop",
+    );
+    eval_assert(
+        "(loop [(i 0)] (recur))",
+        "1:15: Error in recur: incorrect argument count",
+    );
+    eval_assert(
+        "(loop [(i 0)] (recur 1 2))",
+        "1:15: Error in recur: incorrect argument count",
+    );
+    eval_assert(
+        "(recur 1)",
+        "1:1: Error in recur: (recur 1) must be in tail position of a loop",
+    );
+    eval_assert(
+        "(defn f [] (recur 1))",
+        "1:12: Error in recur: (recur 1) must be in tail position of a loop",
+    );
+    eval_assert("(loop)", "1:1: Error in loop: incorrect argument count");
+    eval_assert("(loop [i 0] i)", "1:1: Error in loop: i must be (var init)");
+    eval_assert(
+        "(loop (i 0) i)",
+        "1:1: Error in loop: (i 0) must be vector of bindings",
+    );
+}
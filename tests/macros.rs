@@ -0,0 +1,178 @@
+mod helpers;
+use helpers::eval_assert;
+
+#[test]
+fn test_defmacro() {
+    eval_assert("(defmacro m [] 1) m", "#<macro m>");
+    eval_assert(
+        "(defmacro unless [c body] (list 'if c #none body)) (unless #f 5)",
+        "5",
+    );
+    eval_assert("(defmacro two [x] `(+ ,x ,x)) (two (* 2 3))", "12");
+    eval_assert(
+        "(defmacro two [x] `(+ ,x ,x)) (macro-expand (two 5))",
+        "(+ 5 5)",
+    );
+    eval_assert(
+        "(defmacro my-list args `(list ,@args)) (my-list 1 (+ 1 1))",
+        "(1 2)",
+    );
+    eval_assert(
+        "(defmacro swap-args [f a b] `(,f ,b ,a)) (swap-args - 1 10)",
+        "9",
+    );
+}
+
+#[test]
+fn test_defmacro_with_gensym() {
+    eval_assert(
+        "(defmacro either [a b]
+           (let [(t (gensym))]
+             `(let [(,t ,a)] (if ,t ,t ,b))))
+         (let [(t 5)] (either #f t))",
+        "5",
+    );
+    eval_assert(
+        "(defmacro either [a b]
+           (let [(t (gensym \"t\"))]
+             `(let [(,t ,a)] (if ,t ,t ,b))))
+         (either 1 (/ 1 0))",
+        "1",
+    );
+}
+
+#[test]
+fn test_gensyms_are_not_bound_to_their_prefix() {
+    eval_assert(
+        "(defmacro m [] (gensym \"list\"))
+         (try (m) (catch :undeclared e :unbound))",
+        ":unbound",
+    );
+    eval_assert(
+        "(macro-rules lit [else] [(lit else) :literal] [(lit x) :other])
+         (defmacro m [] (list 'lit (gensym \"else\")))
+         [(lit else) (m)]",
+        "[:literal :other]",
+    );
+}
+
+#[test]
+fn test_defmacro_errors() {
+    eval_assert(
+        "(defmacro 5 [] 1)",
+        "1:1: Error in defmacro: 5 must be symbol",
+    );
+    eval_assert(
+        "(defmacro bad [] (/ 1 0)) (bad)",
+        "1:18: Error: division by zero\n  in / at 1:18\n  in bad at 1:27",
+    );
+}
+
+#[test]
+fn test_macros_expanding_to_top_level_forms() {
+    eval_assert("(defmacro defx [v] `(def x ,v)) (defx 5) x", "5");
+    eval_assert(
+        "(macro-rules defconst [] [(defconst n v) (defn n [] v)]) (defconst five 5) (five)",
+        "5",
+    );
+    eval_assert("(when #t (def z 1)) z", "1");
+    eval_assert("(when #t (defn h [] 2)) (h)", "2");
+    eval_assert("(do (defn g [] 1)) (g)", "1");
+    eval_assert("(do (defmacro m [] 3) (m))", "3");
+    eval_assert("(do (def a 1) (def b (+ a 1)) b)", "2");
+    eval_assert(
+        "(when #t (def 5 1))",
+        "1:10: Error in def: 5 must be symbol",
+    );
+}
+
+#[test]
+fn test_macros_bound_after_use() {
+    eval_assert(
+        "(defn f [x] (twice x)) (defmacro twice [x] `(* 2 ,x)) (f 4)",
+        "8",
+    );
+    eval_assert("(defmacro m [] 1) (defn f [m] (m)) (f (lambda [] 2))", "2");
+}
+
+#[test]
+fn test_macros_redefined_after_use() {
+    eval_assert(
+        "(defn f [x] (if x (m 1) 0))
+         (f #f)
+         (defmacro m [a] `(+ ,a 1))
+         (def one (f #t))
+         (defmacro m [a] `(+ ,a 10))
+         (list one (f #t) (f #t))",
+        "(2 11 11)",
+    );
+}
+
+#[test]
+fn test_expanded_macros_redefined() {
+    eval_assert(
+        "(defmacro m [a] `(+ ,a 1))
+         (defn f [] (m 1))
+         (defn g [] (+ 1 (m 1)))
+         (def before (list (f) (g)))
+         (defmacro m [a] `(+ ,a 10))
+         (list before (f) (g) (f))",
+        "((2 3) 11 12 11)",
+    );
+    eval_assert(
+        "(defmacro m [a] `(+ ,a 1))
+         (defn f [] (m 1))
+         (f)
+         (defn m [a] (* a 100))
+         (f)",
+        "100",
+    );
+}
+
+#[test]
+fn test_macroexpand_all() {
+    eval_assert(
+        "(macroexpand-all '(when a (unless b c)))",
+        "(if a (do (if b #none (do c))))",
+    );
+    eval_assert(
+        "(macroexpand-all '(defn f [x] (def y (when x 1)) [(when y 2)]))",
+        "(defn f [x] (def y (if x (do 1))) [(if y (do 2))])",
+    );
+    eval_assert(
+        "(macroexpand-all '(loop [(i (when a 1))] (when i (recur 1))))",
+        "(loop [(i (if a (do 1)))] (if i (do (recur 1))))",
+    );
+    eval_assert(
+        "(macroexpand-all '(try (when a 1) (catch e (when e 2))))",
+        "(try (if a (do 1)) (catch e (if e (do 2))))",
+    );
+}
+
+#[test]
+fn test_macroexpand_all_leaves_quoted_and_shadowed() {
+    eval_assert(
+        "(macroexpand-all '(list '(when a b) `(when ,c d)))",
+        "(list (quote (when a b)) (quasiquote (when (unquote c) d)))",
+    );
+    eval_assert(
+        "(macroexpand-all '(lambda [when] (when 1 2)))",
+        "(lambda [when] (when 1 2))",
+    );
+    eval_assert("(macroexpand-all 5)", "5");
+    eval_assert(
+        "(macroexpand-all)",
+        "1:1: Error in macroexpand-all: expected 1 argument, got 0\n  in macroexpand-all at 1:1",
+    );
+}
+
+#[test]
+fn test_macroexpand_all_is_a_procedure() {
+    eval_assert("(def f macroexpand-all) (f '(when a b))", "(if a (do b))");
+    eval_assert(
+        "(defn each [g ls] (if (null? ls) '() (cons (g (first ls)) (each g (rest ls)))))
+         (each macroexpand-all '((when a b) (unless c d)))",
+        "((if a (do b)) (if c #none (do d)))",
+    );
+    eval_assert("(let [(macroexpand-all list)] (macroexpand-all 1))", "(1)");
+}
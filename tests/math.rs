@@ -58,4 +58,18 @@ fn test_arithmetic() {
     eval_assert("(+ 1 2 3 4 5)", "15");
     eval_assert("(+ 1 2 3.1 4 5)", "15.1");
     eval_assert("(+ 1/2 1/4)", "3/4");
+    // sub/difference
+    eval_assert("(- 5)", "-5");
+    eval_assert("(- 10 1 2)", "7");
+    eval_assert("(- 1/2 1/4)", "1/4");
+    // mult/product
+    eval_assert("(*)", "1");
+    eval_assert("(* 2 3 4)", "24");
+    eval_assert("(* 2 1/4)", "1/2");
+    // div/quotient
+    eval_assert("(/ 4)", "1/4");
+    eval_assert("(/ 12 2 3)", "2");
+    eval_assert("(/ 1 3)", "1/3");
+    eval_assert("(/ 1.0 4)", "0.25");
+    eval_assert("(/ 1 0)", "1:1: Error: division by zero\n  in / at 1:1");
 }
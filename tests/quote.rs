@@ -0,0 +1,87 @@
+mod helpers;
+use helpers::eval_assert;
+
+#[test]
+fn test_quote() {
+    eval_assert("'a", "a");
+    eval_assert("(quote a)", "a");
+    eval_assert("'(1 2 (+ 1 2))", "(1 2 (+ 1 2))");
+    eval_assert("'()", "#()");
+    eval_assert("'[a (b c)]", "[a (b c)]");
+    eval_assert("'#[a b]", "#[a b]");
+    eval_assert("'{:a (+ 1 2)}", "{:a (+ 1 2)}");
+    eval_assert("''a", "(quote a)");
+    eval_assert("(quote)", "1:1: Error in quote: incorrect argument count");
+    eval_assert(
+        "(quote a b)",
+        "1:1: Error in quote: incorrect argument count",
+    );
+}
+
+#[test]
+fn test_quasiquote() {
+    eval_assert("`a", "a");
+    eval_assert("`(1 2 (+ 1 2))", "(1 2 (+ 1 2))");
+    eval_assert("`(1 2 ,(+ 1 2))", "(1 2 3)");
+    eval_assert("`(1 ,@(list 2 3) 4)", "(1 2 3 4)");
+    eval_assert("`(1 ,@[2 3] ,@'() 4)", "(1 2 3 4)");
+    eval_assert("`[a ,(+ 1 2) ,@(list 4 5)]", "[a 3 4 5]");
+    eval_assert("`#[a ,(+ 1 2)]", "#[a 3]");
+    eval_assert("`{:a ,(+ 1 2)}", "{:a 3}");
+    eval_assert("`#{,(+ 1 2) b}", "#{3 b}");
+    eval_assert(
+        "`(1 `(2 ,(3 ,(+ 1 3))))",
+        "(1 (quasiquote (2 (unquote (3 4)))))",
+    );
+}
+
+#[test]
+fn test_unquote_errors() {
+    eval_assert(
+        ",a",
+        "1:1: Error in unquote: (unquote a) must be used inside quasiquote",
+    );
+    eval_assert(
+        "`,@(list 1 2)",
+        "1:1: Error in unquote-splicing: (unquote-splicing (list 1 2)) must be used inside a list or vector",
+    );
+    eval_assert(
+        "`(1 ,@2)",
+        "1:1: Error in unquote-splicing: 2 must be list or vector",
+    );
+    eval_assert(
+        ",@a",
+        "1:1: Error in unquote-splicing: (unquote-splicing a) must be used inside quasiquote",
+    );
+    eval_assert(
+        "(unquote)",
+        "1:1: Error in unquote: incorrect argument count",
+    );
+    eval_assert(
+        "(unquote-splicing)",
+        "1:1: Error in unquote-splicing: incorrect argument count",
+    );
+}
+
+#[test]
+fn test_map_literals_are_new_maps() {
+    eval_assert("(def f (lambda [] {:a 1})) (assoc! (f) :a 2) (f)", "{:a 1}");
+    eval_assert("{'(1 2) :a}", "{(1 2) :a}");
+    eval_assert("{[1 2] :a}", "1:1: Error: invalid map key: [1 2]");
+}
+
+#[test]
+fn test_unicode_literals() {
+    eval_assert("\\λ", "\\λ");
+    eval_assert("\\u{3bb}", "\\λ");
+    eval_assert("\\u{2000}", "\\u{2000}");
+    eval_assert("\\newline", "\\newline");
+    eval_assert("\"λx → 😀\"", "\"λx → 😀\"");
+    eval_assert("\"\\u{3bb}\\tx\"", "\"λ\\tx\"");
+    eval_assert("'(λ naïve :ключ)", "(λ naïve :ключ)");
+    eval_assert("(def λ 5) λ", "5");
+    eval_assert("(case \\λ ((\\a) 1) ((\\λ) 2))", "2");
+    eval_assert("(list \\λ\\é)", "1:7: Error: invalid identifier: \\λ\\é");
+    eval_assert("'(\\λλ)", "1:3: Error: invalid identifier: \\λλ");
+    eval_assert("[\\中]", "[\\中]");
+}
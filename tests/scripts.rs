@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn run_script(name: &str, code: &str, args: &[&str]) -> Output {
+    let path: PathBuf = std::env::temp_dir().join(format!("rusp-test-{name}.rusp"));
+    std::fs::write(&path, code).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rusp"))
+        .arg("run")
+        .arg(&path)
+        .args(args)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    output
+}
+
+#[test]
+fn test_running_a_script() {
+    let out = run_script(
+        "simple",
+        "(def a 5)\n(println \"a is\" (+ a 1))\n(print :done)",
+        &[],
+    );
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "a is 6\n:done");
+}
+
+#[test]
+fn test_script_arguments() {
+    let out = run_script(
+        "args",
+        "(println (length *command-line-args*) (first *command-line-args*))",
+        &["hello", "world"],
+    );
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "2 hello\n");
+}
+
+#[test]
+fn test_script_with_shebang() {
+    let out = run_script("shebang", "#!/usr/bin/env rusp\n(println 1)\n", &[]);
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "1\n");
+}
+
+#[test]
+fn test_script_errors_exit_with_failure() {
+    let out = run_script("error", "(println 1)\n(undeclared-thing)\n(println 2)", &[]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "1\n");
+    assert!(String::from_utf8_lossy(&out.stderr).contains("undeclared-thing"));
+
+    let out = run_script("read-error", "(println 1", &[]);
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "");
+}
+
+#[test]
+fn test_forms_before_a_read_error_are_evaluated() {
+    let out = run_script(
+        "late-read-error",
+        "(println 1)\n(print :prompt)\n(println \"unclosed",
+        &[],
+    );
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "1\n:prompt");
+}
+
+#[test]
+fn test_script_errors_report_their_location() {
+    let out = run_script(
+        "location",
+        "(def f (lambda [x]\n  (+ x\n     (undeclared-thing))))\n(f 1)",
+        &[],
+    );
+    assert_eq!(out.status.code(), Some(1));
+    let path = std::env::temp_dir().join("rusp-test-location.rusp");
+    let expect = format!("rusp: {}:3:7: ", path.display());
+    assert!(String::from_utf8_lossy(&out.stderr).starts_with(&expect));
+}
+
+#[test]
+fn test_missing_script() {
+    let out = Command::new(env!("CARGO_BIN_EXE_rusp"))
+        .args(["run", "/this/file/does/not/exist.rusp"])
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(1));
+}